@@ -0,0 +1,144 @@
+use anyhow::Result;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::config;
+use crate::downloader::Downloader;
+use crate::error::FerroError;
+use crate::iso_api::IsoApi;
+use crate::types::Selection;
+use crate::utils;
+
+/// Result of one successful download in a batch
+#[derive(Debug, Clone)]
+pub struct DownloadOutcome {
+    /// Signed URL the ISO was downloaded from
+    pub url: String,
+    /// Files that were written
+    pub paths: Vec<PathBuf>,
+    /// Bytes downloaded, before any compression
+    pub size: u64,
+    /// Time spent resolving and downloading this item
+    pub elapsed: Duration,
+}
+
+/// The download URL of `selection`: `resolved`, a link fetched for it earlier, unless that
+/// has expired or is about to, else a fresh one from the API. The `--batch` queue resolves
+/// its jobs this way too.
+pub async fn resolve_download_url(
+    api: &mut IsoApi,
+    selection: &Selection,
+    resolved: Option<String>,
+) -> Result<String, FerroError> {
+    let now = chrono::Utc::now();
+    match resolved.filter(|url| !utils::link_expires_soon(url, now)) {
+        Some(url) => Ok(url),
+        None => {
+            api.get_download_url(
+                &selection.version,
+                &selection.release,
+                &selection.edition,
+                &selection.language,
+                &selection.architecture,
+            )
+            .await
+        }
+    }
+}
+
+/// Download every selection into `output_dir` under its default file name, yielding each
+/// outcome as soon as it completes.
+///
+/// At most `concurrency` items (at least one) are in flight at once, and outcomes arrive in
+/// completion order, not input order. URL resolution goes through the shared `IsoApi` one
+/// item at a time, since Microsoft's session handling doesn't tolerate parallel requests;
+/// only the transfers themselves overlap. A failed item doesn't stop the others.
+///
+/// Dropping the stream cancels every in-flight item. Cancelled single-stream downloads
+/// leave their incomplete `.part` files behind; segmented downloads also keep their
+/// `.part.json` state and resume on the next attempt.
+pub fn download_all(
+    api: Arc<Mutex<IsoApi>>,
+    downloader: Arc<Downloader>,
+    selections: Vec<Selection>,
+    output_dir: PathBuf,
+    concurrency: usize,
+) -> impl Stream<Item = (Selection, Result<DownloadOutcome>)> {
+    stream::iter(selections)
+        .map(move |selection| {
+            let api = api.clone();
+            let downloader = downloader.clone();
+            let output_dir = output_dir.clone();
+            async move {
+                let outcome = download_one(&api, &downloader, &selection, &output_dir).await;
+                (selection, outcome)
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+}
+
+async fn download_one(
+    api: &Mutex<IsoApi>,
+    downloader: &Downloader,
+    selection: &Selection,
+    output_dir: &Path,
+) -> Result<DownloadOutcome> {
+    let started = Instant::now();
+
+    let url = resolve_download_url(&mut *api.lock().await, selection, None).await?;
+
+    let output_path = output_dir.join(utils::sanitize_filename(
+        &selection.default_filename(&url),
+        config::DEFAULT_MAX_FILENAME_LENGTH.into(),
+    ));
+    let downloaded = downloader.download_to_many(&url, &[output_path]).await?;
+
+    Ok(DownloadOutcome {
+        url,
+        paths: downloaded.paths,
+        size: downloaded.size,
+        elapsed: started.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection() -> Selection {
+        Selection {
+            version: "Windows 11".to_string(),
+            release: "24H2".to_string(),
+            edition: "Windows 11 Home/Pro/Edu".to_string(),
+            language: "English".to_string(),
+            architecture: "x64".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_download_url_reuses_fresh_links() {
+        // Nothing listens on the discard port, so any API call would fail
+        let mut api = IsoApi::with_base_url("http://127.0.0.1:9").await.unwrap();
+
+        let fresh = format!(
+            "https://example.com/Win11.iso?P1={}",
+            chrono::Utc::now().timestamp() + 86400
+        );
+        assert_eq!(
+            resolve_download_url(&mut api, &selection(), Some(fresh.clone()))
+                .await
+                .unwrap(),
+            fresh
+        );
+        let unsigned = "https://example.com/Win11.iso".to_string();
+        assert_eq!(
+            resolve_download_url(&mut api, &selection(), Some(unsigned.clone()))
+                .await
+                .unwrap(),
+            unsigned
+        );
+    }
+}
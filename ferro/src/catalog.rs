@@ -0,0 +1,571 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDate};
+use log::{info, warn};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::types::{
+    get_windows_versions, validate_catalog, WindowsEditionData, WindowsReleaseData,
+    WindowsVersionData,
+};
+
+/// A version in a catalog overlay file
+#[derive(Debug, Clone, Deserialize)]
+pub struct VersionOverlay {
+    pub name: String,
+    /// Required when the overlay adds a new version, optional when it extends one
+    pub page_type: Option<String>,
+    #[serde(default)]
+    pub releases: Vec<ReleaseOverlay>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseOverlay {
+    pub name: String,
+    /// Edition picked when none is given, replacing the built-in default
+    pub default_edition: Option<String>,
+    #[serde(default)]
+    pub editions: Vec<EditionOverlay>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EditionOverlay {
+    pub name: String,
+    pub ids: Vec<u32>,
+}
+
+/// A SHA-256 from the hash table of a Microsoft download page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedHash {
+    /// ID of the product edition whose ISOs the table lists
+    pub edition_id: u32,
+    /// Language as the page names it, e.g. "English International"
+    pub language: String,
+    /// "x64", "x86" or "ARM64"
+    pub architecture: String,
+    /// Lowercase hex
+    pub sha256: String,
+}
+
+/// Age in months after which the newest catalogued Windows build counts as outdated, when
+/// `--fail-if-outdated` is given without `--max-catalog-age`
+pub const DEFAULT_MAX_AGE_MONTHS: u32 = 12;
+
+/// The `Build N - YYYY.MM` date in a release name
+static BUILD_DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Build [\d.]+ - (\d{4})\.(\d{2})").unwrap());
+
+static TABLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<table.*?</table>").unwrap());
+
+/// A heading or table caption, which names the edition a hash table belongs to
+static HEADING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)<(h[1-6]|caption)[^>]*>(.*?)</(?:h[1-6]|caption)>").unwrap()
+});
+
+/// A hash table row: the language and architecture, then the SHA-256. The first cell may
+/// hold markup, but no `</td>` or `</tr>`, so a match can't run into the next row.
+static HASH_ROW: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?is)<tr[^>]*>\s*<td[^>]*>((?:[^<]|<[^/]|</[^t])*?)</td>\s*<td[^>]*>\s*([0-9a-f]{64})\s*</td>",
+    )
+    .unwrap()
+});
+
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]+>").unwrap());
+
+/// Overlay loaded when `--catalog-overlay` isn't given: `<config dir>/ferro/catalog.json`
+pub fn default_overlay_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("ferro").join("catalog.json"))
+}
+
+/// The built-in catalog with the overlay applied. An explicitly given overlay must exist;
+/// the default one is only used if it's there.
+pub async fn load(explicit_overlay: Option<&Path>) -> Result<Vec<WindowsVersionData>> {
+    let mut catalog = get_windows_versions();
+
+    let path = match explicit_overlay {
+        Some(path) => path.to_path_buf(),
+        None => match default_overlay_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(catalog),
+        },
+    };
+
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Failed to read catalog overlay: {}", path.display()))?;
+    let overlay: Vec<VersionOverlay> = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse catalog overlay: {}", path.display()))?;
+
+    apply_overlay(&mut catalog, overlay)
+        .with_context(|| format!("Invalid catalog overlay: {}", path.display()))?;
+    info!("Applied catalog overlay {}", path.display());
+
+    for problem in validate_catalog(&catalog) {
+        warn!("Catalog overlay: {}", problem);
+    }
+
+    Ok(catalog)
+}
+
+/// Merge an overlay into the catalog.
+///
+/// Names are matched case-insensitively at each level. A version, release or edition
+/// with a matching name is merged into the existing entry: a version's `page_type` and
+/// an edition's `ids` replace the built-in values, while releases and editions are
+/// merged recursively. Entries without a match are appended after the built-in ones,
+/// so the defaults Ferro picks (the first release and edition) don't change.
+pub fn apply_overlay(
+    catalog: &mut Vec<WindowsVersionData>,
+    overlay: Vec<VersionOverlay>,
+) -> Result<()> {
+    for version in overlay {
+        let existing = catalog
+            .iter_mut()
+            .find(|v| v.name.eq_ignore_ascii_case(&version.name));
+
+        let target = match existing {
+            Some(target) => {
+                if let Some(page_type) = version.page_type {
+                    target.page_type = page_type;
+                }
+                target
+            }
+            None => {
+                let page_type = version
+                    .page_type
+                    .ok_or_else(|| anyhow!("New version '{}' needs a page_type", version.name))?;
+                catalog.push(WindowsVersionData {
+                    name: version.name,
+                    page_type,
+                    releases: Vec::new(),
+                });
+                catalog.last_mut().expect("version was just added")
+            }
+        };
+
+        for release in version.releases {
+            merge_release(&mut target.releases, release);
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_release(releases: &mut Vec<WindowsReleaseData>, release: ReleaseOverlay) {
+    let index = match releases
+        .iter()
+        .position(|r| r.name.eq_ignore_ascii_case(&release.name))
+    {
+        Some(index) => index,
+        None => {
+            releases.push(WindowsReleaseData {
+                name: release.name,
+                default_edition: None,
+                editions: Vec::new(),
+            });
+            releases.len() - 1
+        }
+    };
+
+    if release.default_edition.is_some() {
+        releases[index].default_edition = release.default_edition;
+    }
+
+    let editions = &mut releases[index].editions;
+    for edition in release.editions {
+        match editions
+            .iter_mut()
+            .find(|e| e.name.eq_ignore_ascii_case(&edition.name))
+        {
+            Some(existing) => existing.ids = edition.ids,
+            None => editions.push(WindowsEditionData {
+                name: edition.name,
+                ids: edition.ids,
+            }),
+        }
+    }
+}
+
+/// Product editions a Microsoft download page offers, as `(id, name)` pairs taken from its
+/// `product-edition` drop-down. Empty when the page has none, e.g. when Microsoft serves the
+/// Media Creation Tool page instead.
+pub fn parse_product_editions(html: &str) -> Vec<(u32, String)> {
+    let Some(start) = html.find(r#"id="product-edition""#) else {
+        return Vec::new();
+    };
+    let select = &html[start..];
+    let select = &select[..select.find("</select>").unwrap_or(select.len())];
+
+    let option = regex::Regex::new(r#"<option[^>]*value="(\d+)"[^>]*>([^<]+)</option>"#).unwrap();
+    option
+        .captures_iter(select)
+        .filter_map(|captures| {
+            let id = captures[1].parse().ok()?;
+            let name = captures[2]
+                .replace("&amp;", "&")
+                .replace("&quot;", "\"")
+                .replace("&#39;", "'")
+                .trim()
+                .to_string();
+            Some((id, name))
+        })
+        .collect()
+}
+
+/// SHA-256 hashes a Microsoft download page publishes for its ISOs, from the table below the
+/// downloads with a row per language and architecture, such as "English 64-bit". A table
+/// belongs to the offered edition its caption or the heading above it names, else to the
+/// page's first edition, the multi-edition ISO. Empty when the page has no hash table or no
+/// edition drop-down to key it by.
+pub fn parse_published_hashes(html: &str) -> Vec<PublishedHash> {
+    let editions = parse_product_editions(html);
+    let Some(&(first_edition, _)) = editions.first() else {
+        return Vec::new();
+    };
+
+    let mut hashes = Vec::new();
+    let mut previous_end = 0;
+    for table in TABLE.find_iter(html) {
+        let caption = HEADING
+            .captures(table.as_str())
+            .filter(|captures| captures[1].eq_ignore_ascii_case("caption"));
+        let heading = caption.or_else(|| {
+            HEADING
+                .captures_iter(&html[previous_end..table.start()])
+                .last()
+        });
+        previous_end = table.end();
+        let title = heading
+            .map(|captures| html_text(&captures[2]).to_lowercase())
+            .unwrap_or_default();
+        let edition_id = editions
+            .iter()
+            .find(|(_, name)| title.contains(&name.to_lowercase()))
+            .map_or(first_edition, |(id, _)| *id);
+
+        for row in HASH_ROW.captures_iter(table.as_str()) {
+            let label = html_text(&row[1]);
+            if let Some((language, architecture)) = split_architecture(&label) {
+                hashes.push(PublishedHash {
+                    edition_id,
+                    language: language.to_string(),
+                    architecture: architecture.to_string(),
+                    sha256: row[2].to_lowercase(),
+                });
+            }
+        }
+    }
+    hashes
+}
+
+/// Split a hash table label like "English International 64-bit" into the language and the
+/// architecture. `None` for a label naming no architecture.
+fn split_architecture(label: &str) -> Option<(&str, &'static str)> {
+    let (language, suffix) = label.rsplit_once(' ')?;
+    let architecture = match suffix.to_lowercase().as_str() {
+        "64-bit" | "x64" => "x64",
+        "32-bit" | "x86" => "x86",
+        "arm64" => "ARM64",
+        _ => return None,
+    };
+    Some((language.trim(), architecture))
+}
+
+/// The text of an HTML fragment, without tags and with whitespace collapsed
+fn html_text(fragment: &str) -> String {
+    let text = TAG.replace_all(fragment, " ");
+    text.replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Overlay adding the offered editions whose IDs `version` doesn't know yet to its newest
+/// release, or `None` when there are none. An edition that shares a name with one of that
+/// release's editions gets the new IDs added to it.
+pub fn new_editions_overlay(
+    version: &WindowsVersionData,
+    offered: &[(u32, String)],
+) -> Option<VersionOverlay> {
+    let newest = version.releases.first()?;
+    let known: Vec<u32> = version
+        .releases
+        .iter()
+        .flat_map(|release| &release.editions)
+        .flat_map(|edition| edition.ids.iter().copied())
+        .collect();
+
+    let mut editions: Vec<EditionOverlay> = Vec::new();
+    for (id, name) in offered.iter().filter(|(id, _)| !known.contains(id)) {
+        match editions
+            .iter_mut()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+        {
+            Some(edition) => edition.ids.push(*id),
+            None => {
+                let existing = newest
+                    .editions
+                    .iter()
+                    .find(|e| e.name.eq_ignore_ascii_case(name));
+                let mut ids = existing.map(|e| e.ids.clone()).unwrap_or_default();
+                ids.push(*id);
+                editions.push(EditionOverlay {
+                    name: name.clone(),
+                    ids,
+                });
+            }
+        }
+    }
+    if editions.is_empty() {
+        return None;
+    }
+
+    Some(VersionOverlay {
+        name: version.name.clone(),
+        page_type: None,
+        releases: vec![ReleaseOverlay {
+            name: newest.name.clone(),
+            default_edition: None,
+            editions,
+        }],
+    })
+}
+
+/// Name of the edition `--edition-id` adds for a product edition ID
+pub fn edition_id_name(id: u32) -> String {
+    format!("Product edition {}", id)
+}
+
+/// Overlay adding an edition that requests exactly the product edition ID `id` to a release
+pub fn edition_id_overlay(version_name: &str, release_name: &str, id: u32) -> VersionOverlay {
+    VersionOverlay {
+        name: version_name.to_string(),
+        page_type: None,
+        releases: vec![ReleaseOverlay {
+            name: release_name.to_string(),
+            default_edition: None,
+            editions: vec![EditionOverlay {
+                name: edition_id_name(id),
+                ids: vec![id],
+            }],
+        }],
+    }
+}
+
+/// The most recent Windows release in the catalog, going by the `Build N - YYYY.MM`
+/// date in release names
+pub fn newest_build(catalog: &[WindowsVersionData]) -> Option<(&str, NaiveDate)> {
+    catalog
+        .iter()
+        .flat_map(|version| &version.releases)
+        .filter_map(|release| {
+            let captures = BUILD_DATE.captures(&release.name)?;
+            let year = captures[1].parse().ok()?;
+            let month = captures[2].parse().ok()?;
+            Some((
+                release.name.as_str(),
+                NaiveDate::from_ymd_opt(year, month, 1)?,
+            ))
+        })
+        .max_by_key(|(_, date)| *date)
+}
+
+/// Describe how outdated the catalog is, if its newest Windows build is more than
+/// `max_age_months` older than `today`
+pub fn outdated(
+    catalog: &[WindowsVersionData],
+    max_age_months: u32,
+    today: NaiveDate,
+) -> Option<String> {
+    let (release, built) = newest_build(catalog)?;
+    let age = (today.year() - built.year()) * 12 + today.month() as i32 - built.month() as i32;
+    if age <= max_age_months as i32 {
+        return None;
+    }
+    Some(format!(
+        "The catalog's newest Windows build, {}, is {} months old; newer builds may be available. \
+         Update Ferro or add them with a catalog overlay",
+        release, age
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlay(json: &str) -> Vec<VersionOverlay> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_parse_product_editions() {
+        let html = r#"<select id="product-edition" class="form-control">
+            <option value="" selected="selected">Select Download</option>
+            <optgroup label="Windows 11">
+            <option value="3113">Windows 11 (multi-edition ISO for x64 devices)</option>
+            <option value="3115">Windows 11 Home China </option>
+            </optgroup></select><option value="1">Not an edition</option>"#;
+        assert_eq!(
+            parse_product_editions(html),
+            vec![
+                (
+                    3113,
+                    "Windows 11 (multi-edition ISO for x64 devices)".to_string()
+                ),
+                (3115, "Windows 11 Home China".to_string()),
+            ]
+        );
+        assert!(parse_product_editions("<html>Media Creation Tool</html>").is_empty());
+    }
+
+    #[test]
+    fn test_parse_published_hashes() {
+        let html = include_str!("../tests/fixtures/windows11_download_page.html");
+        let hashes = parse_published_hashes(html);
+        assert_eq!(hashes.len(), 5);
+        assert!(hashes[..4].iter().all(|hash| hash.edition_id == 3113));
+        assert_eq!(
+            hashes[1],
+            PublishedHash {
+                edition_id: 3113,
+                language: "English International".to_string(),
+                architecture: "x64".to_string(),
+                sha256: "1f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c5b6a7988"
+                    .to_string(),
+            }
+        );
+        assert_eq!(hashes[2].language, "Chinese Simplified");
+        assert_eq!(hashes[3].architecture, "ARM64");
+        // The second table's caption names the China edition
+        assert_eq!(hashes[4].edition_id, 3115);
+        assert_eq!(hashes[4].language, "Chinese Simplified");
+
+        // Without an edition drop-down the hashes can't be attributed
+        let table = &html[html.find("<table").unwrap()..];
+        assert!(parse_published_hashes(table).is_empty());
+    }
+
+    #[test]
+    fn test_new_editions_overlay() {
+        let mut catalog = get_windows_versions();
+        let known = catalog[0].releases[0].editions[0].clone();
+        assert!(new_editions_overlay(&catalog[0], &[(known.ids[0], known.name.clone())]).is_none());
+
+        let offered = [
+            (known.ids[0], known.name.clone()),
+            (9001, known.name.clone()),
+            (9002, "Windows 11 IoT".to_string()),
+        ];
+        let overlay = new_editions_overlay(&catalog[0], &offered).unwrap();
+        apply_overlay(&mut catalog, vec![overlay]).unwrap();
+
+        let editions = &catalog[0].releases[0].editions;
+        let mut ids = known.ids.clone();
+        ids.push(9001);
+        assert_eq!(editions[0].ids, ids);
+        assert_eq!(editions.last().unwrap().name, "Windows 11 IoT");
+        assert_eq!(editions.last().unwrap().ids, vec![9002]);
+    }
+
+    #[test]
+    fn test_overlay_overrides_and_appends() {
+        let mut catalog = get_windows_versions();
+        let builtin_releases = catalog[0].releases.len();
+
+        apply_overlay(
+            &mut catalog,
+            overlay(
+                r#"[{"name": "windows 11", "releases": [
+                    {"name": "24H2 (Build 26100.1742 - 2024.10)", "editions": [
+                        {"name": "Windows 11 Home/Pro/Edu", "ids": [9999]}
+                    ]},
+                    {"name": "Insider Preview", "editions": [
+                        {"name": "Windows 11 Insider", "ids": [4000]}
+                    ]}
+                ]}]"#,
+            ),
+        )
+        .unwrap();
+
+        let windows11 = &catalog[0];
+        assert_eq!(windows11.page_type, "windows11");
+        assert_eq!(windows11.releases[0].editions[0].ids, vec![9999]);
+        assert_eq!(windows11.releases[0].editions.len(), 3);
+        assert_eq!(windows11.releases.len(), builtin_releases + 1);
+        assert_eq!(windows11.releases.last().unwrap().name, "Insider Preview");
+    }
+
+    #[test]
+    fn test_overlay_new_version_needs_page_type() {
+        let mut catalog = get_windows_versions();
+        assert!(apply_overlay(&mut catalog, overlay(r#"[{"name": "Windows 12"}]"#)).is_err());
+
+        apply_overlay(
+            &mut catalog,
+            overlay(r#"[{"name": "Windows 12", "page_type": "windows12"}]"#),
+        )
+        .unwrap();
+        assert_eq!(catalog.last().unwrap().name, "Windows 12");
+    }
+
+    #[test]
+    fn test_overlay_default_edition() {
+        let mut catalog = get_windows_versions();
+        apply_overlay(
+            &mut catalog,
+            overlay(
+                r#"[{"name": "Windows 11", "releases": [
+                    {"name": "24H2 (Build 26100.1742 - 2024.10)",
+                     "default_edition": "Windows 11 Home China"}
+                ]}]"#,
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            catalog[0].releases[0].default_edition.as_deref(),
+            Some("Windows 11 Home China")
+        );
+        assert!(validate_catalog(&catalog).is_empty());
+
+        catalog[0].releases[0].default_edition = Some("Windows 11 Nonexistent".to_string());
+        assert_eq!(validate_catalog(&catalog).len(), 1);
+    }
+
+    #[test]
+    fn test_edition_id_overlay() {
+        let mut catalog = get_windows_versions();
+        let release = catalog[0].releases[0].name.clone();
+        apply_overlay(
+            &mut catalog,
+            vec![edition_id_overlay("Windows 11", &release, 9999)],
+        )
+        .unwrap();
+
+        let editions = &catalog[0].releases[0].editions;
+        let added = editions.last().unwrap();
+        assert_eq!(added.name, "Product edition 9999");
+        assert_eq!(added.ids, vec![9999]);
+        // The built-in default is still picked when no edition is given
+        assert_eq!(editions[0].name, "Windows 11 Home/Pro/Edu");
+    }
+
+    #[test]
+    fn test_outdated_catalog() {
+        let catalog = get_windows_versions();
+        let (_, built) = newest_build(&catalog).unwrap();
+
+        let soon = built + chrono::Months::new(6);
+        assert_eq!(outdated(&catalog, 12, soon), None);
+
+        let later = built + chrono::Months::new(13);
+        assert!(outdated(&catalog, 12, later)
+            .unwrap()
+            .contains("13 months old"));
+    }
+}
@@ -0,0 +1,233 @@
+use anyhow::{Context, Result};
+use sha2::digest::DynDigest;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// Size of the buffer used when hashing files from disk, unless `--hash-buffer` sets another
+pub const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Hash algorithms Ferro can compute for a download
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum ChecksumAlgo {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl ChecksumAlgo {
+    /// Extension of the sidecar file, matching the `<algo>sum` tool that can check it
+    pub fn extension(self) -> &'static str {
+        match self {
+            ChecksumAlgo::Sha256 => "sha256",
+            ChecksumAlgo::Sha1 => "sha1",
+            ChecksumAlgo::Md5 => "md5",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            ChecksumAlgo::Sha256 => Box::new(sha2::Sha256::default()),
+            ChecksumAlgo::Sha1 => Box::new(sha1::Sha1::default()),
+            ChecksumAlgo::Md5 => Box::new(md5::Md5::default()),
+        }
+    }
+}
+
+impl std::fmt::Display for ChecksumAlgo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumAlgo::Sha256 => write!(f, "SHA-256"),
+            ChecksumAlgo::Sha1 => write!(f, "SHA-1"),
+            ChecksumAlgo::Md5 => write!(f, "MD5"),
+        }
+    }
+}
+
+/// Feeds the same data to several hashers, so every digest comes from a single pass
+pub struct MultiHasher {
+    hashers: Vec<(ChecksumAlgo, Box<dyn DynDigest + Send>)>,
+}
+
+impl MultiHasher {
+    pub fn new(algos: &[ChecksumAlgo]) -> Self {
+        let mut hashers: Vec<(ChecksumAlgo, Box<dyn DynDigest + Send>)> = Vec::new();
+        for &algo in algos {
+            if !hashers.iter().any(|(existing, _)| *existing == algo) {
+                hashers.push((algo, algo.hasher()));
+            }
+        }
+        Self { hashers }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(data);
+        }
+    }
+
+    /// Feed the contents of a file, e.g. the part of a download that's already on disk
+    pub async fn update_from_file(&mut self, path: &Path) -> Result<()> {
+        self.update_from_file_buffered(path, HASH_BUFFER_SIZE).await
+    }
+
+    /// Like [`MultiHasher::update_from_file`], reading `buffer_size` bytes at a time
+    pub async fn update_from_file_buffered(
+        &mut self,
+        path: &Path,
+        buffer_size: usize,
+    ) -> Result<()> {
+        let mut file = File::open(path)
+            .await
+            .with_context(|| format!("Failed to open file for hashing: {}", path.display()))?;
+        let mut buffer = vec![0u8; buffer_size.max(1)];
+
+        loop {
+            let read = file
+                .read(&mut buffer)
+                .await
+                .with_context(|| format!("Failed to read file for hashing: {}", path.display()))?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.update(&buffer[..read]);
+        }
+    }
+
+    /// Lowercase hex digest for each algorithm, in the order they were requested
+    pub fn finalize(self) -> Vec<(ChecksumAlgo, String)> {
+        self.hashers
+            .into_iter()
+            .map(|(algo, hasher)| (algo, to_hex(&hasher.finalize())))
+            .collect()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Compute the lowercase hex SHA-256 digest of a file, streaming it from disk
+pub async fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let digests = hash_file(path, &[ChecksumAlgo::Sha256]).await?;
+    Ok(digests
+        .into_iter()
+        .next()
+        .map(|(_, digest)| digest)
+        .unwrap_or_default())
+}
+
+/// Compute several digests of a file while reading it from disk only once
+pub async fn hash_file<P: AsRef<Path>>(
+    path: P,
+    algos: &[ChecksumAlgo],
+) -> Result<Vec<(ChecksumAlgo, String)>> {
+    hash_file_buffered(path, algos, HASH_BUFFER_SIZE).await
+}
+
+/// Like [`hash_file`], reading `buffer_size` bytes at a time
+pub async fn hash_file_buffered<P: AsRef<Path>>(
+    path: P,
+    algos: &[ChecksumAlgo],
+    buffer_size: usize,
+) -> Result<Vec<(ChecksumAlgo, String)>> {
+    let mut hasher = MultiHasher::new(algos);
+    hasher
+        .update_from_file_buffered(path.as_ref(), buffer_size)
+        .await?;
+    Ok(hasher.finalize())
+}
+
+/// Write `<output>.<algo>` in the `<hash>  <file name>` format `sha256sum -c` and friends read
+pub async fn write_sidecar(output: &Path, algo: ChecksumAlgo, digest: &str) -> Result<PathBuf> {
+    let mut sidecar = output.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(algo.extension());
+    let sidecar = PathBuf::from(sidecar);
+
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    tokio::fs::write(&sidecar, format!("{}  {}\n", digest, file_name))
+        .await
+        .with_context(|| format!("Failed to write checksum file: {}", sidecar.display()))?;
+    Ok(sidecar)
+}
+
+/// The SHA-256 of `file_name` in a `sha256sum`-style listing (`<hash>  <file name>` lines).
+/// A listing that is just a hash, as some `.sha256` files are, applies to any file.
+pub fn find_sha256(listing: &str, file_name: &str) -> Option<String> {
+    let is_sha256 = |s: &str| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit());
+
+    let lines: Vec<&str> = listing
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if let [line] = lines.as_slice() {
+        if is_sha256(line) {
+            return Some(line.to_lowercase());
+        }
+    }
+
+    lines.iter().find_map(|line| {
+        let (hash, name) = line.split_once(char::is_whitespace)?;
+        // `sha256sum -b` marks binary files with a leading '*'
+        let name = name.trim_start().trim_start_matches('*');
+        (is_sha256(hash) && name.eq_ignore_ascii_case(file_name)).then(|| hash.to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_hasher_single_pass() {
+        let mut hasher = MultiHasher::new(&[
+            ChecksumAlgo::Sha256,
+            ChecksumAlgo::Sha1,
+            ChecksumAlgo::Md5,
+            ChecksumAlgo::Sha1,
+        ]);
+        hasher.update(b"ab");
+        hasher.update(b"c");
+        assert_eq!(
+            hasher.finalize(),
+            vec![
+                (
+                    ChecksumAlgo::Sha256,
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+                ),
+                (
+                    ChecksumAlgo::Sha1,
+                    "a9993e364706816aba3e25717850c26c9cd0d89d".to_string()
+                ),
+                (
+                    ChecksumAlgo::Md5,
+                    "900150983cd24fb0d6963f7d28e17f72".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_sha256_in_listing() {
+        let release = "a".repeat(64);
+        let debug = "B".repeat(64);
+        let listing = format!(
+            "{}  UEFI-Shell-2.2-25H1-RELEASE.iso\n{} *UEFI-Shell-2.2-25H1-DEBUG.iso\n",
+            release, debug
+        );
+        assert_eq!(
+            find_sha256(&listing, "UEFI-Shell-2.2-25H1-DEBUG.iso"),
+            Some("b".repeat(64))
+        );
+        assert_eq!(find_sha256(&listing, "missing.iso"), None);
+        assert_eq!(
+            find_sha256(&format!("{}\n", debug), "any.iso"),
+            Some("b".repeat(64))
+        );
+    }
+}
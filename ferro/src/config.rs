@@ -0,0 +1,632 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::catalog;
+use crate::checksum;
+use crate::cli::{Cli, DownloadOptions};
+use crate::config_file;
+use crate::downloader::{
+    DownloadMode, DEFAULT_DOWNLOAD_TIMEOUT, DEFAULT_MAX_RETRIES, DEFAULT_SPACE_MARGIN,
+    DEFAULT_STALL_TIMEOUT,
+};
+use crate::http::{self, HttpOptions, UserAgentPreset};
+use crate::iso_api::{self, RequestDelay};
+use crate::output::{Output, OutputFormat};
+use crate::output_template;
+use crate::progress::ProgressFormat;
+use crate::session_cache;
+use crate::utils;
+
+/// Windows version downloaded when none is given
+pub const DEFAULT_VERSION: &str = "Windows 11";
+
+/// Parallel connections used by segmented modes when none is given
+pub const DEFAULT_CONNECTIONS: u16 = 4;
+
+/// Longest filename, in bytes, Ferro derives for a download when none is given
+pub const DEFAULT_MAX_FILENAME_LENGTH: u16 = 255;
+
+/// Proxy variables reqwest honours, in the order `--config-print` reports them
+const PROXY_ENV_VARS: [&str; 4] = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"];
+
+/// Where an effective setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// Given on the command line
+    Flag,
+    /// Taken from an environment variable
+    Env,
+    /// Taken from the config file
+    File,
+    /// Ferro's built-in default
+    Default,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Flag => write!(f, "flag"),
+            Source::Env => write!(f, "env"),
+            Source::File => write!(f, "file"),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// An effective setting together with the layer that supplied it
+#[derive(Debug, Clone)]
+pub struct Resolved<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Resolved<T> {
+    /// Take the flag's value if it was given, the default otherwise
+    pub fn from_flag(flag: Option<T>, default: T) -> Self {
+        match flag {
+            Some(value) => Self {
+                value,
+                source: Source::Flag,
+            },
+            None => Self {
+                value: default,
+                source: Source::Default,
+            },
+        }
+    }
+
+    /// Report a value that was filled in from the config file as coming from there
+    pub fn from_file_if(mut self, from_file: bool) -> Self {
+        if from_file && self.source == Source::Flag {
+            self.source = Source::File;
+        }
+        self
+    }
+}
+
+impl<T: From<String>> Resolved<T> {
+    /// Take the environment variable `var` if it's set and not empty, the default otherwise
+    pub fn from_env(var: &str, default: T) -> Self {
+        match std::env::var(var) {
+            Ok(value) if !value.is_empty() => Self {
+                value: value.into(),
+                source: Source::Env,
+            },
+            _ => Self {
+                value: default,
+                source: Source::Default,
+            },
+        }
+    }
+}
+
+impl Resolved<bool> {
+    /// Switches are only ever turned on from the command line
+    pub fn from_switch(flag: bool) -> Self {
+        Self::from_flag(flag.then_some(true), false)
+    }
+}
+
+/// One line of `--config-print` output
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub name: &'static str,
+    pub value: String,
+    pub source: Source,
+}
+
+fn entry<T>(name: &'static str, resolved: &Resolved<T>, display: impl Fn(&T) -> String) -> Entry {
+    Entry {
+        name,
+        value: display(&resolved.value),
+        source: resolved.source,
+    }
+}
+
+/// The command-line spelling of an enum value, e.g. `parallel-resumable`
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Effective global settings, resolved from the command line and defaults
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Log filter from -q/-v, or `RUST_LOG`
+    pub log_level: Resolved<String>,
+    pub quiet: Resolved<bool>,
+    pub format: Resolved<OutputFormat>,
+    pub compact: Resolved<bool>,
+    pub user_agent_preset: Resolved<Option<UserAgentPreset>>,
+    /// User-Agent for API requests, taking precedence over the preset there
+    pub user_agent: Resolved<Option<String>>,
+    pub resolve: Resolved<Vec<(String, IpAddr)>>,
+    /// Proxy from `--proxy`, or the one reqwest picks up from the environment
+    pub proxy: Resolved<Option<String>>,
+    /// Referer override, `None` to use each version's download page
+    pub referer: Resolved<Option<String>>,
+    pub warm_session: Resolved<bool>,
+    pub refresh_versions: Resolved<bool>,
+    pub no_cache: Resolved<bool>,
+    pub request_delay: Resolved<RequestDelay>,
+    pub api_timeout: Resolved<Duration>,
+    /// Locale from `--query-locale`, `None` to check the system locale
+    pub query_locale: Resolved<Option<String>>,
+    /// API profile IDs, more than one when rotating on retries
+    pub profiles: Resolved<Vec<String>>,
+    pub trace_timings: Resolved<bool>,
+    pub print_session_id: Resolved<bool>,
+    pub redact_logs: Resolved<bool>,
+    /// Catalog overlay file, `None` when there is none to apply
+    pub catalog_overlay: Resolved<Option<PathBuf>>,
+    /// Value of `FERRO_DUMP_RESPONSES`, `None` to save no API responses
+    pub dump_responses: Resolved<Option<String>>,
+    /// Config file with default download options, `None` when there is none
+    pub config_file: Resolved<Option<PathBuf>>,
+}
+
+impl Settings {
+    pub fn resolve(cli: &Cli) -> Self {
+        Self {
+            log_level: match cli.log_level() {
+                Some(level) => {
+                    Resolved::from_flag(Some(level.to_string().to_lowercase()), String::new())
+                }
+                None => Resolved::from_env("RUST_LOG", "error".to_string()),
+            },
+            quiet: Resolved::from_switch(cli.quiet),
+            format: Resolved::from_flag(cli.format, OutputFormat::default()),
+            // --pretty is the default, only --compact changes anything
+            compact: Resolved::from_switch(cli.compact),
+            user_agent_preset: Resolved::from_flag(cli.user_agent_preset.map(Some), None),
+            user_agent: match &cli.user_agent {
+                Some(user_agent) => Resolved::from_flag(Some(Some(user_agent.clone())), None),
+                None => Resolved::from_env(http::USER_AGENT_ENV_VAR, None),
+            },
+            resolve: Resolved::from_flag(
+                (!cli.resolve.is_empty()).then(|| cli.resolve.clone()),
+                Vec::new(),
+            ),
+            proxy: match &cli.proxy {
+                Some(proxy) => Resolved::from_flag(Some(Some(proxy.clone())), None),
+                None => PROXY_ENV_VARS
+                    .iter()
+                    .map(|var| Resolved::from_env(var, None))
+                    .find(|proxy| proxy.source == Source::Env)
+                    .unwrap_or_else(|| Resolved::from_flag(None, None)),
+            },
+            referer: Resolved::from_flag(cli.referer.clone().map(Some), None),
+            warm_session: Resolved::from_switch(cli.warm_session),
+            refresh_versions: Resolved::from_switch(cli.refresh_versions),
+            no_cache: Resolved::from_switch(cli.no_cache),
+            request_delay: Resolved::from_flag(
+                match (cli.delay, cli.min_delay, cli.max_delay) {
+                    (Some(delay), ..) => Some((delay, delay)),
+                    (None, None, None) => None,
+                    (None, min, max) => {
+                        let min = min.unwrap_or(0);
+                        Some((min, max.unwrap_or(min).max(min)))
+                    }
+                }
+                .map(|(min, max)| RequestDelay {
+                    min: Duration::from_millis(min),
+                    max: Duration::from_millis(max),
+                }),
+                RequestDelay::default(),
+            ),
+            api_timeout: Resolved::from_flag(
+                cli.api_timeout.map(Duration::from_secs),
+                iso_api::DEFAULT_API_TIMEOUT,
+            ),
+            query_locale: Resolved::from_flag(cli.query_locale.clone().map(Some), None),
+            profiles: Resolved::from_flag(
+                match &cli.profile {
+                    Some(profile) => Some(vec![profile.clone()]),
+                    None => (!cli.rotate_profiles.is_empty()).then(|| cli.rotate_profiles.clone()),
+                },
+                vec![iso_api::DEFAULT_PROFILE_ID.to_string()],
+            ),
+            trace_timings: Resolved::from_switch(cli.trace_timings),
+            print_session_id: Resolved::from_switch(cli.print_session_id),
+            redact_logs: Resolved::from_switch(cli.redact_logs),
+            catalog_overlay: Resolved::from_flag(
+                cli.catalog_overlay.clone().map(Some),
+                catalog::default_overlay_path().filter(|path| path.exists()),
+            ),
+            dump_responses: Resolved::from_env(iso_api::DUMP_ENV_VAR, None),
+            config_file: Resolved::from_flag(
+                cli.config.clone().map(Some),
+                config_file::default_path(),
+            ),
+        }
+    }
+
+    pub fn output(&self) -> Output {
+        Output {
+            format: self.format.value,
+            pretty: !self.compact.value,
+            quiet: self.quiet.value,
+        }
+    }
+
+    pub fn http_options(&self) -> HttpOptions {
+        HttpOptions {
+            user_agent: self
+                .user_agent_preset
+                .value
+                .map(|preset| preset.user_agent().to_string()),
+            resolve: self.resolve.value.clone(),
+            // reqwest reads the environment variables itself, telling HTTP and HTTPS apart
+            proxy: match self.proxy.source {
+                Source::Flag => self.proxy.value.clone(),
+                _ => None,
+            },
+        }
+    }
+
+    /// File Microsoft sessions are cached in between runs, `None` with `--no-cache`
+    pub fn session_cache(&self) -> Option<PathBuf> {
+        if self.no_cache.value {
+            return None;
+        }
+        session_cache::default_path()
+    }
+
+    /// Directory to save the raw API responses to, if asked to
+    pub fn dump_dir(&self) -> Option<PathBuf> {
+        self.dump_responses.value.as_deref().map(iso_api::dump_dir)
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        vec![
+            entry("log-level", &self.log_level, String::clone),
+            entry("quiet", &self.quiet, bool::to_string),
+            entry("format", &self.format, value_name),
+            entry("json-style", &self.compact, |compact| {
+                if *compact { "compact" } else { "pretty" }.to_string()
+            }),
+            entry("user-agent-preset", &self.user_agent_preset, |preset| {
+                preset.map_or(
+                    "none (PowerShell for the API, browser for downloads)".to_string(),
+                    |preset| value_name(&preset),
+                )
+            }),
+            entry("user-agent", &self.user_agent, |user_agent| {
+                user_agent
+                    .clone()
+                    .unwrap_or_else(|| "none (the preset or PowerShell)".to_string())
+            }),
+            entry("resolve", &self.resolve, |overrides| {
+                if overrides.is_empty() {
+                    return "none".to_string();
+                }
+                overrides
+                    .iter()
+                    .map(|(host, ip)| format!("{}:{}", host, ip))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }),
+            entry("proxy", &self.proxy, |proxy| {
+                proxy
+                    .as_deref()
+                    .map_or("none".to_string(), http::mask_credentials)
+            }),
+            entry("referer", &self.referer, |referer| {
+                referer
+                    .clone()
+                    .unwrap_or_else(|| "the version's download page".to_string())
+            }),
+            entry("warm-session", &self.warm_session, bool::to_string),
+            entry("refresh-versions", &self.refresh_versions, bool::to_string),
+            entry("request-delay", &self.request_delay, |delay| {
+                match (delay.min.as_millis(), delay.max.as_millis()) {
+                    (0, 0) => "none".to_string(),
+                    (min, max) if min == max => format!("{}ms", min),
+                    (min, max) => format!("{}-{}ms", min, max),
+                }
+            }),
+            entry("api-timeout", &self.api_timeout, |timeout| {
+                format!("{}s", timeout.as_secs())
+            }),
+            entry("query-locale", &self.query_locale, |locale| {
+                locale
+                    .clone()
+                    .unwrap_or_else(|| "system locale if supported, else en-US".to_string())
+            }),
+            entry("session-cache", &self.no_cache, |_| {
+                self.session_cache()
+                    .map_or("off".to_string(), |path| path.display().to_string())
+            }),
+            entry("profiles", &self.profiles, |profiles| profiles.join(", ")),
+            entry("trace-timings", &self.trace_timings, bool::to_string),
+            entry("print-session-id", &self.print_session_id, bool::to_string),
+            entry("redact-logs", &self.redact_logs, bool::to_string),
+            entry("catalog-overlay", &self.catalog_overlay, |path| {
+                path.as_ref()
+                    .map_or("none".to_string(), |path| path.display().to_string())
+            }),
+            entry("dump-responses", &self.dump_responses, |_| {
+                self.dump_dir()
+                    .map_or("none".to_string(), |dir| dir.display().to_string())
+            }),
+            entry("config-file", &self.config_file, |path| {
+                path.as_ref()
+                    .map_or("none".to_string(), |path| path.display().to_string())
+            }),
+        ]
+    }
+}
+
+/// Effective settings of the download and which commands
+#[derive(Debug, Clone)]
+pub struct DownloadSettings {
+    pub version: Resolved<String>,
+    pub mode: Resolved<DownloadMode>,
+    pub connections: Resolved<u16>,
+    pub space_margin: Resolved<u64>,
+    pub stall_timeout: Resolved<Duration>,
+    pub download_timeout: Resolved<Duration>,
+    pub max_retries: Resolved<u32>,
+    /// Download speed cap in bytes per second, `None` for no cap
+    pub limit_rate: Resolved<Option<u64>>,
+    pub progress_format: Resolved<ProgressFormat>,
+    pub max_catalog_age: Resolved<u32>,
+    pub max_filename_length: Resolved<u16>,
+    /// Read size when hashing existing files
+    pub hash_buffer: Resolved<u64>,
+    /// Path template for downloads without --output, `None` to use the default filename
+    pub output_template: Resolved<Option<String>>,
+}
+
+impl DownloadSettings {
+    pub fn resolve(options: &DownloadOptions) -> Self {
+        let from_file = |id: &str| options.set_by_config_file(id);
+        Self {
+            version: Resolved::from_flag(options.version.clone(), DEFAULT_VERSION.to_string())
+                .from_file_if(from_file("version")),
+            // Asking for several connections only makes sense with a segmented mode
+            mode: Resolved::from_flag(
+                options.mode.or(options
+                    .connections
+                    .filter(|&connections| connections > 1)
+                    .map(|_| DownloadMode::ParallelResumable)),
+                DownloadMode::default(),
+            )
+            .from_file_if(from_file("mode") || options.mode.is_none() && from_file("connections")),
+            connections: Resolved::from_flag(options.connections, DEFAULT_CONNECTIONS)
+                .from_file_if(from_file("connections")),
+            space_margin: Resolved::from_flag(options.space_margin, DEFAULT_SPACE_MARGIN)
+                .from_file_if(from_file("space_margin")),
+            stall_timeout: Resolved::from_flag(
+                options.stall_timeout.map(Duration::from_secs),
+                DEFAULT_STALL_TIMEOUT,
+            )
+            .from_file_if(from_file("stall_timeout")),
+            download_timeout: Resolved::from_flag(
+                options.download_timeout.map(Duration::from_secs),
+                DEFAULT_DOWNLOAD_TIMEOUT,
+            )
+            .from_file_if(from_file("download_timeout")),
+            max_retries: Resolved::from_flag(options.max_retries, DEFAULT_MAX_RETRIES)
+                .from_file_if(from_file("max_retries")),
+            limit_rate: Resolved::from_flag(options.limit_rate.map(Some), None)
+                .from_file_if(from_file("limit_rate")),
+            progress_format: Resolved::from_flag(
+                options.progress_format,
+                ProgressFormat::default(),
+            )
+            .from_file_if(from_file("progress_format")),
+            max_catalog_age: Resolved::from_flag(
+                options.max_catalog_age,
+                catalog::DEFAULT_MAX_AGE_MONTHS,
+            )
+            .from_file_if(from_file("max_catalog_age")),
+            max_filename_length: Resolved::from_flag(
+                options.max_filename_length,
+                DEFAULT_MAX_FILENAME_LENGTH,
+            )
+            .from_file_if(from_file("max_filename_length")),
+            hash_buffer: Resolved::from_flag(
+                options.hash_buffer,
+                checksum::HASH_BUFFER_SIZE as u64,
+            )
+            .from_file_if(from_file("hash_buffer")),
+            output_template: Resolved::from_env(output_template::ENV_VAR, None),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        vec![
+            entry("version", &self.version, String::clone),
+            entry("mode", &self.mode, value_name),
+            entry("connections", &self.connections, u16::to_string),
+            entry("space-margin", &self.space_margin, |margin| {
+                utils::bytes_to_human_readable(*margin)
+            }),
+            entry("stall-timeout", &self.stall_timeout, |timeout| {
+                format!("{}s", timeout.as_secs())
+            }),
+            entry("download-timeout", &self.download_timeout, |timeout| {
+                format!("{}s", timeout.as_secs())
+            }),
+            entry("max-retries", &self.max_retries, u32::to_string),
+            entry("limit-rate", &self.limit_rate, |rate| {
+                rate.map_or("none".to_string(), |rate| {
+                    format!("{}/s", utils::bytes_to_human_readable(rate))
+                })
+            }),
+            entry("progress-format", &self.progress_format, value_name),
+            entry("max-catalog-age", &self.max_catalog_age, u32::to_string),
+            entry(
+                "max-filename-length",
+                &self.max_filename_length,
+                u16::to_string,
+            ),
+            entry("hash-buffer", &self.hash_buffer, |size| {
+                utils::bytes_to_human_readable(*size)
+            }),
+            entry("output-template", &self.output_template, |template| {
+                template.clone().unwrap_or_else(|| "none".to_string())
+            }),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_settings_record_source() {
+        let cli = Cli::parse_from(["ferro", "--format", "json", "list", "versions"]);
+        let settings = Settings::resolve(&cli);
+        assert_eq!(settings.format.value, OutputFormat::Json);
+        assert_eq!(settings.format.source, Source::Flag);
+        assert_eq!(settings.compact.source, Source::Default);
+        assert!(settings.output().pretty);
+    }
+
+    #[test]
+    fn test_log_level_settings() {
+        let log_level = |args: &[&str]| {
+            let cli = Cli::parse_from(["ferro"].iter().chain(args).chain(&["list", "versions"]));
+            Settings::resolve(&cli).log_level
+        };
+        assert_eq!(log_level(&["-q"]).value, "error");
+        assert_eq!(log_level(&["-v"]).value, "info");
+        assert_eq!(log_level(&["-vv"]).value, "debug");
+        assert_eq!(log_level(&["-vvvv"]).value, "trace");
+        assert_eq!(log_level(&["-vv"]).source, Source::Flag);
+
+        assert!(Cli::try_parse_from(["ferro", "-q", "-v", "list", "versions"]).is_err());
+    }
+
+    #[test]
+    fn test_profile_settings() {
+        let cli = Cli::parse_from(["ferro", "list", "versions"]);
+        let profiles = Settings::resolve(&cli).profiles;
+        assert_eq!(profiles.value, vec![iso_api::DEFAULT_PROFILE_ID]);
+        assert_eq!(profiles.source, Source::Default);
+
+        let cli = Cli::parse_from(["ferro", "--rotate-profiles", "a1,b2", "list", "versions"]);
+        let profiles = Settings::resolve(&cli).profiles;
+        assert_eq!(profiles.value, vec!["a1", "b2"]);
+        assert_eq!(profiles.source, Source::Flag);
+
+        assert!(
+            Cli::try_parse_from(["ferro", "--profile", "a1", "--rotate-profiles", "b2"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_user_agent_settings() {
+        let cli = Cli::parse_from([
+            "ferro",
+            "--user-agent",
+            "Custom/1.0",
+            "--user-agent-preset",
+            "firefox",
+            "list",
+            "versions",
+        ]);
+        let settings = Settings::resolve(&cli);
+        assert_eq!(settings.user_agent.value.as_deref(), Some("Custom/1.0"));
+        assert_eq!(settings.user_agent.source, Source::Flag);
+        // Only the API requests use it, downloads keep the preset
+        assert_eq!(
+            settings.http_options().user_agent.as_deref(),
+            Some(UserAgentPreset::Firefox.user_agent())
+        );
+    }
+
+    #[test]
+    fn test_timeout_settings() {
+        let cli = Cli::parse_from(["ferro", "list", "versions"]);
+        let api_timeout = Settings::resolve(&cli).api_timeout;
+        assert_eq!(api_timeout.value, iso_api::DEFAULT_API_TIMEOUT);
+        assert_eq!(api_timeout.source, Source::Default);
+
+        let cli = Cli::parse_from(["ferro", "--api-timeout", "10", "list", "versions"]);
+        assert_eq!(
+            Settings::resolve(&cli).api_timeout.value,
+            Duration::from_secs(10)
+        );
+
+        let download_timeout = |args: &[&str]| {
+            let cli = Cli::parse_from(["ferro", "download"].iter().chain(args));
+            let Some(crate::cli::Commands::Download { options }) = cli.command else {
+                panic!("expected the download command");
+            };
+            DownloadSettings::resolve(&options).download_timeout.value
+        };
+        assert_eq!(download_timeout(&[]), DEFAULT_DOWNLOAD_TIMEOUT);
+        assert_eq!(
+            download_timeout(&["--download-timeout", "900"]),
+            Duration::from_secs(900)
+        );
+
+        assert!(Cli::try_parse_from(["ferro", "--api-timeout", "0", "list", "versions"]).is_err());
+    }
+
+    #[test]
+    fn test_request_delay_settings() {
+        let delay = |args: &[&str]| {
+            let cli = Cli::parse_from(["ferro"].iter().chain(args).chain(&["list", "versions"]));
+            let delay = Settings::resolve(&cli).request_delay.value;
+            (delay.min.as_millis(), delay.max.as_millis())
+        };
+        assert_eq!(delay(&[]), (0, 0));
+        assert_eq!(delay(&["--delay", "800"]), (800, 800));
+        assert_eq!(
+            delay(&["--min-delay", "500", "--max-delay", "1500"]),
+            (500, 1500)
+        );
+        assert_eq!(delay(&["--min-delay", "500"]), (500, 500));
+        assert_eq!(delay(&["--max-delay", "1500"]), (0, 1500));
+        assert_eq!(
+            delay(&["--min-delay", "900", "--max-delay", "100"]),
+            (900, 900)
+        );
+
+        assert!(Cli::try_parse_from(["ferro", "--delay", "0", "--min-delay", "5"]).is_err());
+    }
+
+    #[test]
+    fn test_download_settings_defaults() {
+        let cli = Cli::parse_from(["ferro", "download", "--connections", "8"]);
+        let Some(crate::cli::Commands::Download { options }) = cli.command else {
+            panic!("expected the download command");
+        };
+        let settings = DownloadSettings::resolve(&options);
+        assert_eq!(settings.version.value, DEFAULT_VERSION);
+        assert_eq!(settings.version.source, Source::Default);
+        assert_eq!(settings.connections.value, 8);
+        assert_eq!(settings.connections.source, Source::Flag);
+        assert_eq!(settings.mode.value, DownloadMode::ParallelResumable);
+    }
+
+    #[test]
+    fn test_mode_follows_connections() {
+        let mode = |args: &[&str]| {
+            let cli = Cli::parse_from(["ferro", "download"].iter().chain(args));
+            let Some(crate::cli::Commands::Download { options }) = cli.command else {
+                panic!("expected the download command");
+            };
+            DownloadSettings::resolve(&options).mode
+        };
+        assert_eq!(mode(&[]).value, DownloadMode::Single);
+        assert_eq!(mode(&[]).source, Source::Default);
+        assert_eq!(mode(&["--connections", "1"]).value, DownloadMode::Single);
+        assert_eq!(
+            mode(&["--connections", "8", "--mode", "h2-parallel"]).value,
+            DownloadMode::H2Parallel
+        );
+    }
+}
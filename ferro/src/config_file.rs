@@ -0,0 +1,223 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::parser::{ArgMatches, ValueSource};
+use clap::{Arg, Command, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Cli;
+
+/// Commands whose options the `[download]` table sets defaults for
+const DOWNLOAD_COMMANDS: [&str; 3] = ["download", "fetch", "which"];
+
+/// A config file: `[download]` holds defaults for any `ferro download` option, keyed by its
+/// long name, e.g. `language = "English"` or `checksum-algo = ["sha256"]`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub download: toml::Table,
+}
+
+/// Places searched for a config file when `--config` isn't given, in order:
+/// `<config dir>/ferro/config.toml`, then `~/.config/ferro/config.toml` where the platform's
+/// config directory is elsewhere, as on macOS
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = dirs::config_dir()
+        .into_iter()
+        .chain(dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("ferro").join("config.toml"))
+        .collect();
+    paths.dedup();
+    paths
+}
+
+/// The first config file found in [`search_paths`], if any
+pub fn default_path() -> Option<PathBuf> {
+    search_paths().into_iter().find(|path| path.exists())
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Parse the command line `args` again with this file's defaults added for every option
+    /// the command line leaves out. Options the command line sets, or that conflict with one
+    /// it sets (e.g. `language` with `--language-index`), keep the command line's meaning.
+    pub fn apply(&self, path: &Path, args: Vec<OsString>) -> Result<Cli> {
+        let command = Cli::command();
+        let matches = command.clone().try_get_matches_from(&args)?;
+        let Some((name, sub_matches)) = matches
+            .subcommand()
+            .filter(|(name, _)| DOWNLOAD_COMMANDS.contains(name))
+        else {
+            return Ok(Cli::from_arg_matches(&matches)?);
+        };
+        let subcommand = command
+            .find_subcommand(name)
+            .context("Unknown subcommand")?;
+
+        let mut defaults = Vec::new();
+        let mut applied = Vec::new();
+        for (key, value) in &self.download {
+            let arg = subcommand
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+                .ok_or_else(|| anyhow!("{}: `{}` is not a download option", path.display(), key))?;
+            if given(subcommand, sub_matches, name, arg) {
+                continue;
+            }
+            let values = option_values(value)
+                .with_context(|| format!("{}: invalid value for `{}`", path.display(), key))?;
+            if arg.get_action().takes_values() {
+                defaults.extend(values.iter().map(|value| format!("--{}={}", key, value)));
+            } else {
+                match values.as_slice() {
+                    [value] if value == "true" => defaults.push(format!("--{}", key)),
+                    [value] if value == "false" => continue,
+                    _ => bail!("{}: `{}` must be true or false", path.display(), key),
+                }
+            }
+            applied.push(arg.get_id().to_string());
+        }
+
+        let mut args = args;
+        args.extend(defaults.into_iter().map(OsString::from));
+        let matches = command.try_get_matches_from(&args).map_err(|e| {
+            let message = e.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            anyhow!(
+                "{}: {}",
+                path.display(),
+                message.trim_start_matches("error: ")
+            )
+        })?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        if let Some(options) = cli.download_options_mut() {
+            options.from_config_file = applied;
+        }
+        Ok(cli)
+    }
+}
+
+/// Whether the command line sets `arg`, or an option that can't be combined with it.
+/// `fetch` takes its link in place of `--url`, so it counts as given there.
+fn given(subcommand: &Command, matches: &ArgMatches, name: &str, arg: &Arg) -> bool {
+    let on_command_line = |other: &Arg| {
+        let id = other.get_id().as_str();
+        (name == "fetch" && id == "url")
+            || matches!(matches.value_source(id), Some(ValueSource::CommandLine))
+    };
+    on_command_line(arg)
+        || subcommand
+            .get_arg_conflicts_with(arg)
+            .into_iter()
+            .any(on_command_line)
+        || subcommand.get_arguments().any(|other| {
+            on_command_line(other)
+                && subcommand
+                    .get_arg_conflicts_with(other)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == arg.get_id())
+        })
+}
+
+/// A TOML value as command-line values: one per array item
+fn option_values(value: &toml::Value) -> Result<Vec<String>> {
+    match value {
+        toml::Value::String(value) => Ok(vec![value.clone()]),
+        toml::Value::Integer(value) => Ok(vec![value.to_string()]),
+        toml::Value::Float(value) => Ok(vec![value.to_string()]),
+        toml::Value::Boolean(value) => Ok(vec![value.to_string()]),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::Array(_) | toml::Value::Table(_) => {
+                    Err(anyhow!("arrays can only hold plain values"))
+                }
+                value => Ok(option_values(value)?.remove(0)),
+            })
+            .collect(),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => {
+            Err(anyhow!("expected a string, number, boolean or array"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Commands;
+
+    fn apply(file: &str, args: &[&str]) -> Result<Cli> {
+        let file: ConfigFile = toml::from_str(file).unwrap();
+        let args = std::iter::once("ferro").chain(args.iter().copied());
+        file.apply(Path::new("config.toml"), args.map(OsString::from).collect())
+    }
+
+    fn download_options(cli: Cli) -> crate::cli::DownloadOptions {
+        match cli.command {
+            Some(Commands::Download { options }) => options,
+            _ => panic!("expected the download command"),
+        }
+    }
+
+    #[test]
+    fn test_config_file_defaults() {
+        let file = r#"
+            [download]
+            version = "Windows 10"
+            language = "English"
+            connections = 8
+            checksum-algo = ["sha256", "sha1"]
+            write-checksum = true
+            validate-iso = false
+        "#;
+        let options = download_options(apply(file, &["download", "--language", "German"]).unwrap());
+        assert_eq!(options.version.as_deref(), Some("Windows 10"));
+        // The command line wins
+        assert_eq!(options.language.as_deref(), Some("German"));
+        assert_eq!(options.connections, Some(8));
+        assert_eq!(options.checksum_algo.len(), 2);
+        assert!(options.write_checksum);
+        assert!(!options.validate_iso);
+        assert!(options
+            .from_config_file
+            .contains(&"connections".to_string()));
+        assert!(!options.from_config_file.contains(&"language".to_string()));
+    }
+
+    #[test]
+    fn test_config_file_skips_conflicting_defaults() {
+        let file = "[download]\nlanguage = \"English\"\nversion = \"Windows 10\"\n";
+        let options =
+            download_options(apply(file, &["download", "--language-index", "2"]).unwrap());
+        assert_eq!(options.language, None);
+        assert_eq!(options.language_index, Some(2));
+
+        let options = download_options(
+            apply(file, &["download", "--url", "https://example.com/a.iso"]).unwrap(),
+        );
+        assert_eq!(options.version, None);
+
+        // fetch takes a link, so the selection is left out there too
+        let cli = apply(file, &["fetch", "https://example.com/a.iso"]).unwrap();
+        let Some(Commands::Fetch { options, .. }) = cli.command else {
+            panic!("expected the fetch command");
+        };
+        assert_eq!(options.version, None);
+    }
+
+    #[test]
+    fn test_config_file_errors() {
+        assert!(apply("[download]\nno-such-option = 1\n", &["download"]).is_err());
+        assert!(apply("[download]\nconnections = 99\n", &["download"]).is_err());
+        assert!(apply("[download]\nwrite-checksum = \"yes\"\n", &["download"]).is_err());
+        assert!(toml::from_str::<ConfigFile>("[upload]\n").is_err());
+        // Other commands ignore the download defaults
+        assert!(apply("[download]\nconnections = 99\n", &["list", "versions"]).is_ok());
+    }
+}
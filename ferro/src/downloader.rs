@@ -1,42 +1,327 @@
 use anyhow::{Context, Result};
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, warn};
-use reqwest::Client;
-use std::path::Path;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE};
+use reqwest::{Client, RequestBuilder, StatusCode, Version};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use crate::checksum::{self, ChecksumAlgo, MultiHasher};
+use crate::http::{self, HttpOptions, DOWNLOAD_USER_AGENT};
+use crate::lock::OutputLock;
+use crate::progress::{self, ProgressFormat};
+use crate::rate_limit::RateLimiter;
+use crate::redact::redact;
+use crate::segmented;
+use crate::signals;
+use crate::sink::{self, Compression, TeeWriter};
+use crate::timings::Timings;
 use crate::utils;
 
+/// How the file is transferred from the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DownloadMode {
+    /// Stream the file over a single connection
+    #[default]
+    Single,
+    /// Download segments over parallel connections, resuming each segment after an interruption
+    ParallelResumable,
+    /// Like parallel-resumable, but multiplex the segments' range requests over a single
+    /// HTTP/2 connection, using separate connections when the server only speaks HTTP/1.1
+    H2Parallel,
+}
+
+impl DownloadMode {
+    /// Whether the mode downloads byte ranges concurrently into a `.part` file
+    pub fn is_segmented(self) -> bool {
+        matches!(
+            self,
+            DownloadMode::ParallelResumable | DownloadMode::H2Parallel
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloaderConfig {
+    pub mode: DownloadMode,
+    /// Number of concurrent connections used by segmented modes
+    pub connections: usize,
+    pub http: HttpOptions,
+    pub timings: Timings,
+    /// Free space to leave on the target filesystem on top of the file size
+    pub space_margin: u64,
+    /// Only warn when the free-space check finds too little room, instead of failing
+    pub force: bool,
+    /// Keep writing the remaining outputs when one of several destinations fails
+    pub tolerate_output_errors: bool,
+    /// Digests to compute from the downloaded data
+    pub checksum_algos: Vec<ChecksumAlgo>,
+    pub progress_format: ProgressFormat,
+    /// How long a transfer may go without receiving any data before it reconnects
+    pub stall_timeout: Duration,
+    /// How long any request may wait for the server to respond or send more data before it
+    /// fails. There is no limit on a whole transfer, which can take hours for a large ISO.
+    pub read_timeout: Duration,
+    /// Compress the outputs as they're written, adding the format's extension to each
+    pub compression: Option<Compression>,
+    /// Delete any partial download of the outputs first instead of resuming it
+    pub clobber_part: bool,
+    /// Times in a row a transfer interrupted by a network error is resumed before giving up
+    pub max_retries: u32,
+    /// Cap on the download speed in bytes per second, shared by all connections
+    pub limit_rate: Option<u64>,
+}
+
+/// What a finished download produced
+#[derive(Debug, Clone)]
+pub struct Downloaded {
+    /// Outputs that were written successfully
+    pub paths: Vec<PathBuf>,
+    /// Bytes downloaded, before any compression
+    pub size: u64,
+    /// Digest of the file for each configured checksum algorithm
+    pub checksums: Vec<(ChecksumAlgo, String)>,
+    /// Version of the remote file that was downloaded
+    pub remote: RemoteVersion,
+}
+
+/// Result of a conditional download
+#[derive(Debug, Clone)]
+pub enum Fetched {
+    Downloaded(Downloaded),
+    /// The remote file hasn't changed since the previous download
+    NotModified,
+}
+
+/// The validators that identify one version of a remote file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteVersion {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl RemoteVersion {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Ask the server to answer 304 Not Modified if the file is still this version
+    fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        let mut request = request;
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+
+    /// Whether `current` is the same version, for servers that ignore conditional headers.
+    /// The ETag decides when both sides have one, Last-Modified otherwise.
+    fn matches(&self, current: &RemoteVersion) -> bool {
+        match (&self.etag, &current.etag) {
+            (Some(previous), Some(current)) => previous == current,
+            _ => match (&self.last_modified, &current.last_modified) {
+                (Some(previous), Some(current)) => previous == current,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Default free-space margin, so the target filesystem is never filled to the last byte
+pub const DEFAULT_SPACE_MARGIN: u64 = 512 * 1024 * 1024;
+
+/// Default for how long a transfer may receive nothing before it counts as stalled
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default for how long a download request may wait on the server
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default for how often an interrupted transfer is resumed
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Longest wait between two attempts at resuming a transfer
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Wait before the `attempt`th resume: 1s, 2s, 4s, ... up to [`MAX_RETRY_BACKOFF`]
+fn retry_backoff(attempt: u32) -> Duration {
+    1u64.checked_shl(attempt.saturating_sub(1))
+        .map_or(MAX_RETRY_BACKOFF, Duration::from_secs)
+        .min(MAX_RETRY_BACKOFF)
+}
+
+impl Default for DownloaderConfig {
+    fn default() -> Self {
+        Self {
+            mode: DownloadMode::Single,
+            connections: 4,
+            http: HttpOptions::default(),
+            timings: Timings::default(),
+            space_margin: DEFAULT_SPACE_MARGIN,
+            force: false,
+            tolerate_output_errors: false,
+            checksum_algos: Vec::new(),
+            progress_format: ProgressFormat::default(),
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            read_timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+            compression: None,
+            clobber_part: false,
+            max_retries: DEFAULT_MAX_RETRIES,
+            limit_rate: None,
+        }
+    }
+}
+
 pub struct Downloader {
     client: Client,
+    config: DownloaderConfig,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Downloader {
     pub fn new() -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300)) // 5 minutes timeout for downloads
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-            .build()
-            .expect("Failed to create HTTP client");
+        Self::with_config(DownloaderConfig::default())
+    }
+
+    pub fn with_config(config: DownloaderConfig) -> Self {
+        let builder = config
+            .http
+            .configure(Client::builder(), DOWNLOAD_USER_AGENT)
+            .read_timeout(config.read_timeout);
+        let builder = match config.mode {
+            // An HTTP/2 server would otherwise get every segment multiplexed over one connection
+            DownloadMode::ParallelResumable => builder.http1_only(),
+            // All segments share the connection's flow-control window, so let it grow
+            DownloadMode::H2Parallel => builder.http2_adaptive_window(true),
+            DownloadMode::Single => builder,
+        };
+        let client = builder.build().expect("Failed to create HTTP client");
 
-        Self { client }
+        let rate_limiter = config
+            .limit_rate
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+
+        Self {
+            client,
+            config,
+            rate_limiter,
+        }
     }
 
     pub async fn download<P: AsRef<Path>>(&self, url: &str, output_path: P) -> Result<()> {
-        let output_path = output_path.as_ref();
+        self.download_to_many(url, &[output_path.as_ref().to_path_buf()])
+            .await
+            .map(|_| ())
+    }
+
+    /// Download `url` once, writing every chunk to all of `output_paths` and
+    /// feeding it to the configured hashers. An output path of `-` is standard output.
+    pub async fn download_to_many(
+        &self,
+        url: &str,
+        output_paths: &[PathBuf],
+    ) -> Result<Downloaded> {
+        match self.fetch(url, output_paths, None).await? {
+            Fetched::Downloaded(downloaded) => Ok(downloaded),
+            Fetched::NotModified => unreachable!("unconditional downloads are never skipped"),
+        }
+    }
 
-        info!("Starting download: {}", url);
-        info!("Output file: {}", output_path.display());
+    /// Like [`Downloader::download_to_many`], but skip the transfer when the remote file
+    /// is still the `previous` version
+    pub async fn download_if_changed(
+        &self,
+        url: &str,
+        output_paths: &[PathBuf],
+        previous: &RemoteVersion,
+    ) -> Result<Fetched> {
+        self.fetch(url, output_paths, Some(previous)).await
+    }
+
+    async fn fetch(
+        &self,
+        url: &str,
+        output_paths: &[PathBuf],
+        previous: Option<&RemoteVersion>,
+    ) -> Result<Fetched> {
+        let _timer = self.config.timings.start("download");
+        let compressed_paths: Vec<PathBuf>;
+        let output_paths = match self.config.compression {
+            Some(compression) => {
+                compressed_paths = output_paths
+                    .iter()
+                    .map(|path| compression.compressed_path(path))
+                    .collect();
+                &compressed_paths
+            }
+            None => output_paths,
+        };
+
+        // Standard output has no partial file, lock or free space to check
+        let files: Vec<PathBuf> = output_paths
+            .iter()
+            .filter(|path| !sink::is_stdout(path))
+            .cloned()
+            .collect();
+        let to_stdout = files.len() < output_paths.len();
+
+        // A file:// source can't also be a destination, it would be truncated before it's read
+        let source = url::Url::parse(url)
+            .ok()
+            .filter(|parsed| parsed.scheme() == "file")
+            .and_then(|parsed| parsed.to_file_path().ok());
+        sink::check_collisions(&files, source.as_deref())?;
+        let _locks = files
+            .iter()
+            .map(|path| OutputLock::acquire(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        if self.config.clobber_part {
+            for output_path in &files {
+                segmented::discard_partial(url, output_path).await?;
+            }
+        }
+
+        info!("Starting download: {}", redact(url));
+        for output_path in output_paths {
+            info!("Output file: {}", output_path.display());
+        }
 
         // Get file size first
-        let head_response = self
-            .client
-            .head(url)
-            .send()
-            .await
-            .context("Failed to get file information")?;
+        let head_response = http::send_with_connect_retry(
+            || match previous {
+                Some(previous) => previous.apply(self.client.head(url)),
+                None => self.client.head(url),
+            },
+            "file information",
+        )
+        .await
+        .context("Failed to get file information")?;
+
+        let remote = RemoteVersion::from_headers(head_response.headers());
+        if let Some(previous) = previous {
+            if head_response.status() == StatusCode::NOT_MODIFIED || previous.matches(&remote) {
+                info!("Remote file has not changed since the previous download");
+                return Ok(Fetched::NotModified);
+            }
+        }
 
         let content_length = head_response
             .headers()
@@ -46,15 +331,90 @@ impl Downloader {
 
         if let Some(size) = content_length {
             info!("File size: {}", utils::bytes_to_human_readable(size));
+            for output_path in &files {
+                self.check_free_space(output_path, size)?;
+            }
         }
 
+        let accepts_ranges = head_response
+            .headers()
+            .get("accept-ranges")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("bytes"));
+
+        if self.config.mode.is_segmented() && self.config.compression.is_some() {
+            warn!("Compressed downloads are written as a single stream, falling back to a single connection");
+        } else if self.config.mode.is_segmented() && to_stdout {
+            warn!("Standard output is written as a single stream, falling back to a single connection");
+        } else if self.config.mode.is_segmented() {
+            match (content_length, output_paths) {
+                (Some(total_size), [output_path]) if accepts_ranges && total_size > 0 => {
+                    if self.config.mode == DownloadMode::H2Parallel {
+                        if head_response.version() == Version::HTTP_2 {
+                            info!(
+                                "Multiplexing {} range requests over one HTTP/2 connection",
+                                self.config.connections
+                            );
+                        } else {
+                            info!("Server does not speak HTTP/2, using separate connections");
+                        }
+                    }
+                    segmented::download(
+                        &self.client,
+                        url,
+                        output_path,
+                        total_size,
+                        self.config.connections,
+                        segmented::Pacing {
+                            stall_timeout: self.config.stall_timeout,
+                            rate_limiter: self.rate_limiter.clone(),
+                        },
+                        progress::observer(self.config.progress_format, Some(total_size)),
+                    )
+                    .await?;
+                    info!("Download completed successfully");
+
+                    // Segments arrive out of order, so hash the assembled file instead
+                    let checksums = if self.config.checksum_algos.is_empty() {
+                        Vec::new()
+                    } else {
+                        checksum::hash_file(output_path, &self.config.checksum_algos).await?
+                    };
+                    return Ok(Fetched::Downloaded(Downloaded {
+                        paths: output_paths.to_vec(),
+                        size: total_size,
+                        checksums,
+                        remote,
+                    }));
+                }
+                (_, [_]) => warn!(
+                    "Server does not support range requests, falling back to a single connection"
+                ),
+                _ => warn!("Segmented downloads write a single output, falling back to a single connection"),
+            }
+        }
+
+        // Continue a partial file left by an earlier attempt, if the server lets us
+        let mut offset = match output_paths {
+            [output_path] if accepts_ranges && self.config.compression.is_none() && !to_stdout => {
+                resumable_offset(output_path, content_length).await?
+            }
+            _ => 0,
+        };
+
         // Start the actual download
-        let response = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .context("Failed to start download")?;
+        let response = http::send_with_connect_retry(
+            || match offset {
+                0 => self.client.get(url),
+                offset => self
+                    .client
+                    .get(url)
+                    .header(RANGE, format!("bytes={}-", offset)),
+            },
+            "download",
+        )
+        .await
+        .context("Failed to start download")?;
 
         if !response.status().is_success() {
             return Err(anyhow::anyhow!(
@@ -62,62 +422,250 @@ impl Downloader {
                 response.status()
             ));
         }
+        if offset > 0 {
+            if response.status() == StatusCode::PARTIAL_CONTENT {
+                info!(
+                    "Resuming download from {}",
+                    utils::bytes_to_human_readable(offset)
+                );
+            } else {
+                warn!(
+                    "Server ignored the range request, restarting the download from the beginning"
+                );
+                offset = 0;
+            }
+        }
 
-        // Create progress bar
-        let progress_bar = if let Some(total_size) = content_length {
-            let pb = ProgressBar::new(total_size);
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
-                    .progress_chars("#>-"),
-            );
-            Some(pb)
+        let progress = progress::observer(self.config.progress_format, content_length);
+        let mut hasher = MultiHasher::new(&self.config.checksum_algos);
+
+        // Create output files, or reopen the partial one after the bytes it already holds
+        let mut writer = if offset > 0 {
+            let part_path = segmented::part_path(&output_paths[0]);
+            hasher.update_from_file(&part_path).await?;
+            progress.set_position(offset);
+            TeeWriter::append(&output_paths[0]).await?
         } else {
-            warn!("Content-Length header not found, progress bar disabled");
-            None
+            TeeWriter::create(
+                output_paths,
+                self.config.tolerate_output_errors,
+                self.config.compression,
+            )
+            .await?
         };
 
-        // Create output file
-        let mut file = File::create(output_path)
-            .await
-            .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
-
         // Stream the download
         let mut stream = response.bytes_stream();
-        let mut downloaded = 0u64;
+        let mut received = offset;
+        let mut stalls = 0;
+        let mut retries = 0;
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.context("Failed to read chunk from response")?;
-            file.write_all(&chunk)
-                .await
-                .context("Failed to write chunk to file")?;
+        loop {
+            let chunk = tokio::select! {
+                chunk = tokio::time::timeout(self.config.stall_timeout, stream.next()) => chunk,
+                signal = signals::received() => {
+                    writer.close().await?;
+                    let partial: Vec<String> = writer
+                        .paths()
+                        .iter()
+                        .map(|path| match sink::is_stdout(path) {
+                            true => "standard output".to_string(),
+                            false => segmented::part_path(path).display().to_string(),
+                        })
+                        .collect();
+                    return Err(anyhow::Error::new(signals::Interrupted(signal)).context(format!(
+                        "Download interrupted by {}, partial data left in {}",
+                        signal,
+                        partial.join(", ")
+                    )));
+                }
+            };
+            let chunk = match chunk {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(_) => {
+                    // The connection is open but dead; continue on a new one from where it stopped
+                    let stalled = http::Stalled(self.config.stall_timeout);
+                    stalls += 1;
+                    if !accepts_ranges || stalls > http::STALL_RETRIES {
+                        writer.close().await?;
+                        return Err(anyhow::Error::new(stalled).context("Download stalled"));
+                    }
+                    warn!(
+                        "{}, reconnecting from byte {} (attempt {}/{})",
+                        stalled,
+                        received,
+                        stalls,
+                        http::STALL_RETRIES
+                    );
+                    stream = self.resume_from(url, received).await?.bytes_stream();
+                    continue;
+                }
+            };
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) if accepts_ranges && retries < self.config.max_retries => {
+                    writer.flush().await?;
+                    let error = anyhow::Error::new(e).context("Failed to read chunk from response");
+                    stream = self
+                        .resume_with_backoff(url, received, &mut retries, error)
+                        .await?
+                        .bytes_stream();
+                    continue;
+                }
+                Err(e) => {
+                    writer.close().await?;
+                    return Err(e).context("Failed to read chunk from response");
+                }
+            };
+            writer.write_all(&chunk).await?;
+            hasher.update(&chunk);
+            progress.inc(chunk.len() as u64);
+            received += chunk.len() as u64;
+            stalls = 0;
+            retries = 0;
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.throttle(chunk.len() as u64).await;
+            }
+        }
+
+        // Ensure all data is written to disk, then give each file its final name
+        writer.finish().await?;
+
+        progress.finish();
+
+        info!("Download completed successfully");
+        Ok(Fetched::Downloaded(Downloaded {
+            paths: writer.paths(),
+            size: received,
+            checksums: hasher.finalize(),
+            remote,
+        }))
+    }
 
-            downloaded += chunk.len() as u64;
+    /// Resume a transfer that failed with `error` from byte `offset` on, waiting 1s, 2s, 4s, ...
+    /// (at most a minute) between attempts. `retries` counts the attempts used so far, up to `max_retries`.
+    /// Statuses that won't change on their own, such as 403 or 404, end the retries at once.
+    async fn resume_with_backoff(
+        &self,
+        url: &str,
+        offset: u64,
+        retries: &mut u32,
+        mut error: anyhow::Error,
+    ) -> Result<reqwest::Response> {
+        while *retries < self.config.max_retries {
+            *retries += 1;
+            let backoff = retry_backoff(*retries);
+            warn!(
+                "{:#}, resuming from byte {} in {}s (attempt {}/{})",
+                error,
+                offset,
+                backoff.as_secs(),
+                retries,
+                self.config.max_retries
+            );
+            tokio::time::sleep(backoff).await;
 
-            if let Some(pb) = &progress_bar {
-                pb.set_position(downloaded);
+            match self.resume_from(url, offset).await {
+                Ok(response) => return Ok(response),
+                Err(e)
+                    if e.downcast_ref::<http::BadStatus>()
+                        .is_some_and(|status| !status.is_transient()) =>
+                {
+                    return Err(e)
+                }
+                Err(e) => error = e,
             }
         }
+        Err(error)
+    }
 
-        // Ensure all data is written to disk
-        file.flush().await.context("Failed to flush file")?;
-        drop(file);
+    /// Request the rest of `url` from byte `offset` on, for continuing an interrupted transfer
+    async fn resume_from(&self, url: &str, offset: u64) -> Result<reqwest::Response> {
+        let response = http::send_with_connect_retry(
+            || {
+                self.client
+                    .get(url)
+                    .header(RANGE, format!("bytes={}-", offset))
+            },
+            "download",
+        )
+        .await
+        .context("Failed to resume download")?;
 
-        if let Some(pb) = progress_bar {
-            pb.finish_with_message("Download completed");
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(anyhow::Error::new(http::BadStatus(response.status()))
+                .context("Resuming download failed"));
+        }
+        Ok(response)
+    }
+
+    /// Make sure the target filesystem can hold the file plus the configured margin
+    fn check_free_space(&self, output_path: &Path, size: u64) -> Result<()> {
+        let directory = sink::parent_dir(output_path);
+
+        let available = match fs2::available_space(directory) {
+            Ok(available) => available,
+            Err(e) => {
+                warn!(
+                    "Could not determine free space on {}: {}",
+                    directory.display(),
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        // Bytes already sitting in a partial file don't need to be allocated again
+        let needed = size.saturating_sub(partial_bytes(output_path));
+        let required = needed.saturating_add(self.config.space_margin);
+
+        if available < required {
+            let message = format!(
+                "Not enough free space on {}: {} required ({} for the download + {} safety margin), {} available",
+                directory.display(),
+                utils::bytes_to_human_readable(required),
+                utils::bytes_to_human_readable(needed),
+                utils::bytes_to_human_readable(self.config.space_margin),
+                utils::bytes_to_human_readable(available)
+            );
+            if !self.config.force {
+                return Err(anyhow::anyhow!(message));
+            }
+            warn!("{}, downloading anyway", message);
         }
 
-        info!("Download completed successfully");
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Work out a filename for a download from its URL, falling back to the
+    /// server's Content-Disposition header when the URL doesn't end in a file name
+    pub async fn remote_filename(&self, url: &str) -> Result<Option<String>> {
+        if let Some(name) = utils::extract_filename_from_url(url) {
+            return Ok(Some(name));
+        }
+
+        let response = self
+            .client
+            .head(url)
+            .send()
+            .await
+            .context("Failed to get file information")?;
+
+        Ok(response
+            .headers()
+            .get("content-disposition")
+            .and_then(|value| value.to_str().ok())
+            .and_then(utils::filename_from_content_disposition))
+    }
+
     pub async fn get_file_size(&self, url: &str) -> Result<Option<u64>> {
         let response = self
             .client
             .head(url)
             .send()
             .await
+            .and_then(reqwest::Response::error_for_status)
             .context("Failed to get file information")?;
 
         let content_length = response
@@ -129,7 +677,6 @@ impl Downloader {
         Ok(content_length)
     }
 
-    #[allow(dead_code)]
     pub async fn verify_url(&self, url: &str) -> Result<bool> {
         let response = self
             .client
@@ -148,10 +695,149 @@ impl Default for Downloader {
     }
 }
 
+/// Bytes of `output_path` already downloaded into its partial file. A segmented download's
+/// partial file is allocated at full size up front (sparse where the file system allows), so
+/// its length says nothing; the segments' progress in the resume state does.
+fn partial_bytes(output_path: &Path) -> u64 {
+    let state_path = segmented::state_path(output_path);
+    if state_path.exists() {
+        return std::fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<segmented::PartialState>(&contents).ok())
+            .map_or(0, |state| state.downloaded());
+    }
+    std::fs::metadata(segmented::part_path(output_path))
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+/// How many bytes of `output_path`'s partial file a single-stream download can keep, 0 to
+/// start over
+async fn resumable_offset(output_path: &Path, content_length: Option<u64>) -> Result<u64> {
+    let part_path = segmented::part_path(output_path);
+    let Ok(metadata) = tokio::fs::metadata(&part_path).await else {
+        return Ok(0);
+    };
+    // A segmented download's partial file is allocated at full size and filled out of order
+    if segmented::state_path(output_path).exists() {
+        info!(
+            "{} belongs to a segmented download, starting over",
+            part_path.display()
+        );
+        return Ok(0);
+    }
+
+    let len = metadata.len();
+    match content_length {
+        Some(total) if len >= total => {
+            warn!(
+                "Partial file {} is not smaller than the remote file ({} of {} bytes), starting over",
+                part_path.display(),
+                len,
+                total
+            );
+            Ok(0)
+        }
+        // Without a size there is no telling whether the partial file belongs to this download
+        None => Ok(0),
+        Some(_) => Ok(len),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_remote_version_matches() {
+        let previous = RemoteVersion {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: Some("Tue, 01 Oct 2024 00:00:00 GMT".to_string()),
+        };
+        let mut current = previous.clone();
+        assert!(previous.matches(&current));
+
+        // A changed ETag wins over an unchanged Last-Modified
+        current.etag = Some("\"def\"".to_string());
+        assert!(!previous.matches(&current));
+
+        current.etag = None;
+        assert!(previous.matches(&current));
+        assert!(!RemoteVersion::default().matches(&RemoteVersion::default()));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(1), Duration::from_secs(1));
+        assert_eq!(retry_backoff(3), Duration::from_secs(4));
+        assert_eq!(retry_backoff(7), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(64), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(u32::MAX), MAX_RETRY_BACKOFF);
+    }
+
+    #[test]
+    fn test_segmented_modes() {
+        let mode = <DownloadMode as clap::ValueEnum>::from_str("h2-parallel", false).unwrap();
+        assert_eq!(mode, DownloadMode::H2Parallel);
+        assert!(mode.is_segmented());
+        assert!(DownloadMode::ParallelResumable.is_segmented());
+        assert!(!DownloadMode::Single.is_segmented());
+    }
+
+    #[tokio::test]
+    async fn test_resumable_offset() {
+        let output = std::env::temp_dir().join("ferro-resume-test.iso");
+        let part = segmented::part_path(&output);
+        tokio::fs::write(&part, [0u8; 10]).await.unwrap();
+
+        assert_eq!(resumable_offset(&output, Some(100)).await.unwrap(), 10);
+        assert_eq!(resumable_offset(&output, Some(10)).await.unwrap(), 0);
+        assert_eq!(resumable_offset(&output, Some(5)).await.unwrap(), 0);
+        assert_eq!(resumable_offset(&output, None).await.unwrap(), 0);
+
+        tokio::fs::remove_file(&part).await.unwrap();
+        assert_eq!(resumable_offset(&output, Some(100)).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_partial_bytes_of_segmented_download() {
+        let output = std::env::temp_dir().join("ferro-partial-bytes-test.iso");
+        let part = segmented::part_path(&output);
+        let state_path = segmented::state_path(&output);
+
+        tokio::fs::write(&part, [0u8; 10]).await.unwrap();
+        assert_eq!(partial_bytes(&output), 10);
+
+        // A preallocated segmented partial file counts only what its segments hold
+        tokio::fs::File::create(&part)
+            .await
+            .unwrap()
+            .set_len(100)
+            .await
+            .unwrap();
+        let mut state = segmented::PartialState::new("https://example.com/a.iso", 100, 2);
+        state.segments[0].downloaded = 7;
+        state.save(&state_path).await.unwrap();
+        assert_eq!(partial_bytes(&output), 7);
+
+        tokio::fs::remove_file(&part).await.unwrap();
+        tokio::fs::remove_file(&state_path).await.unwrap();
+    }
+
+    #[test]
+    fn test_free_space_check_with_force() {
+        let output = std::env::temp_dir().join("ferro-space-test.iso");
+        let downloader = |force| {
+            Downloader::with_config(DownloaderConfig {
+                space_margin: u64::MAX,
+                force,
+                ..DownloaderConfig::default()
+            })
+        };
+        assert!(downloader(false).check_free_space(&output, 1).is_err());
+        assert!(downloader(true).check_free_space(&output, 1).is_ok());
+    }
+
     #[tokio::test]
     async fn test_downloader_creation() {
         let downloader = Downloader::new();
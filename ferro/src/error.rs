@@ -0,0 +1,148 @@
+use reqwest::StatusCode;
+
+/// Exit status of the `ferro` command when Microsoft has banned the IP, so scripts can back off
+/// instead of retrying at once
+pub const IP_BANNED_EXIT_CODE: i32 = 3;
+
+/// Why an [`IsoApi`](crate::IsoApi) call failed, so callers can tell a typo in a version name
+/// from an IP ban
+#[derive(Debug, thiserror::Error)]
+pub enum FerroError {
+    #[error("Version '{0}' not found")]
+    VersionNotFound(String),
+    #[error("Release '{0}' not found")]
+    ReleaseNotFound(String),
+    #[error("Edition '{0}' not found")]
+    EditionNotFound(String),
+    #[error("Language '{0}' not found")]
+    LanguageNotFound(String),
+    /// A shortened name matches more than one catalog entry; `what` is "Version", "Release"
+    /// or "Edition"
+    #[error("{what} '{query}' is ambiguous, it matches {}. Give more of the name.", quoted(.candidates))]
+    Ambiguous {
+        what: &'static str,
+        query: String,
+        candidates: Vec<String>,
+    },
+    /// No download for the architecture; `language` is set when another language may have it
+    #[error("{}", missing_architecture(.architecture, .language.as_deref(), .available))]
+    ArchitectureNotFound {
+        architecture: String,
+        language: Option<String>,
+        /// Architectures the language does offer
+        available: Vec<String>,
+    },
+    /// Microsoft banned the IP: a request was refused with error type 9, or every SKU request
+    /// came back empty. `message` is the ban notice from Microsoft's page, or Fido's text if it
+    /// can't be loaded. The session ID is only shown with `--print-session-id`
+    /// (`show_session_id`), so a pasted error doesn't leak it.
+    #[error("{message} {}", banned_session(.session_id, *.show_session_id))]
+    IpBanned {
+        session_id: String,
+        message: String,
+        show_session_id: bool,
+    },
+    #[error("API returned empty response. Status: {status}. This might indicate that the API is blocking our requests or requires additional authentication.")]
+    EmptyApiResponse { status: StatusCode },
+    /// Microsoft answered an API request with a web page, as it does when it blocks or
+    /// redirects the request; `title` is the page's title, if it has one
+    #[error("Microsoft returned an HTML page instead of the {what} response (status {status}{}), likely rate-limited or region-blocked. Wait a while or try another network; -vv shows the page.", .title.as_ref().map(|title| format!(", \"{}\"", title)).unwrap_or_default())]
+    HtmlResponse {
+        what: &'static str,
+        status: StatusCode,
+        title: Option<String>,
+    },
+    /// Microsoft answered with an error of its own
+    #[error("API error: {0}")]
+    ApiError(String),
+    /// A request failed without a usable answer from the server
+    #[error("{context}")]
+    HttpError {
+        context: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl FerroError {
+    pub(crate) fn http(context: &str, source: reqwest::Error) -> Self {
+        FerroError::HttpError {
+            context: context.to_string(),
+            source,
+        }
+    }
+}
+
+fn quoted(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("'{}'", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Explain that a language has no download for the requested architecture.
+/// Microsoft publishes architectures per language SKU, so another language may still have it.
+fn missing_architecture(
+    architecture: &str,
+    language: Option<&str>,
+    available: &[String],
+) -> String {
+    match language {
+        None => format!("Architecture '{}' not found", architecture),
+        Some(language) if available.is_empty() => format!(
+            "Architecture '{}' not found: no downloads are offered for language '{}'. Try another language.",
+            architecture, language
+        ),
+        Some(language) => format!(
+            "Architecture '{}' is not available for language '{}' (available: {}). Try another language, other languages may offer {}.",
+            architecture,
+            language,
+            available.join(", "),
+            architecture
+        ),
+    }
+}
+
+/// The session ID that ends a ban message, or how to get it when it's hidden
+fn banned_session(session_id: &str, show: bool) -> &str {
+    if show {
+        session_id
+    } else {
+        "<hidden, rerun with --print-session-id to show it>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_architecture_not_found_lists_alternatives() {
+        let error = FerroError::ArchitectureNotFound {
+            architecture: "ARM64".to_string(),
+            language: Some("Hebrew".to_string()),
+            available: vec!["x64".to_string(), "x86".to_string()],
+        };
+        let message = error.to_string();
+        assert!(message.contains("not available for language 'Hebrew'"));
+        assert!(message.contains("available: x64, x86"));
+    }
+
+    #[test]
+    fn test_ip_banned_hides_session_id() {
+        let banned = |show_session_id| FerroError::IpBanned {
+            session_id: "6f9a3b1e-2c4d-4e5f-8a9b-0c1d2e3f4a5b".to_string(),
+            message: "Message code 715-123130, session ID".to_string(),
+            show_session_id,
+        };
+        let hidden = banned(false).to_string();
+        assert!(!hidden.contains("6f9a3b1e"));
+        assert!(hidden.contains("--print-session-id"));
+        assert!(banned(true)
+            .to_string()
+            .ends_with("6f9a3b1e-2c4d-4e5f-8a9b-0c1d2e3f4a5b"));
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::checksum;
+
+/// Error returned when the post-download command exits unsuccessfully.
+/// The binary uses `code` as its own exit status.
+#[derive(Debug, thiserror::Error)]
+#[error("Post-download command exited with status {code}")]
+pub struct HookFailed {
+    pub code: i32,
+}
+
+/// Values available for substitution in a post-download command
+pub struct HookContext<'a> {
+    pub output: &'a Path,
+    pub checksum: Option<String>,
+    pub size: u64,
+}
+
+/// Run a post-download command template such as `virt-install --cdrom {output}`.
+///
+/// Supported tokens are `{output}`, `{checksum}` (SHA-256, hex) and `{size}` (bytes).
+/// Substituted values are quoted for the platform shell, so templates must not
+/// wrap tokens in quotes themselves. The command runs through `sh -c` on Unix and
+/// `cmd /C` on Windows, inheriting stdio.
+///
+/// `checksum` is the already-known SHA-256 of the file, if any; it is only computed
+/// here when the template needs it and none was supplied. `size` is the number of bytes
+/// downloaded, which differs from the file's size when it was written compressed.
+pub async fn run_post_download_hook(
+    template: &str,
+    output: &Path,
+    checksum: Option<String>,
+    size: u64,
+) -> Result<()> {
+    // Hashing a multi-GB ISO is expensive, only do it when the template asks for it
+    let checksum = match checksum {
+        None if template.contains("{checksum}") => Some(checksum::sha256_file(output).await?),
+        checksum => checksum,
+    };
+
+    let command_line = render_template(
+        template,
+        &HookContext {
+            output,
+            checksum,
+            size,
+        },
+    );
+
+    info!("Running post-download command: {}", command_line);
+
+    let status = shell_command(&command_line)
+        .status()
+        .await
+        .context("Failed to spawn post-download command")?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        // A missing code means the command was killed by a signal
+        Err(HookFailed {
+            code: status.code().unwrap_or(1),
+        }
+        .into())
+    }
+}
+
+/// Substitute the supported tokens in a command template
+pub fn render_template(template: &str, context: &HookContext) -> String {
+    template
+        .replace("{output}", &shell_quote(&context.output.to_string_lossy()))
+        .replace(
+            "{checksum}",
+            &shell_quote(context.checksum.as_deref().unwrap_or_default()),
+        )
+        .replace("{size}", &context.size.to_string())
+}
+
+#[cfg(unix)]
+fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(command_line);
+    command
+}
+
+#[cfg(windows)]
+fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(command_line);
+    command
+}
+
+#[cfg(unix)]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_template() {
+        let context = HookContext {
+            output: Path::new("/tmp/Win11 x64.iso"),
+            checksum: Some("abc123".to_string()),
+            size: 42,
+        };
+        assert_eq!(
+            render_template("echo {output} {checksum} {size}", &context),
+            "echo '/tmp/Win11 x64.iso' 'abc123' 42"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_template_escapes_quotes() {
+        let context = HookContext {
+            output: Path::new("it's.iso"),
+            checksum: None,
+            size: 0,
+        };
+        assert_eq!(
+            render_template("ls {output}", &context),
+            r"ls 'it'\''s.iso'"
+        );
+    }
+}
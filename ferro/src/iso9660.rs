@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// ISO 9660 logical sector size
+const SECTOR_SIZE: usize = 2048;
+
+/// Volume descriptors start after the 16-sector system area
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+
+/// Descriptor type of the primary volume descriptor
+const PRIMARY_VOLUME_DESCRIPTOR: u8 = 1;
+
+/// Read the volume label (volume identifier) of an ISO image, e.g. `CCCOMA_X64FRE_EN-US_DV9`
+pub async fn volume_label(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.seek(SeekFrom::Start(
+        FIRST_DESCRIPTOR_SECTOR * SECTOR_SIZE as u64,
+    ))
+    .await?;
+    let mut sector = vec![0; SECTOR_SIZE];
+    file.read_exact(&mut sector)
+        .await
+        .with_context(|| format!("{} is too small to be an ISO image", path.display()))?;
+    parse_primary_volume_descriptor(&sector)
+        .with_context(|| format!("{} is not an ISO image", path.display()))
+}
+
+/// The volume identifier of a primary volume descriptor sector
+pub fn parse_primary_volume_descriptor(sector: &[u8]) -> Result<String> {
+    if sector.len() < 72 || &sector[1..6] != b"CD001" {
+        return Err(anyhow!("No ISO 9660 volume descriptor found"));
+    }
+    if sector[0] != PRIMARY_VOLUME_DESCRIPTOR {
+        return Err(anyhow!(
+            "Expected a primary volume descriptor, found type {}",
+            sector[0]
+        ));
+    }
+    // Bytes 40-71, padded with spaces
+    Ok(String::from_utf8_lossy(&sector[40..72])
+        .trim_end_matches([' ', '\0'])
+        .to_string())
+}
+
+/// Architecture a Windows volume label names, e.g. `x64` for `CCCOMA_X64FRE_EN-US_DV9`.
+/// `None` for labels that don't follow Microsoft's `<arch>FRE` scheme.
+pub fn label_architecture(label: &str) -> Option<&'static str> {
+    label.to_uppercase().split('_').find_map(|part| match part {
+        "X64FRE" => Some("x64"),
+        "X86FRE" => Some("x86"),
+        "A64FRE" => Some("ARM64"),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(label: &str) -> Vec<u8> {
+        let mut sector = vec![0; SECTOR_SIZE];
+        sector[0] = PRIMARY_VOLUME_DESCRIPTOR;
+        sector[1..6].copy_from_slice(b"CD001");
+        sector[6] = 1;
+        sector[40..72].fill(b' ');
+        sector[40..40 + label.len()].copy_from_slice(label.as_bytes());
+        sector
+    }
+
+    #[test]
+    fn test_parse_primary_volume_descriptor() {
+        assert_eq!(
+            parse_primary_volume_descriptor(&descriptor("CCCOMA_X64FRE_EN-US_DV9")).unwrap(),
+            "CCCOMA_X64FRE_EN-US_DV9"
+        );
+        assert!(parse_primary_volume_descriptor(&[0; SECTOR_SIZE]).is_err());
+
+        let mut supplementary = descriptor("CCCOMA_X64FRE_EN-US_DV9");
+        supplementary[0] = 2;
+        assert!(parse_primary_volume_descriptor(&supplementary).is_err());
+    }
+
+    #[test]
+    fn test_label_architecture() {
+        assert_eq!(label_architecture("CCCOMA_X64FRE_EN-US_DV9"), Some("x64"));
+        assert_eq!(label_architecture("CPBA_A64FRE_DE-DE_DV9"), Some("ARM64"));
+        assert_eq!(label_architecture("CCSA_X86FRE_EN-US_DV5"), Some("x86"));
+        assert_eq!(label_architecture("UEFI_SHELL"), None);
+    }
+}
@@ -1,74 +1,503 @@
 use anyhow::{anyhow, Context, Result};
-use log::{debug, warn};
+use futures_util::{StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use rand::Rng;
 use reqwest::Client;
 use reqwest_cookie_store::CookieStoreMutex;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use uuid::Uuid;
 
+use crate::catalog;
+use crate::checksum;
+use crate::error::FerroError;
+use crate::http::{HttpOptions, POWERSHELL_USER_AGENT};
+use crate::redact::redact;
+use crate::session_cache::SessionCache;
+use crate::timings::Timings;
 use crate::types::*;
 use crate::utils;
 
+/// UEFI Shell build flavour, which the catalog models as the release's edition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UefiVariant {
+    Release,
+    Debug,
+}
+
+impl UefiVariant {
+    /// Name of the matching edition in the catalog
+    pub fn edition_name(self) -> &'static str {
+        match self {
+            UefiVariant::Release => "Release",
+            UefiVariant::Debug => "Debug",
+        }
+    }
+
+    pub fn from_edition_name(edition_name: &str) -> Self {
+        if edition_name.to_lowercase().contains("release") {
+            UefiVariant::Release
+        } else {
+            UefiVariant::Debug
+        }
+    }
+
+    fn iso_suffix(self) -> &'static str {
+        match self {
+            UefiVariant::Release => "RELEASE",
+            UefiVariant::Debug => "DEBUG",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiConfig {
+    pub http: HttpOptions,
+    /// User-Agent for API requests, in place of `http`'s; downloads are made by the
+    /// [`Downloader`](crate::Downloader) with its own
+    pub user_agent: Option<String>,
+    pub timings: Timings,
+    /// Print every generated session ID to stderr, for correlating ban reports
+    pub print_session_ids: bool,
+    /// Versions, releases and editions to offer, normally the built-in catalog plus any overlay
+    pub catalog: Vec<WindowsVersionData>,
+    /// Referer sent with download-links requests instead of the version's download page
+    pub referer: Option<String>,
+    /// Visit the version's download page before the first SKU request
+    pub warm_session: bool,
+    /// API profile IDs; SKU requests start with the first and move to the next on each retry
+    pub profiles: Vec<String>,
+    /// Directory to save the raw API responses to (`api_response.json` and
+    /// `download_links_response.json`), for debugging; `None` saves nothing
+    pub dump_dir: Option<PathBuf>,
+    /// File to reuse Microsoft sessions from and save them to, see [`SessionCache`];
+    /// `None` starts new sessions every time
+    pub session_cache: Option<PathBuf>,
+    /// Pause before each SKU and download-links request
+    pub request_delay: RequestDelay,
+    /// Time limit for each API request
+    pub timeout: Duration,
+    /// Locale for API requests, in place of the system locale Microsoft's pages are checked
+    /// for; `None` checks the system locale and falls back to en-US
+    pub query_locale: Option<String>,
+    /// Server to send every Microsoft request to instead of www.microsoft.com and
+    /// vlscppe.microsoft.com, such as a mock of the API in tests
+    pub base_url: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            http: HttpOptions::default(),
+            user_agent: None,
+            timings: Timings::default(),
+            print_session_ids: false,
+            catalog: get_windows_versions(),
+            referer: None,
+            warm_session: false,
+            profiles: vec![DEFAULT_PROFILE_ID.to_string()],
+            dump_dir: None,
+            session_cache: None,
+            request_delay: RequestDelay::default(),
+            timeout: DEFAULT_API_TIMEOUT,
+            query_locale: None,
+            base_url: None,
+        }
+    }
+}
+
+/// Random pause between `min` and `max` before each SKU and download-links request. Fido makes
+/// no pauses, which is the default; longer ones make a burst of requests look less like a bot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestDelay {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl RequestDelay {
+    /// Pick the next pause; `max` below `min` counts as `min`
+    pub fn sample(&self) -> Duration {
+        if self.max <= self.min {
+            return self.min;
+        }
+        rand::thread_rng().gen_range(self.min..=self.max)
+    }
+
+    async fn wait(&self) {
+        let delay = self.sample();
+        if !delay.is_zero() {
+            debug!("Waiting {}ms before the next request", delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// The `profile` parameter Fido sends with every SKU and download-links request
+pub const DEFAULT_PROFILE_ID: &str = "606624d44113";
+
+/// Time limit for each API request, the DefaultTimeout Fido uses
+pub const DEFAULT_API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serves the download pages and the SKU and download-links API
+const MICROSOFT_URL: &str = "https://www.microsoft.com";
+
+/// Whitelists each session ID before its first SKU request
+const WHITELIST_URL: &str = "https://vlscppe.microsoft.com";
+
+/// Environment variable that turns on saving the raw API responses: `1` for a `ferro`
+/// directory under the system's temp directory, or the directory to use
+pub const DUMP_ENV_VAR: &str = "FERRO_DUMP_RESPONSES";
+
+/// Directory `FERRO_DUMP_RESPONSES=value` asks for
+pub fn dump_dir(value: &str) -> PathBuf {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" => std::env::temp_dir().join("ferro"),
+        _ => PathBuf::from(value),
+    }
+}
+
+/// Languages whose download links `get_architecture_matrix` fetches at the same time. Kept
+/// low, since every language is another API call against Microsoft's rate limiting.
+pub const MATRIX_CONCURRENCY: usize = 2;
+
+/// Session indexes of an edition whose SKU information, or a language's download links,
+/// are requested at the same time
+pub const SESSION_CONCURRENCY: usize = 2;
+
+/// A Windows Server evaluation ISO as `(version, language, display name, link)`
+type EvaluationIso = (&'static str, &'static str, &'static str, &'static str);
+
+/// Windows Server evaluation ISOs. The Evaluation Center has no download API; each ISO is a
+/// fixed `go.microsoft.com` link that redirects to the current build.
+const EVALUATION_ISOS: &[EvaluationIso] = &[
+    (
+        "Windows Server 2025",
+        "English",
+        "English (United States)",
+        "https://go.microsoft.com/fwlink/?linkid=2293312&clcid=0x409&culture=en-us&country=us",
+    ),
+    (
+        "Windows Server 2022",
+        "English",
+        "English (United States)",
+        "https://go.microsoft.com/fwlink/p/?LinkID=2195280&clcid=0x409&culture=en-us&country=US",
+    ),
+];
+
+/// API responses this client already fetched, so resolving again within one run doesn't
+/// repeat the calls. Cleared whenever a request fails, since its session may be gone.
+#[derive(Debug, Default)]
+struct ResponseCache {
+    /// SKU information by product edition ID and locale
+    skus: HashMap<(u32, String), CachedSkus>,
+    /// Download links by SKU ID
+    links: HashMap<String, MicrosoftApiResponse>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedSkus {
+    response: MicrosoftApiResponse,
+    /// Download links have to be requested with the session and profile that listed the SKUs
+    session_id: String,
+    profile_id: String,
+}
+
 pub struct IsoApi {
     client: Client,
+    cookie_store: Arc<CookieStoreMutex>,
+    http: HttpOptions,
     session_data: SessionData,
     session_ids: HashMap<usize, String>, // Store session IDs by index for reuse like Fido
     query_locale: String, // $QueryLocale like Fido - can be different from system locale
+    /// `query_locale` was given rather than checked, so failures may be down to it
+    locale_forced: bool,
+    microsoft_url: String,
+    whitelist_url: String,
+    timings: Timings,
+    print_session_ids: bool,
+    published_hashes: HashMap<String, String>, // SHA-256 by lowercase file name, when Microsoft's download page or GitHub lists them
+    /// SHA-256 Microsoft publishes for the ISO of SKUs with a single one, by SKU ID
+    sku_hashes: HashMap<String, String>,
+    /// Hashes each download page publishes, by page type, loaded once per client
+    page_hashes: HashMap<String, Vec<catalog::PublishedHash>>,
+    catalog: Vec<WindowsVersionData>,
+    referer: Option<String>,
+    warm_session: bool,
+    session_warmed: bool,
+    profiles: Vec<String>,
+    dump_dir: Option<PathBuf>,
+    versions_refreshed: bool,
+    session_cache: Option<PathBuf>,
+    /// Whitelisted session IDs loaded from the session cache, not used yet
+    cached_sessions: HashMap<usize, String>,
+    /// When the sessions in `session_ids` were first whitelisted
+    sessions_created: chrono::DateTime<chrono::Utc>,
+    request_delay: RequestDelay,
+    timeout: Duration,
+    responses: Mutex<ResponseCache>,
 }
 
 impl IsoApi {
-    pub async fn new() -> Result<Self> {
-        // Create simple client like PowerShell's Invoke-RestMethod with -UseBasicParsing
-        let cookie_store = Arc::new(CookieStoreMutex::default());
+    pub async fn new() -> Result<Self, FerroError> {
+        Self::with_config(ApiConfig::default()).await
+    }
+
+    /// A client with the default configuration that sends every request to `base_url`,
+    /// see [`ApiConfig::base_url`]
+    pub async fn with_base_url(base_url: &str) -> Result<Self, FerroError> {
+        Self::with_config(ApiConfig {
+            base_url: Some(base_url.to_string()),
+            ..ApiConfig::default()
+        })
+        .await
+    }
 
-        // Use the exact PowerShell User-Agent format that Fido uses
-        // PowerShell 5.1 format: Mozilla/5.0 (Windows NT; Windows NT 10.0; en-US) WindowsPowerShell/5.1.19041.4170
-        let powershell_user_agent = "Mozilla/5.0 (Windows NT; Windows NT 10.0; en-US) WindowsPowerShell/5.1.19041.4170";
-        let client = Client::builder()
-            .user_agent(powershell_user_agent)
-            .redirect(reqwest::redirect::Policy::none()) // MaximumRedirection 0 like Fido
-            .timeout(Duration::from_secs(30)) // DefaultTimeout like Fido
-            .cookie_provider(cookie_store.clone())
-            // gzip decompression is enabled by default in reqwest
-            .build()?;
+    pub async fn with_config(config: ApiConfig) -> Result<Self, FerroError> {
+        let now = chrono::Utc::now();
+        let cached = config
+            .session_cache
+            .as_deref()
+            .and_then(|path| SessionCache::load_fresh(path, now));
+        let cookie_store = Arc::new(match &cached {
+            Some(cache) => CookieStoreMutex::new(cache.cookie_store()),
+            None => CookieStoreMutex::default(),
+        });
+        let http = HttpOptions {
+            user_agent: config.user_agent.or(config.http.user_agent),
+            ..config.http
+        };
+        let client = build_client(&http, config.timeout, cookie_store.clone())?;
 
         let mut api = IsoApi {
             client,
+            cookie_store,
+            http,
             session_data: SessionData {
                 session_id: Uuid::new_v4().to_string(),
                 org_id: "y6jn8c31".to_string(),
-                profile_id: "606624d44113".to_string(), // Matches Fido exactly
+                // Download-links requests use the profile whose SKU request succeeded
+                profile_id: config
+                    .profiles
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| DEFAULT_PROFILE_ID.to_string()),
             },
             session_ids: HashMap::new(),
-            query_locale: "en-US".to_string(), // Default, will be validated
+            query_locale: config
+                .query_locale
+                .clone()
+                .unwrap_or_else(|| "en-US".to_string()), // Default, will be validated
+            locale_forced: config.query_locale.is_some(),
+            microsoft_url: base_url(&config.base_url, MICROSOFT_URL),
+            whitelist_url: base_url(&config.base_url, WHITELIST_URL),
+            timings: config.timings,
+            print_session_ids: config.print_session_ids,
+            published_hashes: HashMap::new(),
+            sku_hashes: HashMap::new(),
+            page_hashes: HashMap::new(),
+            catalog: config.catalog,
+            referer: config.referer,
+            warm_session: config.warm_session,
+            session_warmed: false,
+            profiles: config.profiles,
+            dump_dir: config.dump_dir,
+            versions_refreshed: false,
+            session_cache: config.session_cache,
+            sessions_created: cached.as_ref().map_or(now, |cache| cache.created),
+            cached_sessions: cached.map(|cache| cache.session_ids).unwrap_or_default(),
+            request_delay: config.request_delay,
+            timeout: config.timeout,
+            responses: Mutex::default(),
         };
 
         // Check and set proper locale like Fido's Check-Locale function
-        api.check_and_set_locale().await?;
+        if api.locale_forced {
+            debug!("Using locale {} without checking it", api.query_locale);
+        } else {
+            api.check_and_set_locale().await?;
+        }
 
         Ok(api)
     }
 
-    pub async fn get_available_versions(&self) -> Result<Vec<WindowsVersion>> {
-        let versions = get_windows_versions();
-        Ok(versions
-            .into_iter()
+    /// Microsoft's download page for a version's `page_type`, e.g. `.../software-download/windows11`
+    fn download_page_url(&self, page_type: &str) -> String {
+        format!("{}/software-download/{}", self.microsoft_url, page_type)
+    }
+
+    /// The versions, releases and editions this client offers
+    pub fn catalog(&self) -> &[WindowsVersionData] {
+        &self.catalog
+    }
+
+    /// Add the product editions Microsoft's download pages currently offer but the catalog
+    /// doesn't know yet, such as a feature update published after this build of Ferro, to
+    /// each version's newest release. Returns how many editions were added or extended.
+    ///
+    /// A version whose page can't be loaded, or has no edition drop-down to parse, keeps
+    /// its catalog entries, so this never fails. The pages are only fetched once per client.
+    pub async fn refresh_versions(&mut self) -> usize {
+        if self.versions_refreshed {
+            return 0;
+        }
+
+        let mut overlay = Vec::new();
+        for version in &self.catalog {
+            if utils::is_uefi_shell(&version.name) || utils::is_evaluation_center(&version.name) {
+                continue;
+            }
+            let url = self.download_page_url(&version.page_type);
+            let offered = match self.fetch_page(&url).await {
+                Ok(html) => catalog::parse_product_editions(&html),
+                Err(e) => {
+                    warn!(
+                        "Could not load {}, keeping the built-in {} editions: {:#}",
+                        url, version.name, e
+                    );
+                    continue;
+                }
+            };
+            if offered.is_empty() {
+                warn!(
+                    "No product editions found on {}, keeping the built-in {} editions",
+                    url, version.name
+                );
+                continue;
+            }
+            debug!("{} offers {:?}", url, offered);
+            overlay.extend(catalog::new_editions_overlay(version, &offered));
+        }
+
+        let added = overlay
+            .iter()
+            .flat_map(|version| &version.releases)
+            .map(|release| release.editions.len())
+            .sum();
+        for version in &overlay {
+            for edition in version.releases.iter().flat_map(|r| &r.editions) {
+                info!(
+                    "Found {} {:?} on Microsoft's download page",
+                    edition.name, edition.ids
+                );
+            }
+        }
+        // Every entry names a version already in the catalog, which can't fail
+        if let Err(e) = catalog::apply_overlay(&mut self.catalog, overlay) {
+            warn!("Could not merge the refreshed editions: {:#}", e);
+        }
+        self.versions_refreshed = true;
+        added
+    }
+
+    async fn fetch_page(&self, url: &str) -> Result<String> {
+        let response = self
+            .client
+            .get(url)
+            .header("Sec-Fetch-Dest", "document")
+            .header("Sec-Fetch-Mode", "navigate")
+            .send()
+            .await
+            .context("Failed to load page")?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Page request failed with status: {}",
+                response.status()
+            ));
+        }
+        response.text().await.context("Failed to read page")
+    }
+
+    pub async fn get_available_versions(&self) -> Result<Vec<WindowsVersion>, FerroError> {
+        Ok(self
+            .catalog
+            .iter()
             .enumerate()
             .map(|(index, version_data)| WindowsVersion {
-                name: version_data.name,
-                page_type: version_data.page_type,
+                name: version_data.name.clone(),
+                page_type: version_data.page_type.clone(),
                 index,
             })
             .collect())
     }
 
-    pub async fn get_releases(&self, version_name: &str) -> Result<Vec<WindowsRelease>> {
-        let versions = get_windows_versions();
-        let version_data = versions
-            .iter()
-            .find(|v| v.name.to_lowercase().contains(&version_name.to_lowercase()))
-            .ok_or_else(|| anyhow!("Version '{}' not found", version_name))?;
+    /// The catalog version `version_name` names or abbreviates
+    fn version_data(&self, version_name: &str) -> Result<&WindowsVersionData, FerroError> {
+        find_named(
+            &self.catalog,
+            version_name,
+            |v| &v.name,
+            "Version",
+            FerroError::VersionNotFound,
+        )
+    }
+
+    /// Full catalog name of the version `version_name` names or abbreviates, e.g.
+    /// "Windows 11" for "11"
+    pub fn version_name(&self, version_name: &str) -> Result<String, FerroError> {
+        Ok(self.version_data(version_name)?.name.clone())
+    }
+
+    /// Full catalog name of the release `release_name` names or abbreviates
+    pub fn release_name(
+        &self,
+        version_name: &str,
+        release_name: &str,
+    ) -> Result<String, FerroError> {
+        let version_data = self.version_data(version_name)?;
+        Ok(release_data(version_data, release_name)?.name.clone())
+    }
+
+    /// Add an edition requesting exactly the product edition ID `id` to a release, so its
+    /// languages can be looked up even when the catalog doesn't list the ID. Returns the
+    /// edition's name for the other calls.
+    pub fn add_edition_id(
+        &mut self,
+        version_name: &str,
+        release_name: &str,
+        id: u32,
+    ) -> Result<String, FerroError> {
+        if utils::is_uefi_shell(version_name) || utils::is_evaluation_center(version_name) {
+            return Err(anyhow!(
+                "{} isn't downloaded through the API, it has no product edition IDs",
+                version_name
+            )
+            .into());
+        }
+        let version_name = self.version_name(version_name)?;
+        let release_name = self.release_name(&version_name, release_name)?;
+        let overlay = catalog::edition_id_overlay(&version_name, &release_name, id);
+        // The version and release were just found in the catalog, so this can't fail
+        catalog::apply_overlay(&mut self.catalog, vec![overlay])?;
+        Ok(catalog::edition_id_name(id))
+    }
+
+    /// Full catalog name of the edition `edition_name` names or abbreviates
+    pub fn edition_name(
+        &self,
+        version_name: &str,
+        release_name: &str,
+        edition_name: &str,
+    ) -> Result<String, FerroError> {
+        let version_data = self.version_data(version_name)?;
+        let release_data = release_data(version_data, release_name)?;
+        let edition = find_named(
+            &release_data.editions,
+            edition_name,
+            |e| &e.name,
+            "Edition",
+            FerroError::EditionNotFound,
+        )?;
+        Ok(edition.name.clone())
+    }
+
+    pub async fn get_releases(
+        &self,
+        version_name: &str,
+    ) -> Result<Vec<WindowsRelease>, FerroError> {
+        let version_data = self.version_data(version_name)?;
 
         Ok(version_data
             .releases
@@ -81,10 +510,15 @@ impl IsoApi {
             .collect())
     }
 
+    /// The version's `page_type`, falling back to the Windows 11 page
+    fn page_type(&self, version_name: &str) -> &str {
+        self.version_data(version_name)
+            .map_or("windows11", |v| v.page_type.as_str())
+    }
+
     // Simulate visiting the main download page like a browser would
-    #[allow(dead_code)]
     async fn simulate_page_visit(&self, url: &str) -> Result<()> {
-        debug!("Simulating page visit to: {}", url);
+        debug!("Simulating page visit to: {}", redact(url));
 
         let _response = self
             .client
@@ -105,18 +539,10 @@ impl IsoApi {
         &self,
         version_name: &str,
         release_name: &str,
-    ) -> Result<Vec<WindowsEdition>> {
-        let versions = get_windows_versions();
-        let version_data = versions
-            .iter()
-            .find(|v| v.name.to_lowercase().contains(&version_name.to_lowercase()))
-            .ok_or_else(|| anyhow!("Version '{}' not found", version_name))?;
+    ) -> Result<Vec<WindowsEdition>, FerroError> {
+        let version_data = self.version_data(version_name)?;
 
-        let release_data = version_data
-            .releases
-            .iter()
-            .find(|r| r.name.to_lowercase().contains(&release_name.to_lowercase()))
-            .ok_or_else(|| anyhow!("Release '{}' not found", release_name))?;
+        let release_data = release_data(version_data, release_name)?;
 
         Ok(release_data
             .editions
@@ -128,14 +554,47 @@ impl IsoApi {
             .collect())
     }
 
+    /// The edition to use when none is given: the release's `default_edition` from the
+    /// catalog, or its first edition
+    pub fn default_edition(
+        &self,
+        version_name: &str,
+        release_name: &str,
+    ) -> Result<String, FerroError> {
+        let version_data = self.version_data(version_name)?;
+
+        let release_data = release_data(version_data, release_name)?;
+
+        release_data
+            .default_edition
+            .clone()
+            .or_else(|| release_data.editions.first().map(|e| e.name.clone()))
+            .ok_or_else(|| anyhow!("No editions found").into())
+    }
+
     pub async fn get_languages(
         &mut self,
         version_name: &str,
         release_name: &str,
         edition_name: &str,
-    ) -> Result<Vec<WindowsLanguage>> {
+    ) -> Result<Vec<WindowsLanguage>, FerroError> {
+        let result = self
+            .fetch_languages(version_name, release_name, edition_name)
+            .await;
+        if result.is_err() && self.locale_forced {
+            self.warn_if_locale_unsupported().await;
+        }
+        result
+    }
+
+    async fn fetch_languages(
+        &mut self,
+        version_name: &str,
+        release_name: &str,
+        edition_name: &str,
+    ) -> Result<Vec<WindowsLanguage>, FerroError> {
         // Check if this is a UEFI Shell version
-        if version_name.to_lowercase().contains("uefi") {
+        if utils::is_uefi_shell(version_name) {
             return Ok(vec![WindowsLanguage {
                 name: "en-us".to_string(),
                 display_name: "English (US)".to_string(),
@@ -146,33 +605,121 @@ impl IsoApi {
             }]);
         }
 
+        if utils::is_evaluation_center(version_name) {
+            return Ok(self
+                .evaluation_isos(version_name)?
+                .map(|(_, name, display_name, _)| WindowsLanguage {
+                    name: name.to_string(),
+                    display_name: display_name.to_string(),
+                    data: Vec::new(),
+                })
+                .collect());
+        }
+
         let editions = self.get_editions(version_name, release_name).await?;
-        let edition = editions
-            .iter()
-            .find(|e| e.name.to_lowercase().contains(&edition_name.to_lowercase()))
-            .ok_or_else(|| anyhow!("Edition '{}' not found", edition_name))?;
+        let edition = find_named(
+            &editions,
+            edition_name,
+            |e| &e.name,
+            "Edition",
+            FerroError::EditionNotFound,
+        )?;
+
+        // Load the product page first, like a browser, so the cookie store is populated
+        // before the SKU calls. Once per session is enough.
+        if self.warm_session && !self.session_warmed {
+            let page = self.download_page_url(self.page_type(version_name));
+            match self.simulate_page_visit(&page).await {
+                Ok(()) => self.session_warmed = true,
+                Err(e) => warn!("Could not warm the session: {:#}", e),
+            }
+        }
 
-        let mut languages = HashMap::new();
+        if self.cookie_store.lock().is_err() {
+            self.reset_cookies("the cookie store was poisoned")?;
+        }
 
+        // Sessions are handed out in order, so each session index keeps the same session
+        let mut responses = Vec::new();
+        let mut pending = Vec::new();
         for (session_index, &edition_id) in edition.id.iter().enumerate() {
-            let session_id = Uuid::new_v4().to_string();
-
-            // Store the session ID for later reuse (like Fido does)
-            self.session_ids.insert(session_index, session_id.clone());
-
-            // Whitelist session ID like Fido does
-            self.whitelist_session(&session_id).await?;
-
-            // Fido doesn't use artificial delays - remove this to match original behavior
-            // Microsoft might detect artificial delays as bot behavior
+            let key = (edition_id, self.query_locale.clone());
+            let hit = self.responses.lock().unwrap().skus.get(&key).cloned();
+            match hit {
+                Some(hit) => {
+                    debug!("Reusing the SKU information of edition {}", edition_id);
+                    self.session_ids.insert(session_index, hit.session_id);
+                    self.session_data.profile_id = hit.profile_id;
+                    responses.push((session_index, hit.response));
+                }
+                None => {
+                    let (session_id, whitelisted) = self.assign_session(session_index, edition_id);
+                    pending.push((session_index, edition_id, session_id, whitelisted));
+                }
+            }
+        }
 
-            // Get SKU information using exact Fido approach
-            let languages_response = self
-                .try_get_sku_information(edition_id, &session_id, 0)
-                .await?;
+        // The first SKU request of up to SESSION_CONCURRENCY sessions goes out at a time
+        let this = &*self;
+        let first_attempts: Vec<_> = futures_util::stream::iter(&pending)
+            .map(|(_, edition_id, session_id, whitelisted)| async move {
+                if !whitelisted {
+                    this.whitelist_session(session_id).await?;
+                }
+                // None by default, like Fido
+                this.request_delay.wait().await;
+                Ok::<_, FerroError>(
+                    this.try_get_sku_information(*edition_id, session_id, 0)
+                        .await,
+                )
+            })
+            .buffered(SESSION_CONCURRENCY)
+            .collect()
+            .await;
+
+        // Failed requests are retried one edition at a time, since a retry resets the cookies
+        for ((session_index, edition_id, session_id, _), first_attempt) in
+            pending.into_iter().zip(first_attempts)
+        {
+            let result = match first_attempt {
+                Ok(first_attempt) => {
+                    self.fetch_skus(session_index, edition_id, session_id, first_attempt)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(response) => responses.push((session_index, response)),
+                Err(e) => {
+                    self.forget_responses();
+                    return Err(e);
+                }
+            }
+        }
+        // Merged in session order, so languages and their SKUs come out the same every run
+        responses.sort_by_key(|(session_index, _)| *session_index);
 
+        let page_hashes = self.load_page_hashes(version_name).await;
+        let mut languages = HashMap::new();
+        for (session_index, languages_response) in responses {
             if let Some(skus) = languages_response.skus {
                 for sku in skus {
+                    // Hashes in the SKU information win; the download page fills in the rest
+                    let mut hashes = sku.published_hashes();
+                    for (file_name, hash) in sku_published_hashes(&sku, &edition.id, &page_hashes) {
+                        if !hashes.iter().any(|(name, _)| *name == file_name) {
+                            hashes.push((file_name, hash));
+                        }
+                    }
+                    if let ([_], [(_, hash)]) = (
+                        sku.friendly_file_names.as_deref().unwrap_or_default(),
+                        hashes.as_slice(),
+                    ) {
+                        self.sku_hashes.insert(sku.id.clone(), hash.clone());
+                    }
+                    for (file_name, hash) in hashes {
+                        self.published_hashes.insert(file_name.to_lowercase(), hash);
+                    }
                     languages
                         .entry(sku.language.clone())
                         .or_insert_with(|| WindowsLanguage {
@@ -189,10 +736,83 @@ impl IsoApi {
             }
         }
 
-        // Store session IDs in a way that can be accessed later
-        // For now, we'll need to modify the approach to pass session IDs through
+        self.save_session_cache();
+
+        // Sorted like `list languages` shows them, so positions are stable for --language-index
+        let mut languages: Vec<WindowsLanguage> = languages.into_values().collect();
+        languages.sort_by_key(|language| language.display_name.to_lowercase());
+        Ok(languages)
+    }
+
+    /// SHA-256 hashes the version's download page publishes. A page that can't be loaded
+    /// publishes none; either way the page is only fetched once per client.
+    async fn load_page_hashes(&mut self, version_name: &str) -> Vec<catalog::PublishedHash> {
+        let page_type = self.page_type(version_name).to_string();
+        if let Some(hashes) = self.page_hashes.get(&page_type) {
+            return hashes.clone();
+        }
 
-        Ok(languages.into_values().collect())
+        let url = self.download_page_url(&page_type);
+        let hashes = match self.fetch_page(&url).await {
+            Ok(html) => catalog::parse_published_hashes(&html),
+            Err(e) => {
+                warn!("Could not load the published hashes from {}: {:#}", url, e);
+                Vec::new()
+            }
+        };
+        debug!("{} publishes {} SHA-256 hashes", url, hashes.len());
+        self.page_hashes.insert(page_type, hashes.clone());
+        hashes
+    }
+
+    /// Pick the session for `session_index`: one from the session cache, which is already
+    /// whitelisted, or a new one that still has to be. Returns the session ID and whether
+    /// it is whitelisted.
+    fn assign_session(&mut self, session_index: usize, edition_id: u32) -> (String, bool) {
+        match self.cached_sessions.remove(&session_index) {
+            Some(session_id) => {
+                self.record_session(session_index, edition_id, &session_id, true);
+                debug!("Reusing cached session {}", session_index);
+                (session_id, true)
+            }
+            None => {
+                let session_id = Uuid::new_v4().to_string();
+                self.record_session(session_index, edition_id, &session_id, false);
+                (session_id, false)
+            }
+        }
+    }
+
+    /// Finish the SKU request of `edition_id` made with `session_id`, retrying it if the
+    /// first attempt failed, and remember the response for this run
+    async fn fetch_skus(
+        &mut self,
+        session_index: usize,
+        edition_id: u32,
+        session_id: String,
+        first_attempt: Result<MicrosoftApiResponse, FerroError>,
+    ) -> Result<MicrosoftApiResponse, FerroError> {
+        let (languages_response, profile_id, session_id) = self
+            .get_sku_information_with_retry(edition_id, session_index, session_id, first_attempt)
+            .await?;
+        self.session_data.profile_id = profile_id.clone();
+
+        self.responses.lock().unwrap().skus.insert(
+            (edition_id, self.query_locale.clone()),
+            CachedSkus {
+                response: languages_response.clone(),
+                session_id,
+                profile_id,
+            },
+        );
+        Ok(languages_response)
+    }
+
+    /// Drop every cached response, after a failed request or when the cookies are reset
+    fn forget_responses(&self) {
+        let mut responses = self.responses.lock().unwrap();
+        responses.skus.clear();
+        responses.links.clear();
     }
 
     pub async fn get_architectures(
@@ -201,13 +821,16 @@ impl IsoApi {
         release_name: &str,
         edition_name: &str,
         language_name: &str,
-    ) -> Result<Vec<WindowsArchitecture>> {
+    ) -> Result<Vec<WindowsArchitecture>, FerroError> {
         // Check if this is a UEFI Shell version
-        if version_name.to_lowercase().contains("uefi") {
+        if utils::is_uefi_shell(version_name) {
             return self
                 .get_uefi_shell_architectures(version_name, release_name, edition_name)
                 .await;
         }
+        if utils::is_evaluation_center(version_name) {
+            return self.evaluation_architectures(version_name, language_name);
+        }
 
         let languages = self
             .get_languages(version_name, release_name, edition_name)
@@ -222,32 +845,152 @@ impl IsoApi {
                         .to_lowercase()
                         .contains(&language_name.to_lowercase())
             })
-            .ok_or_else(|| anyhow!("Language '{}' not found", language_name))?;
+            .ok_or_else(|| FerroError::LanguageNotFound(language_name.to_string()))?;
 
-        let mut architectures = vec![];
+        self.language_architectures(version_name, language).await
+    }
 
-        for language_data in &language.data {
-            // Reuse the session ID from the SKU information call (like Fido does with $SessionId[$Entry.SessionIndex])
-            // Don't create a new session or whitelist again - reuse existing session
-
-            // Fido doesn't use artificial delays between requests
-            // Remove delays to match original behavior exactly
-
-            // Get the stored session ID for this session index
-            let session_id = self
-                .session_ids
-                .get(&language_data.session_index)
-                .ok_or_else(|| {
-                    anyhow!(
-                        "Session ID not found for index {}",
-                        language_data.session_index
-                    )
-                })?;
-
-            let download_links = self
-                .get_download_links(&language_data.sku_id, session_id)
+    /// Every architecture of every language of an edition, for mirroring a whole edition.
+    ///
+    /// The SKU calls are made once, then the download links of up to
+    /// [`MATRIX_CONCURRENCY`] languages are fetched at a time.
+    pub async fn get_architecture_matrix(
+        &mut self,
+        version_name: &str,
+        release_name: &str,
+        edition_name: &str,
+    ) -> Result<Vec<MatrixEntry>, FerroError> {
+        let languages = self
+            .get_languages(version_name, release_name, edition_name)
+            .await?;
+
+        if utils::is_uefi_shell(version_name) {
+            let architectures = self
+                .get_uefi_shell_architectures(version_name, release_name, edition_name)
                 .await?;
+            return Ok(matrix_entries(&languages[0], architectures));
+        }
+        if utils::is_evaluation_center(version_name) {
+            let mut matrix = Vec::new();
+            for language in &languages {
+                let architectures = self.evaluation_architectures(version_name, &language.name)?;
+                matrix.extend(matrix_entries(language, architectures));
+            }
+            return Ok(matrix);
+        }
+
+        let link_calls: usize = languages.iter().map(|l| l.data.len()).sum();
+        warn!(
+            "Fetching download links for {} languages takes {} more API calls; Microsoft may ban IPs that make too many",
+            languages.len(),
+            link_calls
+        );
+
+        let this = &*self;
+        let mut matrix: Vec<MatrixEntry> = futures_util::stream::iter(&languages)
+            .map(|language| async move {
+                let architectures = this
+                    .language_architectures(version_name, language)
+                    .await
+                    .map_err(|e| match e {
+                        // Keep a ban matchable, it isn't specific to the language
+                        FerroError::IpBanned { .. } => e,
+                        e => anyhow::Error::new(e)
+                            .context(format!(
+                                "Failed to list architectures for {}",
+                                language.name
+                            ))
+                            .into(),
+                    })?;
+                Ok::<_, FerroError>(matrix_entries(language, architectures))
+            })
+            .buffer_unordered(MATRIX_CONCURRENCY)
+            .try_concat()
+            .await?;
+        matrix.sort_by(|a, b| {
+            (a.display_name.to_lowercase(), &a.architecture)
+                .cmp(&(b.display_name.to_lowercase(), &b.architecture))
+        });
+        Ok(matrix)
+    }
 
+    /// Request the download links of every language of an edition without downloading
+    /// anything, and report which languages currently work, which are banned and which
+    /// have no downloads.
+    ///
+    /// Requests go out [`MATRIX_CONCURRENCY`] languages at a time, like
+    /// [`IsoApi::get_architecture_matrix`]. `on_probed` is called with the number of languages
+    /// probed so far, the total and the entry as each language finishes.
+    pub async fn probe(
+        &mut self,
+        version_name: &str,
+        release_name: &str,
+        edition_name: &str,
+        on_probed: impl Fn(usize, usize, &ProbeEntry) + Sync,
+    ) -> Result<Vec<ProbeEntry>, FerroError> {
+        if utils::is_uefi_shell(version_name) {
+            return Err(anyhow!(
+                "The UEFI Shell is downloaded from GitHub, there is no download API to probe"
+            )
+            .into());
+        }
+        if utils::is_evaluation_center(version_name) {
+            return Err(anyhow!(
+                "Windows Server evaluations are fixed Evaluation Center links, there is no download API to probe"
+            )
+            .into());
+        }
+
+        let languages = self
+            .get_languages(version_name, release_name, edition_name)
+            .await?;
+        let link_calls: usize = languages.iter().map(|l| l.data.len()).sum();
+        warn!(
+            "Probing {} languages takes {} download-links API calls; Microsoft may ban IPs that make too many",
+            languages.len(),
+            link_calls
+        );
+
+        let this = &*self;
+        let probed = AtomicUsize::new(0);
+        let probed = &probed;
+        let on_probed = &on_probed;
+        let total = languages.len();
+        let mut entries: Vec<ProbeEntry> = futures_util::stream::iter(&languages)
+            .map(|language| async move {
+                let result = this.language_architectures(version_name, language).await;
+                let entry = probe_entry(language, result);
+                on_probed(probed.fetch_add(1, Ordering::Relaxed) + 1, total, &entry);
+                entry
+            })
+            .buffer_unordered(MATRIX_CONCURRENCY)
+            .collect()
+            .await;
+        entries.sort_by_key(|entry| entry.display_name.to_lowercase());
+        Ok(entries)
+    }
+
+    /// Download links of every architecture `language` is published in, using the sessions
+    /// of the preceding [`IsoApi::get_languages`] call
+    async fn language_architectures(
+        &self,
+        version_name: &str,
+        language: &WindowsLanguage,
+    ) -> Result<Vec<WindowsArchitecture>, FerroError> {
+        let referer = self
+            .referer
+            .clone()
+            .unwrap_or_else(|| self.download_page_url(self.page_type(version_name)));
+
+        // Up to SESSION_CONCURRENCY at a time, kept in the order of the language's SKUs
+        let responses: Vec<MicrosoftApiResponse> = futures_util::stream::iter(&language.data)
+            .map(|language_data| self.sku_download_links(language_data, &referer))
+            .buffered(SESSION_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let mut architectures = vec![];
+        for download_links in responses {
             if let Some(download_options) = download_links.product_download_options {
                 for option in download_options {
                     let arch_name = utils::get_arch_from_type(option.download_type);
@@ -262,6 +1005,58 @@ impl IsoApi {
         Ok(architectures)
     }
 
+    /// The download links of one SKU of a language, requested with the session that listed it
+    async fn sku_download_links(
+        &self,
+        language_data: &LanguageData,
+        referer: &str,
+    ) -> Result<MicrosoftApiResponse, FerroError> {
+        // Reuse the session ID from the SKU information call (like Fido does with $SessionId[$Entry.SessionIndex])
+        // Don't create a new session or whitelist again - reuse existing session
+
+        self.request_delay.wait().await;
+
+        // Get the stored session ID for this session index
+        let session_id = self
+            .session_ids
+            .get(&language_data.session_index)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Session ID not found for index {}",
+                    language_data.session_index
+                )
+            })?;
+
+        let cached = self
+            .responses
+            .lock()
+            .unwrap()
+            .links
+            .get(&language_data.sku_id)
+            .cloned();
+        if let Some(download_links) = cached {
+            debug!("Reusing the download links of SKU {}", language_data.sku_id);
+            return Ok(download_links);
+        }
+        match self
+            .get_download_links(&language_data.sku_id, session_id, referer)
+            .await
+        {
+            Ok(download_links) => {
+                self.responses
+                    .lock()
+                    .unwrap()
+                    .links
+                    .insert(language_data.sku_id.clone(), download_links.clone());
+                Ok(download_links)
+            }
+            Err(e) => {
+                self.forget_responses();
+                Err(e)
+            }
+        }
+    }
+
     pub async fn get_download_url(
         &mut self,
         version_name: &str,
@@ -269,26 +1064,87 @@ impl IsoApi {
         edition_name: &str,
         language_name: &str,
         architecture_name: &str,
-    ) -> Result<String> {
+    ) -> Result<String, FerroError> {
         let architectures = self
-            .get_architectures(version_name, release_name, edition_name, language_name)
+            .get_all_download_urls(version_name, release_name, edition_name, language_name)
             .await?;
 
-        let architecture = architectures
+        architectures
             .iter()
             .find(|a| a.name.to_lowercase() == architecture_name.to_lowercase())
-            .ok_or_else(|| anyhow!("Architecture '{}' not found", architecture_name))?;
+            .map(|a| a.url.clone())
+            .ok_or_else(|| FerroError::ArchitectureNotFound {
+                architecture: architecture_name.to_string(),
+                // The UEFI Shell has the same architectures in every language
+                language: (!utils::is_uefi_shell(version_name)).then(|| language_name.to_string()),
+                available: architectures.iter().map(|a| a.name.clone()).collect(),
+            })
+    }
+
+    /// The download link of every architecture of a language, from the same API calls
+    /// [`IsoApi::get_download_url`] makes for one of them, e.g. for a mirror. Unlike
+    /// [`IsoApi::get_architectures`], links about to expire are requested again.
+    pub async fn get_all_download_urls(
+        &mut self,
+        version_name: &str,
+        release_name: &str,
+        edition_name: &str,
+        language_name: &str,
+    ) -> Result<Vec<WindowsArchitecture>, FerroError> {
+        let mut architectures = self
+            .get_architectures(version_name, release_name, edition_name, language_name)
+            .await?;
+
+        // A link reused from earlier in the run may be about to expire, which would only
+        // show up as a 403 once the download is under way
+        let now = chrono::Utc::now();
+        if architectures
+            .iter()
+            .any(|a| utils::link_expires_soon(&a.url, now))
+        {
+            info!("A download link expires soon, requesting fresh ones");
+            self.forget_responses();
+            architectures = self
+                .get_architectures(version_name, release_name, edition_name, language_name)
+                .await?;
+        }
 
-        Ok(architecture.url.clone())
+        // Every UEFI Shell architecture shares the release's one ISO
+        if utils::is_uefi_shell(version_name) {
+            if let Some(url) = architectures.first().map(|a| a.url.clone()) {
+                if let Err(e) = self.fetch_uefi_shell_sha256(release_name, &url).await {
+                    warn!("No published SHA-256 found for the UEFI Shell: {:#}", e);
+                }
+            }
+        }
+
+        Ok(architectures)
+    }
+
+    /// SHA-256 published for a download's file name, if the SKU information or download page
+    /// (or for the UEFI Shell, the GitHub release) fetched so far listed one
+    pub fn published_sha256(&self, file_name: &str) -> Option<&str> {
+        self.published_hashes
+            .get(&file_name.to_lowercase())
+            .map(String::as_str)
     }
 
-    async fn whitelist_session(&self, session_id: &str) -> Result<()> {
+    /// SHA-256 Microsoft publishes for a SKU's ISO, if the SKU information or the download page
+    /// loaded by [`IsoApi::get_languages`] lists one for the SKU's edition and language. SKUs with an
+    /// ISO per architecture need [`IsoApi::published_sha256`] with the download's file name
+    /// instead.
+    pub fn get_published_sha256(&self, sku_id: &str) -> Option<String> {
+        self.sku_hashes.get(sku_id).cloned()
+    }
+
+    async fn whitelist_session(&self, session_id: &str) -> Result<(), FerroError> {
+        let _timer = self.timings.start("whitelist");
         let url = format!(
-            "https://vlscppe.microsoft.com/tags?org_id={}&session_id={}",
-            self.session_data.org_id, session_id
+            "{}/tags?org_id={}&session_id={}",
+            self.whitelist_url, self.session_data.org_id, session_id
         );
 
-        debug!("Whitelisting session: {}", url);
+        debug!("Whitelisting session: {}", redact(&url));
 
         // Exact replication of Fido: Invoke-WebRequest -UseBasicParsing -TimeoutSec $DefaultTimeout -MaximumRedirection 0 $url | Out-Null
         // Use minimal headers like PowerShell -UseBasicParsing
@@ -304,27 +1160,81 @@ impl IsoApi {
                 // Like Fido: catch { Error($_.Exception.Message); return @() }
                 // Let's add more debugging information to understand what's failing
                 debug!("Session whitelisting failed with error: {}", e);
-                Err(anyhow!("Session whitelisting failed: {}", e))
+                Err(FerroError::http("Session whitelisting failed", e))
             }
         }
     }
 
-    #[allow(dead_code)]
+    /// Create and whitelist a new session for `session_index`, like Fido does
+    async fn start_session(
+        &mut self,
+        session_index: usize,
+        edition_id: u32,
+    ) -> Result<String, FerroError> {
+        let session_id = Uuid::new_v4().to_string();
+        self.record_session(session_index, edition_id, &session_id, false);
+        self.whitelist_session(&session_id).await?;
+        Ok(session_id)
+    }
+
+    fn record_session(
+        &mut self,
+        session_index: usize,
+        edition_id: u32,
+        session_id: &str,
+        cached: bool,
+    ) {
+        // Store the session ID for later reuse (like Fido does)
+        self.session_ids
+            .insert(session_index, session_id.to_string());
+
+        if self.print_session_ids {
+            eprintln!(
+                "Session ID [{}] (edition {}): {}{}",
+                session_index,
+                edition_id,
+                session_id,
+                if cached { " (cached)" } else { "" }
+            );
+        }
+    }
+
+    /// Retry a failed SKU request with fresh cookies and the next profile. An empty answer
+    /// often means the session wasn't whitelisted in time, so the retry after one also
+    /// starts a new session. Returns the response with the profile and session that got it,
+    /// which is `first_attempt`'s when it succeeded.
     async fn get_sku_information_with_retry(
-        &self,
+        &mut self,
         product_edition_id: u32,
-        session_id: &str,
-    ) -> Result<MicrosoftApiResponse> {
+        session_index: usize,
+        mut session_id: String,
+        first_attempt: Result<MicrosoftApiResponse, FerroError>,
+    ) -> Result<(MicrosoftApiResponse, String, String), FerroError> {
         let mut retry_count = 0;
-        let max_retries = 3;
-
-        while retry_count < max_retries {
-            match self
-                .try_get_sku_information(product_edition_id, session_id, retry_count)
-                .await
-            {
-                Ok(response) => return Ok(response),
+        let mut empty_responses = 0;
+        let max_retries = match self.profiles.len() {
+            0 | 1 => 2,
+            len => (len as u32).max(3),
+        };
+
+        let mut attempt = first_attempt;
+        loop {
+            match attempt {
+                Ok(response) => {
+                    let profile_id = self.profile(retry_count).to_string();
+                    return Ok((response, profile_id, session_id));
+                }
+                // A ban doesn't lift with new cookies or another profile
+                Err(e @ FerroError::IpBanned { .. }) => return Err(e),
                 Err(e) if retry_count < max_retries - 1 => {
+                    let empty = matches!(e, FerroError::EmptyApiResponse { .. });
+                    if empty {
+                        empty_responses += 1;
+                    }
+                    // Connection failures have nothing to do with the cookies
+                    if !matches!(e, FerroError::HttpError { .. }) {
+                        self.reset_cookies("the API rejected the SKU request")?;
+                    }
                     let backoff_secs = 2u64.pow(retry_count + 1); // 2, 4, 8 seconds
                     warn!(
                         "SKU request failed (attempt {}), retrying in {} seconds: {}",
@@ -333,16 +1243,71 @@ impl IsoApi {
                         e
                     );
                     tokio::time::sleep(Duration::from_secs(backoff_secs)).await;
+                    if empty {
+                        session_id = self
+                            .start_session(session_index, product_edition_id)
+                            .await?;
+                    }
                     retry_count += 1;
                 }
+                // Empty answers even with fresh cookies are how a ban shows before any
+                // download-links request gets to report it
+                Err(FerroError::EmptyApiResponse { status }) if empty_responses == retry_count => {
+                    warn!(
+                        "Every SKU request came back empty (status {}), treating it as an IP ban",
+                        status
+                    );
+                    return Err(self.banned(&session_id).await);
+                }
                 Err(e) => return Err(e),
             }
+            if self.cookie_store.lock().is_err() {
+                self.reset_cookies("the cookie store was poisoned")?;
+            }
+            attempt = self
+                .try_get_sku_information(product_edition_id, &session_id, retry_count)
+                .await;
+        }
+    }
+
+    /// Save the whitelisted sessions and the cookies for the next run, if a cache is configured
+    fn save_session_cache(&self) {
+        let Some(path) = &self.session_cache else {
+            return;
+        };
+        let result = match self.cookie_store.lock() {
+            Ok(cookies) => {
+                SessionCache::new(self.sessions_created, self.session_ids.clone(), &cookies)
+            }
+            Err(_) => Err(anyhow!("the cookie store was poisoned")),
         }
+        .and_then(|cache| cache.save(path));
+        if let Err(e) = result {
+            debug!("Could not save the session cache: {:#}", e);
+        }
+    }
 
-        Err(anyhow!(
-            "Failed to get SKU information after {} attempts",
-            max_retries
-        ))
+    /// Start over with an empty cookie store. The client is rebuilt around the new store, so
+    /// this also recovers from a store whose mutex was poisoned.
+    fn reset_cookies(&mut self, reason: &str) -> Result<(), FerroError> {
+        warn!("Resetting the cookie store: {}", reason);
+        let cookie_store = Arc::new(CookieStoreMutex::default());
+        self.client = build_client(&self.http, self.timeout, cookie_store.clone())?;
+        self.cookie_store = cookie_store;
+        // Visit the product page again on the next SKU lookup, to populate the new store
+        self.session_warmed = false;
+        // Cached sessions and the responses fetched with them went with the old cookies
+        self.cached_sessions.clear();
+        self.forget_responses();
+        Ok(())
+    }
+
+    /// Profile to send with the given SKU request attempt, cycling through the configured ones
+    fn profile(&self, attempt: u32) -> &str {
+        match self.profiles.len() {
+            0 => DEFAULT_PROFILE_ID,
+            len => &self.profiles[attempt as usize % len],
+        }
     }
 
     async fn try_get_sku_information(
@@ -350,14 +1315,21 @@ impl IsoApi {
         product_edition_id: u32,
         session_id: &str,
         attempt: u32,
-    ) -> Result<MicrosoftApiResponse> {
+    ) -> Result<MicrosoftApiResponse, FerroError> {
+        let _timer = self.timings.start("sku");
+
         // Use exact same URL format as Fido with $QueryLocale
         let url = format!(
-            "https://www.microsoft.com/software-download-connector/api/getskuinformationbyproductedition?profile={}&productEditionId={}&SKU=undefined&friendlyFileName=undefined&Locale={}&sessionID={}",
-            self.session_data.profile_id, product_edition_id, self.query_locale, session_id
+            "{}/software-download-connector/api/getskuinformationbyproductedition?profile={}&productEditionId={}&SKU=undefined&friendlyFileName=undefined&Locale={}&sessionID={}",
+            self.microsoft_url, self.profile(attempt), product_edition_id, self.query_locale, session_id
         );
 
-        debug!("Getting SKU information (attempt {}): {}", attempt + 1, url);
+        debug!(
+            "Getting SKU information (attempt {}, profile {}): {}",
+            attempt + 1,
+            self.profile(attempt),
+            redact(&url)
+        );
 
         // Use minimal headers like Fido's -UseBasicParsing
         // Let reqwest handle compression automatically
@@ -366,39 +1338,40 @@ impl IsoApi {
             .get(&url)
             .send()
             .await
-            .context("Failed to get SKU information")?;
+            .map_err(|e| FerroError::http("Failed to get SKU information", e))?;
 
         let status = response.status();
         let headers = response.headers().clone();
         debug!("SKU API response status: {}", status);
-        debug!("SKU API response headers: {:?}", headers);
+        debug!(
+            "SKU API response headers: {}",
+            redact(&format!("{:?}", headers))
+        );
 
         let response_text = response
             .text()
             .await
-            .context("Failed to get response text")?;
+            .map_err(|e| FerroError::http("Failed to get response text", e))?;
         debug!(
             "SKU information response (length {}): {}",
             response_text.len(),
-            response_text
+            redact(&response_text)
         );
 
-        // Save response to file for debugging
-        if let Err(e) = std::fs::write("api_response.json", &response_text) {
-            debug!("Failed to write response to file: {}", e);
-        }
+        self.dump_response("api_response.json", &response_text);
 
         if response_text.trim().is_empty() {
-            return Err(anyhow!("API returned empty response. Status: {}. This might indicate that the API is blocking our requests or requires additional authentication.", status));
+            return Err(FerroError::EmptyApiResponse { status });
+        }
+        if is_html_page(&headers, &response_text) {
+            return Err(FerroError::HtmlResponse {
+                what: "SKU information",
+                status,
+                title: page_title(&response_text),
+            });
         }
 
-        let api_response: MicrosoftApiResponse = serde_json::from_str(&response_text)
-            .with_context(|| {
-                format!(
-                    "Failed to parse SKU information response. Response was: {}",
-                    response_text
-                )
-            })?;
+        let api_response = parse_api_response(&response_text, "SKU information")?;
 
         // Check for errors in ValidationContainer (newer API format)
         if let Some(validation_container) = &api_response.validation_container {
@@ -407,15 +1380,26 @@ impl IsoApi {
                 validation_container.errors.len()
             );
             if !validation_container.errors.is_empty() {
-                return Err(anyhow!("API error: {:?}", validation_container.errors[0]));
+                return Err(FerroError::ApiError(format!(
+                    "{:?}",
+                    validation_container.errors[0]
+                )));
             }
         }
 
         // Check for legacy errors format
         if let Some(errors) = &api_response.errors {
             debug!("Legacy errors count: {}", errors.len());
-            if !errors.is_empty() {
-                return Err(anyhow!("API error: {}", errors[0].value));
+            if let Some(error) = errors.first() {
+                warn!("SKU information request failed with {}", error.kind());
+                if error.kind() == ApiErrorKind::Banned {
+                    return Err(self.banned(session_id).await);
+                }
+                return Err(FerroError::ApiError(format!(
+                    "{}: {}",
+                    error.kind(),
+                    error.value
+                )));
             }
         }
 
@@ -427,66 +1411,87 @@ impl IsoApi {
         Ok(api_response)
     }
 
+    /// Save a raw API response as `name` in the dump directory, if one is configured
+    fn dump_response(&self, name: &str, response_text: &str) {
+        let Some(dir) = &self.dump_dir else {
+            return;
+        };
+        match write_dump(dir, name, response_text) {
+            Ok(path) => debug!("Saved response to {}", path.display()),
+            Err(e) => warn!("Failed to save response to {}: {}", dir.display(), e),
+        }
+    }
+
     async fn get_download_links(
         &self,
         sku_id: &str,
         session_id: &str,
-    ) -> Result<MicrosoftApiResponse> {
+        referer: &str,
+    ) -> Result<MicrosoftApiResponse, FerroError> {
+        let _timer = self.timings.start("links");
+
         let url = format!(
-            "https://www.microsoft.com/software-download-connector/api/GetProductDownloadLinksBySku?profile={}&productEditionId=undefined&SKU={}&friendlyFileName=undefined&Locale={}&sessionID={}",
-            self.session_data.profile_id, sku_id, self.query_locale, session_id
+            "{}/software-download-connector/api/GetProductDownloadLinksBySku?profile={}&productEditionId=undefined&SKU={}&friendlyFileName=undefined&Locale={}&sessionID={}",
+            self.microsoft_url, self.session_data.profile_id, sku_id, self.query_locale, session_id
         );
 
-        debug!("Getting download links: {}", url);
+        debug!("Getting download links: {}", redact(&url));
 
         // Must add a referer for this request, else Microsoft's servers may deny it (from Fido comment)
-        let referer = "https://www.microsoft.com/software-download/windows11";
         let response = self
             .client
             .get(&url)
             .header("Referer", referer)
             .send()
             .await
-            .context("Failed to get download links")?;
+            .map_err(|e| FerroError::http("Failed to get download links", e))?;
 
+        let status = response.status();
+        let headers = response.headers().clone();
         let response_text = response
             .text()
             .await
-            .context("Failed to get download links response text")?;
+            .map_err(|e| FerroError::http("Failed to get download links response text", e))?;
         debug!(
             "Download links response (length {}): {}",
             response_text.len(),
-            response_text
+            redact(&response_text)
         );
 
-        // Save response to file for debugging
-        if let Err(e) = std::fs::write("download_links_response.json", &response_text) {
-            debug!("Failed to write download links response to file: {}", e);
+        self.dump_response("download_links_response.json", &response_text);
+
+        if is_html_page(&headers, &response_text) {
+            return Err(FerroError::HtmlResponse {
+                what: "download links",
+                status,
+                title: page_title(&response_text),
+            });
         }
 
-        let api_response: MicrosoftApiResponse = serde_json::from_str(&response_text)
-            .with_context(|| {
-                format!(
-                    "Failed to parse download links response. Response was: {}",
-                    response_text
-                )
-            })?;
+        let api_response = parse_api_response(&response_text, "download links")?;
 
         // Check for errors in ValidationContainer first (newer API format)
         if let Some(validation_container) = &api_response.validation_container {
             if !validation_container.errors.is_empty() {
-                return Err(anyhow!("API error: {:?}", validation_container.errors[0]));
+                return Err(FerroError::ApiError(format!(
+                    "{:?}",
+                    validation_container.errors[0]
+                )));
             }
         }
 
         // Check for legacy errors format (like Fido does)
         if let Some(errors) = &api_response.errors {
-            if !errors.is_empty() {
-                if errors[0].error_type == 9 {
-                    let ban_message = self.get_code_715_123130_message().await;
-                    return Err(anyhow!("{} {}", ban_message, session_id));
+            if let Some(error) = errors.first() {
+                warn!("Download links request failed with {}", error.kind());
+                if error.kind() == ApiErrorKind::Banned {
+                    return Err(self.banned(session_id).await);
                 }
-                return Err(anyhow!("API error: {}", errors[0].value));
+                return Err(FerroError::ApiError(format!(
+                    "{}: {}",
+                    error.kind(),
+                    error.value
+                )));
             }
         }
 
@@ -498,62 +1503,153 @@ impl IsoApi {
         version_name: &str,
         release_name: &str,
         edition_name: &str,
-    ) -> Result<Vec<WindowsArchitecture>> {
-        // Extract version info for UEFI Shell
-        let tag = release_name.split(' ').next().unwrap_or("25H1");
-        let shell_version = version_name.split(' ').next_back().unwrap_or("2.2");
-
-        let base_url = format!(
-            "https://github.com/pbatard/UEFI-Shell/releases/download/{}",
-            tag
-        );
-        let link_base = format!("{}/UEFI-Shell-{}-{}", base_url, shell_version, tag);
-
-        let link = if edition_name.to_lowercase().contains("release") {
-            format!("{}-RELEASE.iso", link_base)
-        } else {
-            format!("{}-DEBUG.iso", link_base)
-        };
+    ) -> Result<Vec<WindowsArchitecture>, FerroError> {
+        let link = Self::uefi_shell_iso_url(version_name, release_name, edition_name);
 
         // Try to get supported architectures from Version.xml
-        let version_url = format!("{}/Version.xml", base_url);
+        let version_url = format!("{}/Version.xml", Self::uefi_shell_base_url(release_name));
 
+        let mut archs = vec![];
         match self.client.get(&version_url).send().await {
             Ok(response) if response.status().is_success() => {
                 if let Ok(xml_content) = response.text().await {
-                    // Simple XML parsing for architectures
-                    let archs = self.parse_uefi_architectures(&xml_content);
-                    if !archs.is_empty() {
-                        return Ok(vec![WindowsArchitecture {
-                            name: archs.join(", "),
-                            url: link,
-                        }]);
-                    }
+                    archs = parse_uefi_architectures(&xml_content);
                 }
             }
             _ => {
                 warn!("Could not fetch UEFI Shell version information");
             }
         }
+        if archs.is_empty() {
+            // Fallback to default architectures
+            archs = ["x64", "ARM64", "IA32"].map(String::from).to_vec();
+        }
+
+        // The ISO boots on every architecture it lists, so each one is the same download
+        Ok(archs
+            .into_iter()
+            .map(|name| WindowsArchitecture {
+                name,
+                url: link.clone(),
+            })
+            .collect())
+    }
+
+    /// Evaluation Center ISOs of the catalog version `version_name` matches
+    fn evaluation_isos(
+        &self,
+        version_name: &str,
+    ) -> Result<impl Iterator<Item = &'static EvaluationIso>, FerroError> {
+        let version = self.version_data(version_name)?;
+        let name = version.name.clone();
+        Ok(EVALUATION_ISOS
+            .iter()
+            .filter(move |(version, ..)| *version == name))
+    }
 
-        // Fallback to default architectures
+    /// The single x64 ISO the Evaluation Center offers for a Windows Server language
+    fn evaluation_architectures(
+        &self,
+        version_name: &str,
+        language_name: &str,
+    ) -> Result<Vec<WindowsArchitecture>, FerroError> {
+        let language = language_name.to_lowercase();
+        let (.., url) = self
+            .evaluation_isos(version_name)?
+            .find(|(_, name, display_name, _)| {
+                name.to_lowercase().contains(&language)
+                    || display_name.to_lowercase().contains(&language)
+            })
+            .ok_or_else(|| FerroError::LanguageNotFound(language_name.to_string()))?;
         Ok(vec![WindowsArchitecture {
-            name: "x64, ARM64, IA32".to_string(),
-            url: link,
+            name: "x64".to_string(),
+            url: url.to_string(),
         }])
     }
 
-    fn parse_uefi_architectures(&self, xml_content: &str) -> Vec<String> {
-        // Simple regex-based XML parsing for <arch> elements
-        let arch_regex = regex::Regex::new(r"<arch>([^<]+)</arch>").unwrap();
-        arch_regex
-            .captures_iter(xml_content)
-            .map(|cap| cap[1].to_string())
-            .collect()
+    /// Look up the SHA-256 of a UEFI Shell ISO among the assets of its GitHub release, so
+    /// the download is verified like a Windows ISO with a published hash
+    async fn fetch_uefi_shell_sha256(&mut self, release_name: &str, iso_url: &str) -> Result<()> {
+        let file_name = utils::extract_filename_from_url(iso_url)
+            .ok_or_else(|| anyhow!("No file name in {}", iso_url))?;
+        let api_url = format!(
+            "https://api.github.com/repos/pbatard/UEFI-Shell/releases/tags/{}",
+            Self::uefi_shell_tag(release_name)
+        );
+        debug!("Getting UEFI Shell release assets: {}", api_url);
+
+        let release: GitHubRelease = self
+            .client
+            .get(&api_url)
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("Failed to get the GitHub release")?
+            .json()
+            .await
+            .context("Failed to parse the GitHub release")?;
+        let asset = release.checksum_asset(&file_name).ok_or_else(|| {
+            anyhow!(
+                "Release {} has no checksum asset for {}",
+                release.tag_name,
+                file_name
+            )
+        })?;
+
+        let listing = self
+            .client
+            .get(&asset.browser_download_url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Failed to download {}", asset.name))?
+            .text()
+            .await?;
+        let hash = checksum::find_sha256(&listing, &file_name)
+            .ok_or_else(|| anyhow!("{} does not list {}", asset.name, file_name))?;
+
+        debug!("Published SHA-256 of {}: {}", file_name, hash);
+        self.published_hashes.insert(file_name.to_lowercase(), hash);
+        Ok(())
+    }
+
+    /// Download URL of a UEFI Shell ISO, derived from the catalog without any network access
+    pub fn uefi_shell_iso_url(
+        version_name: &str,
+        release_name: &str,
+        edition_name: &str,
+    ) -> String {
+        let tag = Self::uefi_shell_tag(release_name);
+        let shell_version = version_name.split(' ').next_back().unwrap_or("2.2");
+        let link_base = format!(
+            "{}/UEFI-Shell-{}-{}",
+            Self::uefi_shell_base_url(release_name),
+            shell_version,
+            tag
+        );
+
+        format!(
+            "{}-{}.iso",
+            link_base,
+            UefiVariant::from_edition_name(edition_name).iso_suffix()
+        )
+    }
+
+    fn uefi_shell_tag(release_name: &str) -> &str {
+        release_name.split(' ').next().unwrap_or("25H1")
+    }
+
+    fn uefi_shell_base_url(release_name: &str) -> String {
+        format!(
+            "https://github.com/pbatard/UEFI-Shell/releases/download/{}",
+            Self::uefi_shell_tag(release_name)
+        )
     }
 
     // Check if the locale we want is available - Fall back to en-US otherwise (like Fido)
-    async fn check_and_set_locale(&mut self) -> Result<()> {
+    async fn check_and_set_locale(&mut self) -> Result<(), FerroError> {
+        let _timer = self.timings.start("locale");
         let system_locale = utils::get_system_locale();
 
         // Try system locale first
@@ -568,8 +1664,22 @@ impl IsoApi {
         Ok(())
     }
 
-    async fn check_locale(&self, locale: &str) -> Result<bool> {
-        let url = format!("https://www.microsoft.com/{}/software-download/", locale);
+    /// Check a locale given with `--query-locale` once a request has failed, since Microsoft's
+    /// API answers an unsupported one with errors that don't mention it
+    async fn warn_if_locale_unsupported(&mut self) {
+        // Only worth one extra request
+        self.locale_forced = false;
+        if !self.check_locale(&self.query_locale).await.unwrap_or(true) {
+            // A warning, since the error alone doesn't point at the locale
+            warn!(
+                "Microsoft's download pages don't seem to support the locale {}; try another --query-locale",
+                self.query_locale
+            );
+        }
+    }
+
+    async fn check_locale(&self, locale: &str) -> Result<bool, FerroError> {
+        let url = format!("{}/{}/software-download/", self.microsoft_url, locale);
 
         debug!("Checking locale: {}", url);
 
@@ -582,11 +1692,20 @@ impl IsoApi {
         }
     }
 
+    /// The ban error for `session_id`, with Microsoft's own wording when its page can be loaded
+    async fn banned(&self, session_id: &str) -> FerroError {
+        FerroError::IpBanned {
+            session_id: session_id.to_string(),
+            message: self.get_code_715_123130_message().await,
+            show_session_id: self.print_session_ids,
+        }
+    }
+
     // Get the 715-123130 ban message like Fido does
     async fn get_code_715_123130_message(&self) -> String {
         let url = format!(
-            "https://www.microsoft.com/{}/software-download/windows11",
-            self.query_locale
+            "{}/{}/software-download/windows11",
+            self.microsoft_url, self.query_locale
         );
 
         if let Ok(response) = self.client.get(&url).send().await {
@@ -621,3 +1740,758 @@ impl IsoApi {
         msg.to_string()
     }
 }
+
+/// The hashes `page_hashes` lists for `sku`, an SKU of one of `edition_ids`, as (file name,
+/// SHA-256). The page names languages a little differently from the API, e.g. "Chinese
+/// Simplified" for "Chinese (Simplified)", and each hash goes with the file named after its
+/// architecture.
+fn sku_published_hashes(
+    sku: &Sku,
+    edition_ids: &[u32],
+    page_hashes: &[catalog::PublishedHash],
+) -> Vec<(String, String)> {
+    let comparable = |name: &str| -> String {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let language = comparable(&sku.language);
+    let hashes: Vec<_> = page_hashes
+        .iter()
+        .filter(|hash| {
+            edition_ids.contains(&hash.edition_id) && comparable(&hash.language) == language
+        })
+        .collect();
+
+    sku.friendly_file_names
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|file_name| {
+            let architecture = file_architecture(file_name)?;
+            let hash = hashes
+                .iter()
+                .find(|hash| hash.architecture == architecture)?;
+            Some((file_name.clone(), hash.sha256.clone()))
+        })
+        .collect()
+}
+
+/// The architecture an ISO's file name ends in, e.g. `Win11_24H2_English_x64.iso`
+fn file_architecture(file_name: &str) -> Option<&'static str> {
+    let name = file_name.to_lowercase();
+    if name.contains("arm64") {
+        Some("ARM64")
+    } else if name.contains("x64") {
+        Some("x64")
+    } else if name.contains("x32") || name.contains("x86") {
+        Some("x86")
+    } else {
+        None
+    }
+}
+
+/// The one item `query` names or abbreviates, see [`utils::match_names`]
+fn find_named<'a, T>(
+    items: &'a [T],
+    query: &str,
+    name: impl Fn(&T) -> &str,
+    what: &'static str,
+    not_found: fn(String) -> FerroError,
+) -> Result<&'a T, FerroError> {
+    match utils::match_names(items, query, &name).as_slice() {
+        [] => Err(not_found(query.to_string())),
+        [item] => Ok(item),
+        candidates => Err(FerroError::Ambiguous {
+            what,
+            query: query.to_string(),
+            candidates: candidates
+                .iter()
+                .map(|item| name(item).to_string())
+                .collect(),
+        }),
+    }
+}
+
+fn release_data<'a>(
+    version_data: &'a WindowsVersionData,
+    release_name: &str,
+) -> Result<&'a WindowsReleaseData, FerroError> {
+    find_named(
+        &version_data.releases,
+        release_name,
+        |r| &r.name,
+        "Release",
+        FerroError::ReleaseNotFound,
+    )
+}
+
+/// Architectures listed in a UEFI Shell release's Version.xml: the text or `name` attribute of
+/// every `<arch>` element at any depth and in any namespace, split on commas, without
+/// duplicates. Malformed XML lists none, so the caller falls back to its defaults.
+fn parse_uefi_architectures(xml_content: &str) -> Vec<String> {
+    let document = match roxmltree::Document::parse(xml_content) {
+        Ok(document) => document,
+        Err(e) => {
+            debug!("Could not parse the UEFI Shell Version.xml: {}", e);
+            return vec![];
+        }
+    };
+
+    let mut archs: Vec<String> = vec![];
+    for node in document
+        .descendants()
+        .filter(|node| node.tag_name().name().eq_ignore_ascii_case("arch"))
+    {
+        let value = match node.text().map(str::trim) {
+            Some(text) if !text.is_empty() => text,
+            _ => node.attribute("name").unwrap_or_default(),
+        };
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if !archs.iter().any(|arch| arch.eq_ignore_ascii_case(name)) {
+                archs.push(name.to_string());
+            }
+        }
+    }
+    archs
+}
+
+/// Save a redacted copy of a raw API response as `dir/name`
+fn write_dump(dir: &Path, name: &str, response_text: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(name);
+    std::fs::write(&path, redact(response_text).as_bytes())?;
+    Ok(path)
+}
+
+/// Whether an API answer is a web page rather than JSON, going by its Content-Type or,
+/// when that is missing or wrong, by how the body starts
+fn is_html_page(headers: &reqwest::header::HeaderMap, response_text: &str) -> bool {
+    let content_type = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let start: String = response_text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .take(14)
+        .collect::<String>()
+        .to_lowercase();
+    content_type.to_lowercase().starts_with("text/html")
+        || start.starts_with("<!doctype html")
+        || start.starts_with("<html")
+}
+
+/// The `<title>` of a web page, with its whitespace collapsed
+fn page_title(page: &str) -> Option<String> {
+    let title = regex::Regex::new(r"(?is)<title[^>]*>(.*?)</title>")
+        .unwrap()
+        .captures(page)?
+        .get(1)?
+        .as_str()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!title.is_empty()).then(|| title.chars().take(100).collect())
+}
+
+/// Longest part of an unparseable response quoted in the error
+const RESPONSE_EXCERPT_CHARS: usize = 200;
+
+/// Parse a Microsoft API response, telling apart a body that isn't JSON at all from
+/// JSON whose structure no longer matches `MicrosoftApiResponse` (API drift)
+fn parse_api_response(response_text: &str, what: &str) -> Result<MicrosoftApiResponse> {
+    let value: serde_json::Value = serde_json::from_str(response_text).with_context(|| {
+        let excerpt: String = response_text.chars().take(RESPONSE_EXCERPT_CHARS).collect();
+        format!(
+            "Failed to parse {} response. Response was: {}{}",
+            what,
+            excerpt,
+            if excerpt.len() < response_text.len() {
+                "…"
+            } else {
+                ""
+            }
+        )
+    })?;
+
+    let api_response: MicrosoftApiResponse = match serde_json::from_value(value.clone()) {
+        Ok(api_response) => api_response,
+        Err(e) => {
+            let fields = value
+                .as_object()
+                .map(|object| object.keys().cloned().collect::<Vec<_>>().join(", "))
+                .unwrap_or_else(|| "<not an object>".to_string());
+            warn!(
+                "The {} response structure has changed ({}); top-level fields: {}",
+                what, e, fields
+            );
+            debug!("Unrecognized {} response: {}", what, redact(response_text));
+            return Err(anyhow!(
+                "Microsoft changed the format of the {} response ({}). Please report this issue.",
+                what,
+                e
+            ));
+        }
+    };
+
+    if !api_response.extra.is_empty() {
+        debug!(
+            "Unmapped fields in {} response: {}",
+            what,
+            redact(
+                &serde_json::Value::Object(api_response.extra.clone().into_iter().collect())
+                    .to_string()
+            )
+        );
+    }
+
+    Ok(api_response)
+}
+
+/// Client for the API, like PowerShell's Invoke-RestMethod with -UseBasicParsing
+fn build_client(
+    http: &HttpOptions,
+    timeout: Duration,
+    cookie_store: Arc<CookieStoreMutex>,
+) -> Result<Client, FerroError> {
+    // Use the exact PowerShell User-Agent format that Fido uses unless overridden
+    http.configure(Client::builder(), POWERSHELL_USER_AGENT)
+        .redirect(reqwest::redirect::Policy::none()) // MaximumRedirection 0 like Fido
+        .timeout(timeout)
+        .cookie_provider(cookie_store)
+        // gzip decompression is enabled by default in reqwest
+        .build()
+        .map_err(|e| FerroError::http("Failed to create HTTP client", e))
+}
+
+fn matrix_entries(
+    language: &WindowsLanguage,
+    architectures: Vec<WindowsArchitecture>,
+) -> Vec<MatrixEntry> {
+    architectures
+        .into_iter()
+        .map(|architecture| MatrixEntry {
+            language: language.name.clone(),
+            display_name: language.display_name.clone(),
+            architecture: architecture.name,
+            url: architecture.url,
+        })
+        .collect()
+}
+
+/// Classify the outcome of requesting a language's download links for `probe`
+fn probe_entry(
+    language: &WindowsLanguage,
+    result: Result<Vec<WindowsArchitecture>, FerroError>,
+) -> ProbeEntry {
+    let (status, architectures, error) = match result {
+        Ok(architectures) if architectures.is_empty() => (ProbeStatus::Empty, Vec::new(), None),
+        Ok(architectures) => (
+            ProbeStatus::Reachable,
+            architectures.into_iter().map(|a| a.name).collect(),
+            None,
+        ),
+        Err(e @ FerroError::IpBanned { .. }) => {
+            (ProbeStatus::Banned, Vec::new(), Some(e.to_string()))
+        }
+        Err(e) => (
+            ProbeStatus::Error,
+            Vec::new(),
+            Some(format!("{:#}", anyhow::Error::new(e))),
+        ),
+    };
+    ProbeEntry {
+        language: language.name.clone(),
+        display_name: language.display_name.clone(),
+        status,
+        architectures,
+        error,
+    }
+}
+
+/// `default`, or the server [`ApiConfig::base_url`] sends it to instead
+fn base_url(base_url: &Option<String>, default: &str) -> String {
+    base_url
+        .as_deref()
+        .unwrap_or(default)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_entry_status() {
+        let language = WindowsLanguage {
+            name: "English".to_string(),
+            display_name: "English (US)".to_string(),
+            data: Vec::new(),
+        };
+        let architecture = WindowsArchitecture {
+            name: "x64".to_string(),
+            url: String::new(),
+        };
+
+        let entry = probe_entry(&language, Ok(vec![architecture]));
+        assert_eq!(entry.status, ProbeStatus::Reachable);
+        assert_eq!(entry.architectures, vec!["x64".to_string()]);
+        assert_eq!(
+            probe_entry(&language, Ok(Vec::new())).status,
+            ProbeStatus::Empty
+        );
+
+        let banned = FerroError::IpBanned {
+            session_id: "abc".to_string(),
+            message: "Message code 715-123130, session ID".to_string(),
+            show_session_id: false,
+        };
+        let entry = probe_entry(&language, Err(banned));
+        assert_eq!(entry.status, ProbeStatus::Banned);
+        assert!(entry.error.unwrap().contains("715-123130"));
+        assert_eq!(
+            probe_entry(&language, Err(anyhow!("timeout").into())).status,
+            ProbeStatus::Error
+        );
+    }
+
+    #[test]
+    fn test_request_delay_sample() {
+        assert_eq!(RequestDelay::default().sample(), Duration::ZERO);
+
+        let fixed = Duration::from_millis(800);
+        let delay = RequestDelay {
+            min: fixed,
+            max: fixed,
+        };
+        assert_eq!(delay.sample(), fixed);
+
+        let delay = RequestDelay {
+            min: Duration::from_millis(500),
+            max: Duration::from_millis(1500),
+        };
+        for _ in 0..100 {
+            let sample = delay.sample();
+            assert!(sample >= delay.min && sample <= delay.max);
+        }
+    }
+
+    #[test]
+    fn test_find_named_in_catalog() {
+        let catalog = get_windows_versions();
+        let version = find_named(
+            &catalog,
+            "11",
+            |v| &v.name,
+            "Version",
+            FerroError::VersionNotFound,
+        )
+        .unwrap();
+        assert_eq!(version.name, "Windows 11");
+        assert_eq!(
+            release_data(version, "24h2").unwrap().name,
+            "24H2 (Build 26100.1742 - 2024.10)"
+        );
+
+        let error = find_named(
+            &catalog,
+            "server",
+            |v| &v.name,
+            "Version",
+            FerroError::VersionNotFound,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Version 'server' is ambiguous, it matches 'Windows Server 2025', 'Windows Server 2022'. Give more of the name."
+        );
+        assert!(matches!(
+            release_data(version, "25H2"),
+            Err(FerroError::ReleaseNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_uefi_architectures() {
+        let xml = "<release><arch>x64</arch><arch>ARM64, IA32</arch><arch>X64</arch></release>";
+        assert_eq!(parse_uefi_architectures(xml), vec!["x64", "ARM64", "IA32"]);
+        assert!(parse_uefi_architectures("<release></release>").is_empty());
+    }
+
+    #[test]
+    fn test_parse_uefi_architectures_layouts() {
+        // Nested, namespaced, attributed and self-closing elements
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+            <v:version xmlns:v="urn:uefi-shell">
+              <v:build type="release">
+                <v:arch id="1">
+                  x64
+                </v:arch>
+                <platforms><v:arch name="AA64"/></platforms>
+              </v:build>
+            </v:version>"#;
+        assert_eq!(parse_uefi_architectures(xml), vec!["x64", "AA64"]);
+    }
+
+    #[test]
+    fn test_parse_uefi_architectures_malformed() {
+        assert!(parse_uefi_architectures("<release><arch>x64</release>").is_empty());
+        assert!(parse_uefi_architectures("<html>404: Not Found").is_empty());
+        assert!(parse_uefi_architectures("").is_empty());
+    }
+
+    #[test]
+    fn test_write_dump() {
+        assert_eq!(dump_dir("1"), std::env::temp_dir().join("ferro"));
+        assert_eq!(dump_dir("/tmp/responses"), PathBuf::from("/tmp/responses"));
+
+        let dir = std::env::temp_dir().join(format!("ferro-dump-{}", std::process::id()));
+        let path = write_dump(&dir, "api_response.json", "{}").unwrap();
+        assert_eq!(path, dir.join("api_response.json"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_api_response_keeps_unmapped_fields() {
+        let response = parse_api_response(
+            r#"{"Skus":[],"Errors":null,"NewField":{"a":1}}"#,
+            "SKU information",
+        )
+        .unwrap();
+        assert_eq!(response.skus.map(|skus| skus.len()), Some(0));
+        assert_eq!(response.extra["NewField"]["a"], 1);
+    }
+
+    #[test]
+    fn test_parse_api_response_reports_changed_structure() {
+        let error = parse_api_response(r#"{"Errors":"oops"}"#, "download links").unwrap_err();
+        assert!(error.to_string().contains("changed the format"));
+    }
+
+    #[test]
+    fn test_parse_api_response_reports_invalid_json() {
+        let error = parse_api_response("<html></html>", "download links").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Failed to parse download links response"));
+
+        let error = parse_api_response(&"x".repeat(5000), "download links").unwrap_err();
+        assert!(format!("{:#}", error).len() < 1000);
+    }
+
+    #[test]
+    fn test_html_page_detection() {
+        use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+
+        let page =
+            "\n<!DOCTYPE html>\n<html><head><title>\n  Access Denied\n</title></head></html>";
+        assert!(is_html_page(&HeaderMap::new(), page));
+        assert_eq!(page_title(page).as_deref(), Some("Access Denied"));
+        assert!(is_html_page(
+            &HeaderMap::new(),
+            "<HTML><body>blocked</body></HTML>"
+        ));
+        assert_eq!(page_title("<html><body>blocked</body></html>"), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        assert!(is_html_page(&headers, "Service unavailable"));
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        assert!(!is_html_page(&headers, r#"{"Skus":[]}"#));
+    }
+
+    const SKU_PATH: &str = "/software-download-connector/api/getskuinformationbyproductedition";
+    const LINKS_PATH: &str = "/software-download-connector/api/GetProductDownloadLinksBySku";
+
+    /// A stand-in for Microsoft's servers on a local port. `respond` gives the status and body
+    /// for each request's path and query; every request is recorded in order.
+    struct MockServer {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockServer {
+        async fn start(respond: fn(&str) -> (u16, String)) -> Self {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let recorded = recorded.clone();
+                    tokio::spawn(async move {
+                        let mut request = Vec::new();
+                        let mut buf = [0u8; 4096];
+                        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                            match stream.read(&mut buf).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => request.extend_from_slice(&buf[..n]),
+                            }
+                        }
+                        let request = String::from_utf8_lossy(&request);
+                        let target = request.split(' ').nth(1).unwrap_or_default().to_string();
+                        let (status, body) = respond(&target);
+                        recorded.lock().unwrap().push(target);
+                        let response = format!(
+                            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        let _ = stream.write_all(response.as_bytes()).await;
+                    });
+                }
+            });
+            MockServer { url, requests }
+        }
+
+        /// The recorded requests to `path`
+        fn requests(&self, path: &str) -> Vec<String> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|target| target.split('?').next() == Some(path))
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// The value of `name` in a request's query string
+    fn query_param<'a>(target: &'a str, name: &str) -> &'a str {
+        target
+            .split_once('?')
+            .and_then(|(_, query)| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Microsoft's answers for a working session: one English SKU per edition ID, with the
+    /// SKU ID set to the edition ID, and an x64 and an ARM64 link for each SKU
+    fn respond_ok(target: &str) -> (u16, String) {
+        match target.split('?').next().unwrap_or_default() {
+            "/tags" => (200, String::new()),
+            SKU_PATH => (
+                200,
+                format!(
+                    r#"{{"Skus":[{{"Id":"{}","Language":"English","LocalizedLanguage":"English (United States)","LocalizedProductDisplayName":"Windows 11"}}]}}"#,
+                    query_param(target, "productEditionId")
+                ),
+            ),
+            LINKS_PATH => {
+                let sku = query_param(target, "SKU");
+                (
+                    200,
+                    format!(
+                        r#"{{"ProductDownloadOptions":[{{"Uri":"https://example.com/{0}/Win11_x64.iso","DownloadType":1}},{{"Uri":"https://example.com/{0}/Win11_ARM64.iso","DownloadType":2}}]}}"#,
+                        sku
+                    ),
+                )
+            }
+            _ => (404, String::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_api_download_links() {
+        let server = MockServer::start(respond_ok).await;
+        let mut api = IsoApi::with_base_url(&server.url).await.unwrap();
+
+        let languages = api
+            .get_languages("Windows 11", "24H2", "Home/Pro/Edu")
+            .await
+            .unwrap();
+        assert_eq!(languages.len(), 1);
+        assert_eq!(languages[0].display_name, "English (United States)");
+        assert_eq!(languages[0].data.len(), 2);
+
+        let url = api
+            .get_download_url("Windows 11", "24H2", "Home/Pro/Edu", "English", "ARM64")
+            .await
+            .unwrap();
+        assert_eq!(url, "https://example.com/3113/Win11_ARM64.iso");
+
+        // Each session is whitelisted, then used for its SKU request and that SKU's links
+        let sku_requests = server.requests(SKU_PATH);
+        let link_requests = server.requests(LINKS_PATH);
+        assert_eq!(sku_requests.len(), 2);
+        assert_eq!(link_requests.len(), 2);
+        let whitelist_requests = server.requests("/tags");
+        let mut whitelisted: Vec<&str> = whitelist_requests
+            .iter()
+            .map(|target| query_param(target, "session_id"))
+            .collect();
+        let mut sessions: Vec<&str> = sku_requests
+            .iter()
+            .map(|target| query_param(target, "sessionID"))
+            .collect();
+        whitelisted.sort();
+        sessions.sort();
+        assert_eq!(whitelisted, sessions);
+        for link_request in &link_requests {
+            let sku_request = sku_requests
+                .iter()
+                .find(|target| {
+                    query_param(target, "productEditionId") == query_param(link_request, "SKU")
+                })
+                .unwrap();
+            assert_eq!(
+                query_param(link_request, "sessionID"),
+                query_param(sku_request, "sessionID")
+            );
+        }
+
+        // The responses are reused for the rest of the run
+        api.get_download_url("Windows 11", "24H2", "Home/Pro/Edu", "English", "x64")
+            .await
+            .unwrap();
+        assert_eq!(server.requests(SKU_PATH).len(), 2);
+        assert_eq!(server.requests(LINKS_PATH).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mock_api_published_hashes() {
+        let server = MockServer::start(|target| match target.split('?').next() {
+            Some("/software-download/windows11") => (
+                200,
+                include_str!("../tests/fixtures/windows11_download_page.html").to_string(),
+            ),
+            Some(SKU_PATH) => (
+                200,
+                format!(
+                    r#"{{"Skus":[{{"Id":"{}","Language":"English International","LocalizedLanguage":"English (International)","LocalizedProductDisplayName":"Windows 11","FriendlyFileNames":["Win11_24H2_EnglishInternational_x64.iso"]}},{{"Id":"9{0}","Language":"Chinese (Simplified)","LocalizedLanguage":"Chinese (Simplified)","LocalizedProductDisplayName":"Windows 11","FriendlyFileNames":["Win11_24H2_Chinese_Simplified_x64.iso","Win11_24H2_Chinese_Simplified_Arm64.iso"]}}]}}"#,
+                    query_param(target, "productEditionId")
+                ),
+            ),
+            _ => respond_ok(target),
+        })
+        .await;
+        let mut api = IsoApi::with_base_url(&server.url).await.unwrap();
+        api.get_languages("Windows 11", "24H2", "Home/Pro/Edu")
+            .await
+            .unwrap();
+
+        let english = "1f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c5b6a7988";
+        assert_eq!(api.get_published_sha256("3113").as_deref(), Some(english));
+        assert_eq!(
+            api.published_sha256("Win11_24H2_EnglishInternational_x64.iso"),
+            Some(english)
+        );
+        // A SKU with an ISO per architecture only has hashes by file name, and the page has
+        // none for Chinese on ARM64
+        assert_eq!(api.get_published_sha256("93113"), None);
+        assert_eq!(
+            api.published_sha256("Win11_24H2_Chinese_Simplified_x64.iso"),
+            Some("2b3c4d5e6f708192a3b4c5d6e7f801122b3c4d5e6f708192a3b4c5d6e7f80112")
+        );
+        assert_eq!(
+            api.published_sha256("Win11_24H2_Chinese_Simplified_Arm64.iso"),
+            None
+        );
+        // The page is loaded once
+        assert_eq!(server.requests("/software-download/windows11").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mock_api_ban() {
+        let server = MockServer::start(|target| match target.split('?').next() {
+            Some(SKU_PATH) => (
+                200,
+                r#"{"Errors":[{"Type":9,"Value":"Sentinel marked this request as rejected."}]}"#
+                    .to_string(),
+            ),
+            _ => (404, String::new()),
+        })
+        .await;
+        let mut api = IsoApi::with_base_url(&server.url).await.unwrap();
+
+        let error = api
+            .get_languages("Windows 11", "24H2", "Home/Pro/Edu")
+            .await
+            .unwrap_err();
+        let FerroError::IpBanned {
+            session_id,
+            message,
+            ..
+        } = error
+        else {
+            panic!("expected a ban, got {:?}", error);
+        };
+        // A ban isn't retried
+        let sku_requests = server.requests(SKU_PATH);
+        assert_eq!(sku_requests.len(), 2);
+        assert!(sku_requests
+            .iter()
+            .any(|target| query_param(target, "sessionID") == session_id));
+        assert!(message.contains("715-123130"));
+    }
+
+    #[tokio::test]
+    async fn test_mock_api_empty_responses() {
+        let server = MockServer::start(|target| match target.split('?').next() {
+            Some(SKU_PATH) => (200, String::new()),
+            _ => (404, String::new()),
+        })
+        .await;
+        let mut api = IsoApi::with_base_url(&server.url).await.unwrap();
+
+        let error = api
+            .get_languages("Windows 11", "24H2", "Home/Pro/Edu")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, FerroError::IpBanned { .. }), "{:?}", error);
+
+        // The empty answer is retried once with a new session before counting as a ban
+        let sku_requests = server.requests(SKU_PATH);
+        let sessions: std::collections::HashSet<&str> = sku_requests
+            .iter()
+            .map(|target| query_param(target, "sessionID"))
+            .collect();
+        assert_eq!(sessions.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_mock_api_html_links_response() {
+        let server = MockServer::start(|target| match target.split('?').next() {
+            Some(LINKS_PATH) => (
+                403,
+                "<!DOCTYPE html><html><head><title>Access Denied</title></head></html>".to_string(),
+            ),
+            _ => respond_ok(target),
+        })
+        .await;
+        let mut api = IsoApi::with_base_url(&server.url).await.unwrap();
+
+        let error = api
+            .get_download_url("Windows 11", "24H2", "Home/Pro/Edu", "English", "x64")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                FerroError::HtmlResponse { what: "download links", title: Some(title), .. }
+                    if title == "Access Denied"
+            ),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,53 @@
+//! Download Windows and UEFI Shell ISOs straight from Microsoft and GitHub, as the `ferro`
+//! command does.
+//!
+//! [`IsoApi`] walks Microsoft's software-download API from a version down to a download link,
+//! and [`Downloader`] fetches that link to disk:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut api = ferro::IsoApi::new().await?;
+//! let url = api
+//!     .get_download_url("Windows 11", "24H2", "Windows 11 Home/Pro/Edu", "English", "x64")
+//!     .await?;
+//! ferro::Downloader::new().download(&url, "Win11_24H2_English_x64.iso").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Raw API responses are only saved when asked for; see [`ApiConfig::dump_dir`].
+
+pub mod batch;
+pub mod catalog;
+pub mod checksum;
+pub mod cli;
+pub mod config;
+pub mod config_file;
+pub mod downloader;
+pub mod error;
+pub mod hook;
+pub mod http;
+pub mod iso9660;
+pub mod iso_api;
+pub mod lock;
+pub mod manifest;
+pub mod output;
+pub mod output_template;
+pub mod part_index;
+pub mod progress;
+pub mod queue;
+pub mod rate_limit;
+pub mod redact;
+pub mod segmented;
+pub mod selftest;
+pub mod session_cache;
+pub mod signals;
+pub mod sink;
+pub mod timings;
+pub mod types;
+pub mod utils;
+
+pub use crate::downloader::{Downloaded, Downloader, DownloaderConfig};
+pub use crate::error::FerroError;
+pub use crate::iso_api::{ApiConfig, IsoApi};
+pub use crate::types::*;
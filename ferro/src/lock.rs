@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Context, Result};
+use fs2::FileExt;
+use log::debug;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Advisory lock on a download's output, held while the file and its `.part` are written
+/// so two Ferro processes can't interleave writes to the same file
+pub struct OutputLock {
+    file: File,
+    path: PathBuf,
+}
+
+/// Path of the lock file for an output path
+pub fn lock_path(output_path: &Path) -> PathBuf {
+    let mut os_string = output_path.as_os_str().to_owned();
+    os_string.push(".lock");
+    PathBuf::from(os_string)
+}
+
+impl OutputLock {
+    /// Lock `output_path`, failing right away if another process holds the lock
+    pub fn acquire(output_path: &Path) -> Result<Self> {
+        let path = lock_path(output_path);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create lock file: {}", path.display()))?;
+
+        if file.try_lock_exclusive().is_err() {
+            return Err(anyhow!(
+                "Another download to this file is in progress: {}",
+                output_path.display()
+            ));
+        }
+
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Remove the file while still holding the lock, so nobody locks a file that's about to vanish
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("Failed to remove lock file {}: {}", self.path.display(), e);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_fails() {
+        let output = std::env::temp_dir().join(format!("ferro-lock-{}.iso", std::process::id()));
+
+        let first = OutputLock::acquire(&output).unwrap();
+        let second = OutputLock::acquire(&output);
+        assert!(second
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Another download to this file is in progress"));
+
+        drop(first);
+        assert!(!lock_path(&output).exists());
+        drop(OutputLock::acquire(&output).unwrap());
+    }
+}
@@ -1,42 +1,193 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use log::info;
-use std::path::PathBuf;
+use futures_util::StreamExt;
+use log::{debug, info, warn};
+use std::path::{Path, PathBuf};
 
-mod cli;
-mod downloader;
-mod iso_api;
-mod types;
-mod utils;
-
-use crate::cli::Cli;
-use crate::downloader::Downloader;
-use crate::iso_api::IsoApi;
+use ferro::checksum::{self, ChecksumAlgo};
+use ferro::cli::{Cli, LanguageDisplay};
+use ferro::config::{DownloadSettings, Settings};
+use ferro::config_file::ConfigFile;
+use ferro::downloader::{Downloaded, Downloader, DownloaderConfig, Fetched, RemoteVersion};
+use ferro::error::{self, FerroError};
+use ferro::iso_api::{ApiConfig, IsoApi};
+use ferro::manifest::Manifest;
+use ferro::output::{Output, OutputFormat};
+use ferro::progress::ProgressFormat;
+use ferro::queue::{ItemReport, ItemStatus, Queue};
+use ferro::redact::{self, redact};
+use ferro::timings::Timings;
+use ferro::types::{
+    DownloadReport, DryRun, ProbeStatus, ResolvedUrl, Selection, SelectionQuery, VersionReleases,
+    WindowsLanguage,
+};
+use ferro::{
+    batch, catalog, config, hook, iso9660, output_template, selftest, signals, types, utils,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    // The catalog is hand-maintained, so catch data-entry mistakes early in debug builds
+    if cfg!(debug_assertions) {
+        let problems = types::validate_catalog(&types::get_windows_versions());
+        debug_assert!(
+            problems.is_empty(),
+            "Built-in catalog is inconsistent:\n{}",
+            problems.join("\n")
+        );
+    }
 
     let cli = Cli::parse();
 
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = cli.log_level() {
+        logger.filter_level(level);
+    }
+    logger.init();
+
     match run(cli).await {
         Ok(_) => Ok(()),
         Err(e) => {
             eprintln!("Error: {}", e);
-            std::process::exit(1);
+            let code = if let Some(failed) = e.downcast_ref::<hook::HookFailed>() {
+                failed.code
+            } else if let Some(interrupted) = e.downcast_ref::<signals::Interrupted>() {
+                interrupted.0.exit_code()
+            } else if let Some(FerroError::IpBanned { .. }) = e.downcast_ref::<FerroError>() {
+                error::IP_BANNED_EXIT_CODE
+            } else {
+                1
+            };
+            std::process::exit(code);
         }
     }
 }
 
 async fn run(cli: Cli) -> Result<()> {
-    let mut api = IsoApi::new().await?;
+    let settings = Settings::resolve(&cli);
+    let output = settings.output();
+
+    // Options the command line leaves out are filled in from the config file, if there is one
+    let cli = match &settings.config_file.value {
+        Some(path) => ConfigFile::load(path)?.apply(path, std::env::args_os().collect())?,
+        None => cli,
+    };
 
-    match cli.command {
-        Some(crate::cli::Commands::List { item_type }) => {
-            handle_list_command(item_type, &mut api).await
+    if cli.config_print {
+        return print_config(&settings, cli.command.as_ref(), &output);
+    }
+
+    if settings.redact_logs.value {
+        redact::enable();
+    }
+
+    let timings = Timings::default();
+    let mut api_config = ApiConfig {
+        http: settings.http_options(),
+        user_agent: settings.user_agent.value.clone(),
+        timings: timings.clone(),
+        print_session_ids: settings.print_session_id.value,
+        catalog: catalog::load(settings.catalog_overlay.value.as_deref()).await?,
+        referer: settings.referer.value.clone(),
+        warm_session: settings.warm_session.value,
+        profiles: settings.profiles.value.clone(),
+        dump_dir: settings.dump_dir(),
+        session_cache: settings.session_cache(),
+        request_delay: settings.request_delay.value,
+        timeout: settings.api_timeout.value,
+        query_locale: settings.query_locale.value.clone(),
+        base_url: None,
+    };
+    if settings.refresh_versions.value {
+        // Done once up front, so every client the command creates sees the same catalog
+        let mut api = IsoApi::with_config(api_config.clone()).await?;
+        api.refresh_versions().await;
+        api_config.catalog = api.catalog().to_vec();
+    }
+    signals::install()?;
+    let command = run_command(cli.command, &api_config, &output);
+    tokio::pin!(command);
+    let result = tokio::select! {
+        result = &mut command => result,
+        signal = signals::received() => {
+            // Give an in-flight download the chance to flush and record its progress
+            match tokio::time::timeout(signals::GRACE_PERIOD, &mut command).await {
+                Ok(result) => result,
+                Err(_) => Err(signals::Interrupted(signal).into()),
+            }
         }
-        Some(crate::cli::Commands::Download { options }) => {
-            handle_download_command(options, &mut api).await
+    };
+
+    if settings.trace_timings.value {
+        eprintln!("Timings: {}", timings.summary());
+    }
+
+    result
+}
+
+/// Print the effective settings for the given command, one `name = value (source)` per line
+fn print_config(
+    settings: &Settings,
+    command: Option<&ferro::cli::Commands>,
+    output: &Output,
+) -> Result<()> {
+    let mut entries = settings.entries();
+    match command {
+        Some(ferro::cli::Commands::Download { options })
+        | Some(ferro::cli::Commands::Which { options })
+        | Some(ferro::cli::Commands::Fetch { options, .. }) => {
+            entries.extend(DownloadSettings::resolve(options).entries());
+        }
+        _ => {}
+    }
+
+    if output.is_json() {
+        return output.print_json(&entries);
+    }
+    for entry in entries {
+        println!("{} = {} ({})", entry.name, entry.value, entry.source);
+    }
+    Ok(())
+}
+
+async fn run_command(
+    command: Option<ferro::cli::Commands>,
+    api_config: &ApiConfig,
+    output: &Output,
+) -> Result<()> {
+    // Only commands that talk to Microsoft need the API client and its locale check
+    let new_api = || IsoApi::with_config(api_config.clone());
+
+    match command {
+        Some(ferro::cli::Commands::List { item_type }) => {
+            handle_list_command(item_type, &mut new_api().await?, output).await
+        }
+        Some(ferro::cli::Commands::Download { options }) => {
+            handle_download_command(options, api_config, output).await
+        }
+        Some(ferro::cli::Commands::Fetch {
+            link,
+            allow_any_host,
+            options,
+        }) => handle_fetch_command(link, allow_any_host, options, api_config, output).await,
+        Some(ferro::cli::Commands::Verify { from_manifest }) => {
+            handle_verify_command(&from_manifest).await
+        }
+        Some(ferro::cli::Commands::Which { options }) => {
+            handle_which_command(options, api_config).await
+        }
+        Some(ferro::cli::Commands::Selftest) => handle_selftest_command(api_config, output).await,
+        Some(ferro::cli::Commands::Probe {
+            version,
+            release,
+            edition,
+        }) => {
+            handle_probe_command(&version, &release, &edition, &mut new_api().await?, output).await
+        }
+        Some(ferro::cli::Commands::Completions { shell }) => {
+            let mut command = ferro::cli::completion_command(&api_config.catalog);
+            clap_complete::generate(shell, &mut command, "ferro", &mut std::io::stdout());
+            Ok(())
         }
         None => {
             // Interactive mode - for future implementation
@@ -46,58 +197,205 @@ async fn run(cli: Cli) -> Result<()> {
     }
 }
 
-async fn handle_list_command(item_type: crate::cli::ListType, api: &mut IsoApi) -> Result<()> {
+async fn handle_list_command(
+    item_type: ferro::cli::ListType,
+    api: &mut IsoApi,
+    output: &Output,
+) -> Result<()> {
     match item_type {
-        crate::cli::ListType::Versions => {
+        ferro::cli::ListType::Versions => {
             let versions = api.get_available_versions().await?;
+            if output.is_json() {
+                return output.print_json(&versions);
+            }
             println!("Available Windows versions:");
             for version in versions {
                 println!("  - {}", version.name);
             }
         }
-        crate::cli::ListType::Releases { version } => {
+        ferro::cli::ListType::Releases {
+            version: None, csv, ..
+        } => {
+            let mut all = Vec::new();
+            for version in api.get_available_versions().await? {
+                all.push(VersionReleases {
+                    releases: api.get_releases(&version.name).await?,
+                    version: version.name,
+                });
+            }
+            if csv {
+                println!("{}", ferro::output::csv_row(&["version", "release"]));
+                for version in &all {
+                    for release in &version.releases {
+                        println!(
+                            "{}",
+                            ferro::output::csv_row(&[&version.version, &release.name])
+                        );
+                    }
+                }
+                return Ok(());
+            }
+            if output.is_json() {
+                return output.print_json(&all);
+            }
+            for version in all {
+                println!("{}:", version.version);
+                for release in version.releases {
+                    println!("  - {}", release.name);
+                }
+            }
+        }
+        ferro::cli::ListType::Releases {
+            version: Some(version),
+            ..
+        } => {
             let releases = api.get_releases(&version).await?;
+            if output.is_json() {
+                return output.print_json(&releases);
+            }
             println!("Available releases for {}:", version);
             for release in releases {
                 println!("  - {}", release.name);
             }
         }
-        crate::cli::ListType::Editions { version, release } => {
+        ferro::cli::ListType::Editions { version, release } => {
             let editions = api.get_editions(&version, &release).await?;
+            if output.is_json() {
+                return output.print_json(&editions);
+            }
             println!("Available editions for {} {}:", version, release);
             for edition in editions {
                 println!("  - {}", edition.name);
             }
         }
-        crate::cli::ListType::Languages {
+        ferro::cli::ListType::Languages {
             version,
             release,
             edition,
+            language_display,
+            detailed,
         } => {
             let languages = api.get_languages(&version, &release, &edition).await?;
+            // The SKU IDs and session indexes are always part of the JSON output
+            if output.is_json() {
+                return output.print_json(&languages);
+            }
             println!(
                 "Available languages for {} {} {}:",
                 version, release, edition
             );
-            for language in languages {
-                println!("  - {} ({})", language.display_name, language.name);
+            let mut lines: Vec<(String, &WindowsLanguage)> = languages
+                .iter()
+                .map(|language| {
+                    let line = match language_display.unwrap_or_default() {
+                        LanguageDisplay::Native => {
+                            format!("{} ({})", language.display_name, language.name)
+                        }
+                        LanguageDisplay::En => {
+                            format!("{} ({})", language.name, language.display_name)
+                        }
+                    };
+                    (line, language)
+                })
+                .collect();
+            // Keep the API's order unless a display mode was asked for explicitly
+            if language_display.is_some() {
+                lines.sort_by_key(|(line, _)| line.to_lowercase());
+            }
+            for (line, language) in lines {
+                println!("  - {}", line);
+                if detailed {
+                    for data in &language.data {
+                        // A SKU listed under another language too points at a mixed-up SKU set
+                        let shared = languages.iter().any(|other| {
+                            other.name != language.name
+                                && other.data.iter().any(|d| d.sku_id == data.sku_id)
+                        });
+                        println!(
+                            "      SKU {} (session {}){}",
+                            data.sku_id,
+                            data.session_index,
+                            if shared {
+                                ", also listed for another language"
+                            } else {
+                                ""
+                            }
+                        );
+                    }
+                }
             }
         }
-        crate::cli::ListType::Architectures {
+        ferro::cli::ListType::Architectures {
             version,
             release,
             edition,
-            language,
+            language: None,
+            csv,
+            ..
         } => {
-            let architectures = api
-                .get_architectures(&version, &release, &edition, &language)
+            let matrix = api
+                .get_architecture_matrix(&version, &release, &edition)
                 .await?;
+            if csv {
+                println!(
+                    "{}",
+                    ferro::output::csv_row(&["language", "display_name", "architecture", "url"])
+                );
+                for entry in &matrix {
+                    println!(
+                        "{}",
+                        ferro::output::csv_row(&[
+                            &entry.language,
+                            &entry.display_name,
+                            &entry.architecture,
+                            &entry.url,
+                        ])
+                    );
+                }
+                return Ok(());
+            }
+            if output.is_json() {
+                return output.print_json(&matrix);
+            }
+            println!(
+                "Available downloads for {} {} {}:",
+                version, release, edition
+            );
+            for entry in matrix {
+                println!(
+                    "  - {} {}: {}",
+                    entry.display_name, entry.architecture, entry.url
+                );
+            }
+        }
+        ferro::cli::ListType::Architectures {
+            version,
+            release,
+            edition,
+            language: Some(language),
+            show_urls,
+            ..
+        } => {
+            let architectures = if show_urls {
+                api.get_all_download_urls(&version, &release, &edition, &language)
+                    .await?
+            } else {
+                api.get_architectures(&version, &release, &edition, &language)
+                    .await?
+            };
+            if output.is_json() {
+                return output.print_json(&architectures);
+            }
             println!(
                 "Available architectures for {} {} {} {}:",
                 version, release, edition, language
             );
             for arch in architectures {
-                println!("  - {}", arch.name);
+                if show_urls {
+                    println!("  - {}: {}", arch.name, arch.url);
+                } else {
+                    println!("  - {}", arch.name);
+                }
             }
         }
     }
@@ -105,50 +403,1067 @@ async fn handle_list_command(item_type: crate::cli::ListType, api: &mut IsoApi)
 }
 
 async fn handle_download_command(
-    options: crate::cli::DownloadOptions,
-    api: &mut IsoApi,
+    options: ferro::cli::DownloadOptions,
+    api_config: &ApiConfig,
+    output: &Output,
 ) -> Result<()> {
+    let settings = DownloadSettings::resolve(&options);
+    // The built-in catalog ages with every Ferro release, so only check it when asked to
+    let check_catalog =
+        options.fail_if_outdated || settings.max_catalog_age.source != config::Source::Default;
+    if check_catalog && options.url.is_none() && !utils::is_uefi_shell(&settings.version.value) {
+        let today = chrono::Local::now().date_naive();
+        if let Some(message) =
+            catalog::outdated(&api_config.catalog, settings.max_catalog_age.value, today)
+        {
+            if options.fail_if_outdated {
+                anyhow::bail!(message);
+            }
+            warn!("{}", message);
+        }
+    }
+
+    if options.batch.is_some() || options.resume_queue {
+        return handle_queue(options, api_config, output).await;
+    }
+
+    if options.writes_to_stdout() {
+        check_stdout_output(&options)?;
+        // Keep stdout to the download itself, with status text on stderr
+        let quiet = Output {
+            format: OutputFormat::Json,
+            pretty: false,
+            ..*output
+        };
+        return download_single(&options, api_config, &quiet)
+            .await
+            .map(|_| ());
+    }
+    if !options.output_on_stdout_json {
+        return download_single(&options, api_config, output)
+            .await
+            .map(|_| ());
+    }
+
+    // Status text goes to stderr, leaving stdout to the one result line
+    let quiet = Output {
+        format: OutputFormat::Json,
+        pretty: false,
+        ..*output
+    };
+    let result = download_single(&options, api_config, &quiet).await;
+    let report = match &result {
+        Ok(Some(report)) => report.clone(),
+        // --get-url, --checksum-only and --dry-run conflict with --output-on-stdout-json
+        Ok(None) => return Ok(()),
+        Err(e) => DownloadReport::Error {
+            reason: format!("{:#}", e),
+        },
+    };
+    quiet.print_json_line(&report)?;
+    result.map(|_| ())
+}
+
+/// Refuse options that need the download as a file, or stdout to themselves, with
+/// `--output -`
+fn check_stdout_output(options: &ferro::cli::DownloadOptions) -> Result<()> {
+    let conflicting = [
+        (options.output.len() > 1, "more than one --output"),
+        (options.output_on_stdout_json, "--output-on-stdout-json"),
+        (options.get_url, "--get-url"),
+        (options.checksum_only, "--checksum-only"),
+        (options.dry_run, "--dry-run"),
+        (
+            !options.sidecar_algos().is_empty(),
+            "--checksum-algo or --write-checksum",
+        ),
+        (options.validate_iso, "--validate-iso"),
+        (options.write_manifest, "--write-manifest"),
+        (options.if_newer, "--if-newer"),
+        (options.on_complete.is_some(), "--on-complete"),
+    ];
+    if let Some((_, option)) = conflicting.iter().find(|(given, _)| *given) {
+        anyhow::bail!("--output - can't be combined with {}", option);
+    }
+    Ok(())
+}
+
+/// `ferro fetch`: a download of a known link, which never needs the API
+async fn handle_fetch_command(
+    link: String,
+    allow_any_host: bool,
+    mut options: ferro::cli::DownloadOptions,
+    api_config: &ApiConfig,
+    output: &Output,
+) -> Result<()> {
+    if options.url.is_some()
+        || options.batch.is_some()
+        || options.resume_queue
+        || options.selection_query() != SelectionQuery::default()
+    {
+        anyhow::bail!(
+            "fetch only downloads the link it's given; use `ferro download` to pick a selection"
+        );
+    }
+    if !allow_any_host && !utils::is_microsoft_url(&link) {
+        anyhow::bail!(
+            "{} is not a Microsoft download link; pass --allow-any-host to download it anyway",
+            redact(&link)
+        );
+    }
+    options.url = Some(link);
+    handle_download_command(options, api_config, output).await
+}
+
+/// Download a single selection or URL. Returns `None` when the command prints its own
+/// result instead, as with `--get-url`, `--checksum-only` or `--dry-run`.
+async fn download_single(
+    options: &ferro::cli::DownloadOptions,
+    api_config: &ApiConfig,
+    output: &Output,
+) -> Result<Option<DownloadReport>> {
+    let started = std::time::Instant::now();
+
+    // Checking a file against a digest we were given needs nothing from the API
+    if options.checksum_only && (options.sha256.is_some() || options.sha1.is_some()) {
+        if options.output.is_empty() {
+            anyhow::bail!("Pass the file to check with --output");
+        }
+        check_existing_files(options, &options.output, None).await?;
+        return Ok(None);
+    }
+
     info!("Starting download process...");
 
-    // Resolve defaults if not specified
-    let version = options.version.unwrap_or_else(|| "Windows 11".to_string());
-    let release = if let Some(r) = options.release {
-        r
+    let (selection, download_url, published_sha256) = match &options.url {
+        // A URL we already have doesn't need the API, or its locale check, at all
+        Some(url) => {
+            check_link_expiry(url)?;
+            (None, url.clone(), None)
+        }
+        None => {
+            let mut api = IsoApi::with_config(api_config.clone()).await?;
+            let selection = resolve_selection(&options.selection_query(), &mut api).await?;
+
+            output.status(format!("Selected: {}", selection));
+
+            let download_url = api
+                .get_download_url(
+                    &selection.version,
+                    &selection.release,
+                    &selection.edition,
+                    &selection.language,
+                    &selection.architecture,
+                )
+                .await?;
+            let published_sha256 = published_sha256(&api, &download_url);
+            (Some(selection), download_url, published_sha256)
+        }
+    };
+    require_official(options, &download_url, published_sha256.as_deref())?;
+
+    if options.get_url {
+        // --get-url can't be combined with --url, so there always is a selection
+        match selection {
+            Some(selection) if output.is_json() => output.print_json(&ResolvedUrl {
+                url: download_url,
+                sha256: published_sha256,
+                selection,
+            })?,
+            _ => println!("{}", download_url),
+        }
+        return Ok(None);
+    }
+
+    // Standard output can't be read back, so the digest for the report is taken in-stream
+    let to_stdout = options.writes_to_stdout();
+    let downloader = new_downloader(
+        options,
+        api_config,
+        output,
+        options.output_on_stdout_json || to_stdout,
+    );
+    let output_paths = if options.output.is_empty() {
+        vec![default_output_path(&downloader, selection.as_ref(), &download_url, options).await?]
+    } else {
+        options.output.clone()
+    };
+
+    if options.checksum_only {
+        check_existing_files(options, &output_paths, published_sha256).await?;
+        return Ok(None);
+    }
+    if options.dry_run {
+        let plan = DryRun {
+            size: downloader.get_file_size(&download_url).await?,
+            url: download_url,
+            outputs: match options.compress {
+                Some(compression) => output_paths
+                    .iter()
+                    .map(|path| compression.compressed_path(path))
+                    .collect(),
+                None => output_paths,
+            },
+            selection,
+        };
+        print_dry_run(&plan, output)?;
+        return Ok(None);
+    }
+    if options.output.is_empty() {
+        create_parent_dir(&output_paths[0]).await?;
+    }
+
+    let downloaded = match previous_remote_version(options, &output_paths).await {
+        Some(previous) => {
+            match downloader
+                .download_if_changed(&download_url, &output_paths, &previous)
+                .await?
+            {
+                Fetched::Downloaded(downloaded) => downloaded,
+                Fetched::NotModified => {
+                    for output_path in &output_paths {
+                        output.status(format!("Up to date, skipped: {}", output_path.display()));
+                    }
+                    return Ok(Some(DownloadReport::Skipped {
+                        path: output_paths[0].clone(),
+                        reason: "not modified since the last download".to_string(),
+                    }));
+                }
+            }
+        }
+        None => {
+            downloader
+                .download_to_many(&download_url, &output_paths)
+                .await?
+        }
+    };
+
+    let sha256 = finish_download(
+        options,
+        selection.as_ref(),
+        &download_url,
+        published_sha256,
+        &downloaded,
+        output,
+    )
+    .await?;
+
+    let path = downloaded.paths.first().context("No output was written")?;
+    Ok(Some(DownloadReport::Ok {
+        path: path.clone(),
+        size: downloaded.size,
+        sha256,
+        duration_ms: started.elapsed().as_millis(),
+        url: redact(&download_url).into_owned(),
+        selection,
+    }))
+}
+
+/// Refuse a signed link that has already expired, which would otherwise fail with a 403
+/// once the download starts. There is no selection to resolve a fresh one from.
+fn check_link_expiry(url: &str) -> Result<()> {
+    let Some(expiry) = utils::link_expiry(url) else {
+        return Ok(());
+    };
+    let now = chrono::Utc::now();
+    if expiry <= now {
+        anyhow::bail!(
+            "The download link expired at {}; resolve a fresh one with `ferro download --get-url`",
+            expiry
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+        );
+    }
+    if utils::link_expires_soon(url, now) {
+        warn!(
+            "The download link expires in {} minutes, a slow or resumed download may fail",
+            (expiry - now).num_minutes()
+        );
+    }
+    Ok(())
+}
+
+/// Show what `--dry-run` found in place of downloading it
+fn print_dry_run(plan: &DryRun, output: &Output) -> Result<()> {
+    if output.is_json() {
+        return output.print_json(plan);
+    }
+    println!("URL: {}", plan.url);
+    for path in &plan.outputs {
+        println!("Output: {}", path.display());
+    }
+    println!(
+        "Size: {}",
+        plan.size
+            .map_or("unknown".to_string(), utils::bytes_to_human_readable)
+    );
+    output.status("Dry run, nothing was downloaded");
+    Ok(())
+}
+
+/// With `--if-newer`, the remote version recorded when the outputs were last downloaded.
+/// Every output must still exist, otherwise there is nothing to keep up to date.
+async fn previous_remote_version(
+    options: &ferro::cli::DownloadOptions,
+    output_paths: &[PathBuf],
+) -> Option<RemoteVersion> {
+    if !options.if_newer {
+        return None;
+    }
+
+    let first = output_paths.first()?;
+    if !output_paths.iter().all(|path| path.exists()) {
+        info!("Output missing, downloading");
+        return None;
+    }
+    match Manifest::load_for(first).await {
+        Ok(manifest) if !manifest.remote.is_empty() => Some(manifest.remote),
+        Ok(_) => {
+            warn!("Manifest has no ETag or Last-Modified to compare, downloading");
+            None
+        }
+        Err(e) => {
+            warn!("{}, downloading", e);
+            None
+        }
+    }
+}
+
+/// Report a finished download, then verify it and run the manifest and hook steps.
+/// Returns the file's SHA-256 if it was computed along the way.
+async fn finish_download(
+    options: &ferro::cli::DownloadOptions,
+    selection: Option<&Selection>,
+    download_url: &str,
+    published_sha256: Option<String>,
+    downloaded: &Downloaded,
+    output: &Output,
+) -> Result<Option<String>> {
+    let written = &downloaded.paths;
+    for output_path in written {
+        output.completed(output_path);
+    }
+    let output_path = written.first().context("No output was written")?;
+
+    let sidecar_algos = options.sidecar_algos();
+    let requested = downloaded
+        .checksums
+        .iter()
+        .filter(|(algo, _)| sidecar_algos.contains(algo));
+    for (algo, digest) in requested {
+        output.status(format!("{}: {}", algo, digest));
+        for output_path in written {
+            // Checksums describe the ISO, so with --compress they name the decompressed file
+            let checked_path = match options.compress {
+                Some(compression) => compression.decompressed_path(output_path),
+                None => output_path.clone(),
+            };
+            let sidecar = checksum::write_sidecar(&checked_path, *algo, digest).await?;
+            info!("Checksum written: {}", sidecar.display());
+        }
+    }
+
+    // Reuse a SHA-256 computed during the download instead of reading the file again
+    let mut sha256 = digest_of(&downloaded.checksums, ChecksumAlgo::Sha256).map(str::to_string);
+
+    let expected = expected_digests(options, published_sha256);
+    if expected.is_empty() {
+        debug!("No published SHA-256 for this download, skipping verification");
+    } else {
+        // Hash the file again only for digests that weren't computed while downloading
+        let mut digests = downloaded.checksums.clone();
+        let missing: Vec<ChecksumAlgo> = expected
+            .iter()
+            .map(|expected| expected.algo)
+            .filter(|algo| !digests.iter().any(|(computed, _)| computed == algo))
+            .collect();
+        if !missing.is_empty() {
+            digests.extend(checksum::hash_file(output_path, &missing).await?);
+        }
+
+        for expected in &expected {
+            let digest = digest_of(&digests, expected.algo).unwrap_or_default();
+            if !digest.eq_ignore_ascii_case(&expected.digest) {
+                return Err(anyhow::anyhow!(
+                    "Checksum mismatch for {}: {} {} {}, got {}",
+                    output_path.display(),
+                    expected.origin,
+                    expected.algo,
+                    expected.digest,
+                    digest
+                ));
+            }
+            output.status(format!(
+                "Verified against the {} {}: {}",
+                expected.origin, expected.algo, digest
+            ));
+        }
+        sha256 = digest_of(&digests, ChecksumAlgo::Sha256).map(str::to_string);
+    }
+
+    if options.validate_iso {
+        let label = iso9660::volume_label(output_path).await?;
+        output.status(format!("Volume label: {}", label));
+        // Only Windows labels name the architecture, e.g. CCCOMA_X64FRE_EN-US_DV9
+        let found = iso9660::label_architecture(&label);
+        if let (Some(found), Some(selection)) = (found, selection) {
+            if !found.eq_ignore_ascii_case(&selection.architecture) {
+                return Err(anyhow::anyhow!(
+                    "{} is an {} image (volume label {}), but {} was selected",
+                    output_path.display(),
+                    found,
+                    label,
+                    selection.architecture
+                ));
+            }
+        }
+    }
+
+    if options.write_manifest || options.if_newer {
+        let digest = match sha256 {
+            Some(digest) => digest,
+            None => checksum::sha256_file(output_path).await?,
+        };
+        for output_path in written {
+            let manifest = Manifest {
+                file: output_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size: downloaded.size,
+                sha256: Some(digest.clone()),
+                url: download_url.to_string(),
+                selection: selection.cloned(),
+                created: chrono::Utc::now(),
+                remote: downloaded.remote.clone(),
+            };
+            let manifest_path = manifest.save_for(output_path).await?;
+            info!("Manifest written: {}", manifest_path.display());
+        }
+        sha256 = Some(digest);
+    }
+
+    if let Some(command) = &options.on_complete {
+        hook::run_post_download_hook(command, output_path, sha256.clone(), downloaded.size).await?;
+    }
+
+    Ok(sha256)
+}
+
+/// Work through a `--batch` job file, or the saved queue with `--resume-queue`.
+/// The queue is saved after every step so an interrupted run can pick up where it
+/// stopped; items already done are skipped and failed ones are retried.
+async fn handle_queue(
+    options: ferro::cli::DownloadOptions,
+    api_config: &ApiConfig,
+    output: &Output,
+) -> Result<()> {
+    let state_path = Queue::default_path()?;
+
+    let mut queue = match &options.batch {
+        Some(job_file) => {
+            if let Some(existing) = Queue::load(&state_path).await? {
+                if !existing.is_finished() {
+                    let remaining = existing.items.len() - existing.count(ItemStatus::Done);
+                    if !options.clear_queue {
+                        anyhow::bail!(
+                            "A saved queue still has {} unfinished job(s); finish it with --resume-queue, or pass --clear-queue to replace it",
+                            remaining
+                        );
+                    }
+                    warn!(
+                        "Replacing an unfinished queue with {} remaining item(s)",
+                        remaining
+                    );
+                }
+            }
+            let queue = Queue::from_job_file(job_file).await?;
+            queue.save(&state_path).await?;
+            queue
+        }
+        None => Queue::load(&state_path).await?.with_context(|| {
+            format!(
+                "No saved queue to resume (expected {})",
+                state_path.display()
+            )
+        })?,
+    };
+
+    let total = queue.items.len();
+    if queue.count(ItemStatus::Done) < total {
+        // The JSON Lines records always carry the SHA-256, so compute it while downloading
+        let downloader = new_downloader(&options, api_config, output, output.is_json());
+        let mut api = IsoApi::with_config(api_config.clone()).await?;
+        let mut run = QueueRun {
+            queue: &mut queue,
+            state_path: &state_path,
+            api: &mut api,
+            downloader: &downloader,
+        };
+
+        // Download URLs already resolved for the estimate, so no job asks for its links twice
+        let urls = estimate_queue(&mut run, output).await?;
+        if !options.yes && !confirm("Start the download?")? {
+            output.status("Cancelled; rerun with --resume-queue to start later");
+            return Ok(());
+        }
+
+        for (index, url) in urls.into_iter().enumerate() {
+            if run.queue.items[index].status == ItemStatus::Done {
+                continue;
+            }
+            output.status(format!("[{}/{}]", index + 1, total));
+
+            run.queue.items[index].status = ItemStatus::InProgress;
+            run.queue.save(run.state_path).await?;
+
+            let result = run_queue_item(&mut run, index, url, &options, output).await;
+            let item = &mut run.queue.items[index];
+            let report = match result {
+                Ok(report) => {
+                    item.status = ItemStatus::Done;
+                    item.error = None;
+                    report
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    item.status = ItemStatus::Failed;
+                    item.error = Some(e.to_string());
+                    ItemReport::Error {
+                        selection: item.selection.clone(),
+                        reason: format!("{:#}", e),
+                    }
+                }
+            };
+            run.queue.save(run.state_path).await?;
+            if output.is_json() {
+                output.print_json_line(&report)?;
+            }
+        }
+    }
+
+    let failed = queue.count(ItemStatus::Failed);
+    output.status(format!(
+        "Queue finished: {} done, {} failed",
+        queue.count(ItemStatus::Done),
+        failed
+    ));
+
+    if queue.is_finished() {
+        if let Err(e) = tokio::fs::remove_file(&state_path).await {
+            debug!("Failed to remove queue state: {}", e);
+        }
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} queued download(s) failed, rerun with --resume-queue to retry them",
+            failed
+        ))
+    }
+}
+
+/// What every job of a `--batch` run works with
+struct QueueRun<'a> {
+    queue: &'a mut Queue,
+    /// Where the queue is saved after every step
+    state_path: &'a Path,
+    api: &'a mut IsoApi,
+    downloader: &'a Downloader,
+}
+
+/// Resolve every unfinished item and add up the sizes the server reports, so a huge
+/// batch isn't started by accident. Returns the download URL of each item, where one
+/// was found.
+async fn estimate_queue(run: &mut QueueRun<'_>, output: &Output) -> Result<Vec<Option<String>>> {
+    let queue = &mut *run.queue;
+    let api = &mut *run.api;
+    let (state_path, downloader) = (run.state_path, run.downloader);
+    let mut urls = vec![None; queue.items.len()];
+    let mut files = 0;
+    for (item, slot) in queue
+        .items
+        .iter_mut()
+        .zip(urls.iter_mut())
+        .filter(|(item, _)| item.status != ItemStatus::Done)
+    {
+        files += 1;
+        let selection = match &item.selection {
+            Some(selection) => selection.clone(),
+            None => match resolve_selection(&item.job.query, api).await {
+                Ok(selection) => selection,
+                Err(e) => {
+                    warn!(
+                        "Could not resolve a queued job for the size estimate: {}",
+                        e
+                    );
+                    continue;
+                }
+            },
+        };
+        let url = batch::resolve_download_url(api, &selection, None).await;
+        if let Err(e) = &url {
+            warn!("Could not get the download URL for {}: {}", selection, e);
+        }
+        item.selection = Some(selection);
+        *slot = url.ok();
+    }
+    queue.save(state_path).await?;
+
+    let sizes: Vec<u64> = futures_util::stream::iter(urls.iter().flatten())
+        .map(|url| async move { downloader.get_file_size(url).await.ok().flatten() })
+        .buffer_unordered(4)
+        .filter_map(|size| async move { size })
+        .collect()
+        .await;
+
+    let total: u64 = sizes.iter().sum();
+    let unknown = files - sizes.len();
+    if unknown == 0 {
+        output.status(format!(
+            "{} files, ~{} total",
+            files,
+            utils::bytes_to_human_readable(total)
+        ));
+    } else {
+        output.status(format!(
+            "{} files, ~{} total (size of {} unknown)",
+            files,
+            utils::bytes_to_human_readable(total),
+            unknown
+        ));
+    }
+    Ok(urls)
+}
+
+/// Ask a yes/no question on the terminal; without one, insist on --yes instead of guessing
+fn confirm(question: &str) -> Result<bool> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Not running in a terminal, pass --yes to start without confirmation");
+    }
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+/// Download one queued item, using `download_url` if the estimate already resolved it
+/// and it isn't about to expire
+async fn run_queue_item(
+    run: &mut QueueRun<'_>,
+    index: usize,
+    download_url: Option<String>,
+    options: &ferro::cli::DownloadOptions,
+    output: &Output,
+) -> Result<ItemReport> {
+    let queue = &mut *run.queue;
+    let api = &mut *run.api;
+    let (state_path, downloader) = (run.state_path, run.downloader);
+    let item = &queue.items[index];
+    let selection = match &item.selection {
+        Some(selection) => selection.clone(),
+        None => resolve_selection(&item.job.query, api).await?,
+    };
+
+    output.status(format!("Selected: {}", selection));
+
+    // Earlier jobs may have taken hours, long enough for a link from the estimate to expire
+    let download_url = batch::resolve_download_url(api, &selection, download_url).await?;
+    let published_sha256 = published_sha256(api, &download_url);
+    require_official(options, &download_url, published_sha256.as_deref())?;
+
+    // Keep the output stable across resumes, so a partial download is picked up again
+    let output_path = match (&item.output, &item.job.output) {
+        (Some(output), _) | (None, Some(output)) => output.clone(),
+        (None, None) => {
+            let path =
+                default_output_path(downloader, Some(&selection), &download_url, options).await?;
+            create_parent_dir(&path).await?;
+            path
+        }
+    };
+
+    let item = &mut queue.items[index];
+    item.selection = Some(selection.clone());
+    item.output = Some(output_path.clone());
+    queue.save(state_path).await?;
+
+    let downloaded = downloader
+        .download_to_many(&download_url, &[output_path])
+        .await?;
+
+    let sha256 = finish_download(
+        options,
+        Some(&selection),
+        &download_url,
+        published_sha256,
+        &downloaded,
+        output,
+    )
+    .await?;
+
+    let path = downloaded.paths.first().context("No output was written")?;
+    Ok(ItemReport::Ok {
+        selection,
+        path: path.clone(),
+        sha256,
+        bytes: downloaded.size,
+    })
+}
+
+/// Compare files that are already on disk against the published SHA-256,
+/// without downloading anything
+async fn check_existing_files(
+    options: &ferro::cli::DownloadOptions,
+    paths: &[PathBuf],
+    published_sha256: Option<String>,
+) -> Result<()> {
+    let expected = expected_digests(options, published_sha256);
+    let buffer_size = DownloadSettings::resolve(options).hash_buffer.value as usize;
+    // The SHA-256 is always reported, so there is something to compare by hand
+    let mut algos = vec![ChecksumAlgo::Sha256];
+    algos.extend(expected.iter().map(|expected| expected.algo));
+
+    let mut mismatches = 0;
+    for path in paths {
+        let digests = checksum::hash_file_buffered(path, &algos, buffer_size).await?;
+        if expected.is_empty() {
+            for (algo, digest) in &digests {
+                println!("{}: {} {}", path.display(), algo, digest);
+            }
+        }
+        for expected in &expected {
+            let actual = digest_of(&digests, expected.algo).unwrap_or_default();
+            if actual.eq_ignore_ascii_case(&expected.digest) {
+                println!("OK: {} ({} {})", path.display(), expected.algo, actual);
+            } else {
+                eprintln!(
+                    "MISMATCH: {} (expected {} {}, got {})",
+                    path.display(),
+                    expected.algo,
+                    expected.digest,
+                    actual
+                );
+                mismatches += 1;
+            }
+        }
+    }
+
+    match expected.as_slice() {
+        [] => Err(anyhow::anyhow!(
+            "No SHA-256 was published for this selection, nothing to compare against; pass --sha256 or --sha1"
+        )),
+        _ if mismatches > 0 => Err(anyhow::anyhow!(
+            "{} checksum(s) do not match the {} digest",
+            mismatches,
+            expected[0].origin
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A digest a download or an existing file must match
+struct ExpectedDigest {
+    algo: ChecksumAlgo,
+    digest: String,
+    /// "given" on the command line, or "published" by the download source
+    origin: &'static str,
+}
+
+/// Digests to check against: those given with `--sha256`/`--sha1`, and the published
+/// SHA-256 unless `--sha256` overrides it
+fn expected_digests(
+    options: &ferro::cli::DownloadOptions,
+    published_sha256: Option<String>,
+) -> Vec<ExpectedDigest> {
+    let mut expected = Vec::new();
+    match (&options.sha256, published_sha256) {
+        (Some(given), published) => {
+            if published.is_some_and(|published| !published.eq_ignore_ascii_case(given)) {
+                warn!("The given SHA-256 differs from the published one, checking against the given one");
+            }
+            expected.push(ExpectedDigest {
+                algo: ChecksumAlgo::Sha256,
+                digest: given.clone(),
+                origin: "given",
+            });
+        }
+        (None, Some(published)) => expected.push(ExpectedDigest {
+            algo: ChecksumAlgo::Sha256,
+            digest: published,
+            origin: "published",
+        }),
+        (None, None) => {}
+    }
+    if let Some(given) = &options.sha1 {
+        expected.push(ExpectedDigest {
+            algo: ChecksumAlgo::Sha1,
+            digest: given.clone(),
+            origin: "given",
+        });
+    }
+    expected
+}
+
+fn digest_of(digests: &[(ChecksumAlgo, String)], algo: ChecksumAlgo) -> Option<&str> {
+    digests
+        .iter()
+        .find(|(computed, _)| *computed == algo)
+        .map(|(_, digest)| digest.as_str())
+}
+
+/// SHA-256 published for the file behind a download URL, if any
+fn published_sha256(api: &IsoApi, download_url: &str) -> Option<String> {
+    utils::extract_filename_from_url(download_url)
+        .and_then(|file_name| api.published_sha256(&file_name).map(str::to_string))
+}
+
+/// With `--verify-official`, refuse to download what can't be checked against a published
+/// SHA-256. A published one is always checked by `finish_download`.
+fn require_official(
+    options: &ferro::cli::DownloadOptions,
+    download_url: &str,
+    published_sha256: Option<&str>,
+) -> Result<()> {
+    if options.verify_official && published_sha256.is_none() {
+        anyhow::bail!(
+            "No official SHA-256 is published for {}; drop --verify-official to download it unverified",
+            utils::extract_filename_from_url(download_url).unwrap_or_else(|| redact(download_url).into_owned())
+        );
+    }
+    Ok(())
+}
+
+async fn handle_verify_command(iso_path: &Path) -> Result<()> {
+    let manifest = Manifest::load_for(iso_path).await?;
+    let expected = manifest.sha256.with_context(|| {
+        format!(
+            "Manifest {} does not record a SHA-256",
+            Manifest::path_for(iso_path).display()
+        )
+    })?;
+
+    let actual = checksum::sha256_file(iso_path).await?;
+    if !actual.eq_ignore_ascii_case(&expected) {
+        return Err(anyhow::anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            iso_path.display(),
+            expected,
+            actual
+        ));
+    }
+
+    println!("OK: {} (SHA-256 {})", iso_path.display(), actual);
+    Ok(())
+}
+
+async fn handle_probe_command(
+    version: &str,
+    release: &str,
+    edition: &str,
+    api: &mut IsoApi,
+    output: &Output,
+) -> Result<()> {
+    let entries = api
+        .probe(version, release, edition, |probed, total, entry| {
+            output.status(format!(
+                "[{}/{}] {}: {}",
+                probed, total, entry.display_name, entry.status
+            ));
+        })
+        .await?;
+    if output.is_json() {
+        return output.print_json(&entries);
+    }
+
+    // One column per architecture any language offers
+    let mut architectures: Vec<&str> = Vec::new();
+    for entry in &entries {
+        for architecture in &entry.architectures {
+            if !architectures.contains(&architecture.as_str()) {
+                architectures.push(architecture);
+            }
+        }
+    }
+    architectures.sort();
+
+    let width = entries
+        .iter()
+        .map(|entry| entry.display_name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Language".len());
+    let mut header = format!("{:<width$}  {:<9}", "Language", "Status");
+    for architecture in &architectures {
+        header.push_str(&format!("  {:<6}", architecture));
+    }
+    println!("{}", header.trim_end());
+    for entry in &entries {
+        let mut row = format!("{:<width$}  {:<9}", entry.display_name, entry.status);
+        for architecture in &architectures {
+            let cell = if entry.architectures.iter().any(|a| a == architecture) {
+                "yes"
+            } else if entry.status == ProbeStatus::Reachable {
+                "-"
+            } else {
+                "?"
+            };
+            row.push_str(&format!("  {:<6}", cell));
+        }
+        println!("{}", row.trim_end());
+    }
+
+    for entry in &entries {
+        if let Some(error) = &entry.error {
+            eprintln!("{}: {}", entry.display_name, error);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_selftest_command(api_config: &ApiConfig, output: &Output) -> Result<()> {
+    let reports = selftest::run(api_config).await;
+    let failed = reports
+        .iter()
+        .find(|report| report.status == selftest::StageStatus::Fail);
+
+    if output.is_json() {
+        output.print_json(&reports)?;
+    } else {
+        for report in &reports {
+            if report.detail.is_empty() {
+                println!("{}  {}", report.status, report.stage);
+            } else {
+                println!(
+                    "{}  {:<14}{} ({} ms)",
+                    report.status, report.stage, report.detail, report.elapsed_ms
+                );
+            }
+        }
+    }
+
+    if let Some(report) = failed {
+        anyhow::bail!("Self-test failed at the {} stage", report.stage);
+    }
+    Ok(())
+}
+
+async fn handle_which_command(
+    options: ferro::cli::DownloadOptions,
+    api_config: &ApiConfig,
+) -> Result<()> {
+    if let Some(output) = options.output.first() {
+        println!("{}", output.display());
+        return Ok(());
+    }
+
+    if let Some(url) = &options.url {
+        let downloader = new_downloader(&options, api_config, &Output::default(), false);
+        println!(
+            "{}",
+            default_output_path(&downloader, None, url, &options)
+                .await?
+                .display()
+        );
+        return Ok(());
+    }
+
+    let mut api = IsoApi::with_config(api_config.clone()).await?;
+    let selection = resolve_selection(&options.selection_query(), &mut api).await?;
+
+    // UEFI Shell URLs follow a fixed naming scheme, so there is no need to query the server
+    let download_url = if utils::is_uefi_shell(&selection.version) {
+        IsoApi::uefi_shell_iso_url(&selection.version, &selection.release, &selection.edition)
+    } else {
+        api.get_download_url(
+            &selection.version,
+            &selection.release,
+            &selection.edition,
+            &selection.language,
+            &selection.architecture,
+        )
+        .await?
+    };
+
+    let downloader = new_downloader(&options, api_config, &Output::default(), false);
+    println!(
+        "{}",
+        default_output_path(&downloader, Some(&selection), &download_url, &options)
+            .await?
+            .display()
+    );
+    Ok(())
+}
+
+/// Resolve every part of the selection, filling in defaults for anything not specified
+async fn resolve_selection(query: &SelectionQuery, api: &mut IsoApi) -> Result<Selection> {
+    // Shortened names are expanded, so the selection shows and names files by the full ones
+    let version = if let Some(v) = &query.version {
+        api.version_name(v)?
+    } else if let Some(index) = query.version_index {
+        let versions = api.get_available_versions().await?;
+        utils::pick_index(&versions, index, "version")?.name.clone()
+    } else {
+        config::DEFAULT_VERSION.to_string()
+    };
+    let release = if let Some(r) = &query.release {
+        api.release_name(&version, r)?
+    } else if let Some(index) = query.release_index {
+        let releases = api.get_releases(&version).await?;
+        utils::pick_index(&releases, index, "release")?.name.clone()
     } else {
         let releases = api.get_releases(&version).await?;
         releases.first().context("No releases found")?.name.clone()
     };
 
-    let edition = if let Some(e) = options.edition {
-        e
-    } else {
+    let edition = if let Some(id) = query.edition_id {
+        api.add_edition_id(&version, &release, id)?
+    } else if let Some(e) = &query.edition {
+        api.edition_name(&version, &release, e)?
+    } else if let Some(index) = query.edition_index {
         let editions = api.get_editions(&version, &release).await?;
-        editions.first().context("No editions found")?.name.clone()
+        utils::pick_index(&editions, index, "edition")?.name.clone()
+    } else {
+        api.default_edition(&version, &release)?
     };
 
-    let language = if let Some(l) = options.language {
-        l
+    let language = if let Some(l) = &query.language {
+        l.clone()
+    } else if let Some(index) = query.language_index {
+        let languages = api.get_languages(&version, &release, &edition).await?;
+        utils::pick_index(&languages, index, "language")?
+            .name
+            .clone()
     } else {
         let languages = api.get_languages(&version, &release, &edition).await?;
         // Try to find the best default language in order:
         // 1. System locale exact match
         // 2. English International
-        // 3. English (United States) 
+        // 3. English (United States)
         // 4. Any language starting with "en"
         // 5. First available language
         let system_locale = utils::get_system_locale();
         languages
             .iter()
             .find(|lang| lang.name.starts_with(&system_locale))
-            .or_else(|| languages.iter().find(|lang| 
-                lang.display_name.eq_ignore_ascii_case("English International") ||
-                lang.name.eq_ignore_ascii_case("en-gb")
-            ))
-            .or_else(|| languages.iter().find(|lang| 
-                lang.display_name.eq_ignore_ascii_case("English (United States)") ||
-                lang.display_name.eq_ignore_ascii_case("English") ||
-                lang.name.eq_ignore_ascii_case("en-us")
-            ))
+            .or_else(|| {
+                languages.iter().find(|lang| {
+                    lang.display_name
+                        .eq_ignore_ascii_case("English International")
+                        || lang.name.eq_ignore_ascii_case("en-gb")
+                })
+            })
+            .or_else(|| {
+                languages.iter().find(|lang| {
+                    lang.display_name
+                        .eq_ignore_ascii_case("English (United States)")
+                        || lang.display_name.eq_ignore_ascii_case("English")
+                        || lang.name.eq_ignore_ascii_case("en-us")
+                })
+            })
             .or_else(|| languages.iter().find(|lang| lang.name.starts_with("en")))
             .or_else(|| languages.first())
             .context("No languages found")?
@@ -156,57 +1471,125 @@ async fn handle_download_command(
             .clone()
     };
 
-    let architecture = if let Some(a) = options.architecture {
-        a
-    } else {
+    let architecture = if let Some(a) = query
+        .architecture
+        .as_ref()
+        .filter(|a| !a.eq_ignore_ascii_case(utils::AUTO_ARCHITECTURE))
+    {
+        a.clone()
+    } else if let Some(index) = query.architecture_index {
         let archs = api
             .get_architectures(&version, &release, &edition, &language)
             .await?;
-        let system_arch = utils::get_system_architecture();
-        // Try to find the best architecture in order:
-        // 1. System architecture exact match
-        // 2. x64 (most common)
-        // 3. First available architecture
-        archs
-            .iter()
-            .find(|arch| arch.name == system_arch)
-            .or_else(|| archs.iter().find(|arch| arch.name.eq_ignore_ascii_case("x64")))
-            .or_else(|| archs.first())
-            .context("No architectures found")?
+        utils::pick_index(&archs, index, "architecture")?
             .name
             .clone()
+    } else {
+        let archs = api
+            .get_architectures(&version, &release, &edition, &language)
+            .await?;
+        let system_arch = utils::get_system_architecture();
+        let offered: Vec<&str> = archs.iter().map(|arch| arch.name.as_str()).collect();
+        let picked = utils::pick_host_architecture(&offered, &system_arch)
+            .context("No architectures found")?;
+        if !picked.eq_ignore_ascii_case(&system_arch) {
+            // Shown without -v, since the download won't run natively on this host
+            eprintln!(
+                "No {} image is offered for this {} host, downloading {} instead (offered: {})",
+                system_arch,
+                system_arch,
+                picked,
+                offered.join(", ")
+            );
+        }
+        picked.to_string()
     };
 
-    println!(
-        "Selected: {} {} {} {} {}",
-        version, release, edition, language, architecture
-    );
-
-    let download_url = api
-        .get_download_url(&version, &release, &edition, &language, &architecture)
-        .await?;
+    Ok(Selection {
+        version,
+        release,
+        edition,
+        language,
+        architecture,
+    })
+}
 
-    if options.get_url {
-        println!("{}", download_url);
-        return Ok(());
+/// Build the downloader for a download command. With `always_sha256`, the SHA-256 is
+/// computed while downloading even if `--checksum-algo` doesn't ask for it.
+fn new_downloader(
+    options: &ferro::cli::DownloadOptions,
+    api_config: &ApiConfig,
+    output: &Output,
+    always_sha256: bool,
+) -> Downloader {
+    let settings = DownloadSettings::resolve(options);
+    let mut checksum_algos = options.sidecar_algos();
+    // A compressed file can't be hashed afterwards, so verification needs the in-stream digest.
+    // With --sha256 the digest is checked anyway, and hashing in-stream saves reading the file again.
+    let always_sha256 = always_sha256 || options.compress.is_some() || options.sha256.is_some();
+    if always_sha256 && !checksum_algos.contains(&ChecksumAlgo::Sha256) {
+        checksum_algos.push(ChecksumAlgo::Sha256);
     }
+    if options.sha1.is_some() && !checksum_algos.contains(&ChecksumAlgo::Sha1) {
+        checksum_algos.push(ChecksumAlgo::Sha1);
+    }
+    Downloader::with_config(DownloaderConfig {
+        mode: settings.mode.value,
+        connections: settings.connections.value.into(),
+        http: api_config.http.clone(),
+        timings: api_config.timings.clone(),
+        space_margin: settings.space_margin.value,
+        force: options.force,
+        tolerate_output_errors: options.tolerate_output_errors,
+        checksum_algos,
+        // --quiet hides the progress bar too, unless a format is asked for
+        progress_format: match settings.progress_format.source {
+            config::Source::Default if output.quiet => ProgressFormat::None,
+            _ => settings.progress_format.value,
+        },
+        stall_timeout: settings.stall_timeout.value,
+        read_timeout: settings.download_timeout.value,
+        max_retries: settings.max_retries.value,
+        limit_rate: settings.limit_rate.value,
+        compression: options.compress,
+        clobber_part: options.clobber_part,
+    })
+}
 
-    let downloader = Downloader::new();
-    let output_path = options.output.unwrap_or_else(|| {
-        let filename = utils::extract_filename_from_url(&download_url).unwrap_or_else(|| {
-            format!(
-                "{}_{}_{}_{}.iso",
-                version.replace(" ", ""),
-                release,
-                language,
-                architecture
-            )
-        });
-        PathBuf::from(filename)
-    });
-
-    downloader.download(&download_url, &output_path).await?;
+/// Path used when no output path is given: `FERRO_OUTPUT` rendered for the download if it's
+/// set, the default filename otherwise. Direct URLs have no selection to fall back on, so
+/// their name comes from the URL or the server's Content-Disposition.
+async fn default_output_path(
+    downloader: &Downloader,
+    selection: Option<&Selection>,
+    download_url: &str,
+    options: &ferro::cli::DownloadOptions,
+) -> Result<PathBuf> {
+    let name = match selection {
+        Some(selection) => selection.default_filename(download_url),
+        None => downloader
+            .remote_filename(download_url)
+            .await?
+            .context("Could not determine a filename from the URL, pass --output")?,
+    };
+    let settings = DownloadSettings::resolve(options);
+    let max_length = settings.max_filename_length.value.into();
+    let name = utils::sanitize_filename(&name, max_length);
+    if let Some(dir) = &options.output_dir {
+        return Ok(dir.join(name));
+    }
+    match &settings.output_template.value {
+        Some(template) => output_template::render(template, selection, &name, max_length),
+        None => Ok(PathBuf::from(name)),
+    }
+}
 
-    println!("Download completed: {}", output_path.display());
-    Ok(())
+/// Create the directories `--output-dir` or a `FERRO_OUTPUT` layout puts a download in
+async fn create_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create directory: {}", parent.display())),
+        _ => Ok(()),
+    }
 }
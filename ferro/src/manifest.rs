@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::downloader::RemoteVersion;
+use crate::types::Selection;
+
+/// Record of a completed download, stored next to the ISO as `<file>.manifest.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// File name of the ISO the manifest describes
+    pub file: String,
+    /// Bytes downloaded, before any compression
+    pub size: u64,
+    pub sha256: Option<String>,
+    /// URL the file was downloaded from
+    pub url: String,
+    /// Resolved selection, absent for downloads from a direct URL
+    pub selection: Option<Selection>,
+    pub created: DateTime<Utc>,
+    /// ETag and Last-Modified of the download, used by `--if-newer`
+    #[serde(default, flatten)]
+    pub remote: RemoteVersion,
+}
+
+impl Manifest {
+    /// Path of the manifest that belongs to `iso_path`
+    pub fn path_for(iso_path: &Path) -> PathBuf {
+        let mut os_string = iso_path.as_os_str().to_owned();
+        os_string.push(".manifest.json");
+        PathBuf::from(os_string)
+    }
+
+    pub async fn load_for(iso_path: &Path) -> Result<Self> {
+        let path = Self::path_for(iso_path);
+        let contents = tokio::fs::read_to_string(&path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                anyhow!(
+                    "No manifest found for {} (expected {})",
+                    iso_path.display(),
+                    path.display()
+                )
+            } else {
+                anyhow!("Failed to read manifest {}: {}", path.display(), e)
+            }
+        })?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse manifest: {}", path.display()))
+    }
+
+    pub async fn save_for(&self, iso_path: &Path) -> Result<PathBuf> {
+        let path = Self::path_for(iso_path);
+        let contents = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to write manifest: {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_path() {
+        assert_eq!(
+            Manifest::path_for(Path::new("isos/Win11_24H2_x64.iso")),
+            PathBuf::from("isos/Win11_24H2_x64.iso.manifest.json")
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+
+/// How command results are printed to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON, using the field names of the underlying types
+    Json,
+}
+
+/// Output settings shared by every command that prints results
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub format: OutputFormat,
+    /// Indent JSON for reading rather than emitting it on a single line
+    pub pretty: bool,
+    /// Print no status lines, only the downloaded file's path
+    pub quiet: bool,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Text,
+            pretty: true,
+            quiet: false,
+        }
+    }
+}
+
+impl Output {
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// Serialize `value` according to the pretty/compact setting
+    pub fn render_json<T: Serialize + ?Sized>(&self, value: &T) -> Result<String> {
+        Ok(if self.pretty {
+            serde_json::to_string_pretty(value)?
+        } else {
+            serde_json::to_string(value)?
+        })
+    }
+
+    pub fn print_json<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        println!("{}", self.render_json(value)?);
+        Ok(())
+    }
+
+    /// Report a downloaded file; with --quiet, as its bare path so scripts can use it
+    pub fn completed(&self, path: &Path) {
+        if self.quiet && !self.is_json() {
+            println!("{}", path.display());
+        } else {
+            self.status(format!("Download completed: {}", path.display()));
+        }
+    }
+
+    /// Print `value` on a single line regardless of the pretty setting, as one record
+    /// of a JSON Lines stream
+    pub fn print_json_line<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        println!("{}", serde_json::to_string(value)?);
+        Ok(())
+    }
+
+    /// Print a human-readable status line. In JSON mode it goes to stderr, so stdout
+    /// carries nothing but JSON.
+    pub fn status(&self, line: impl std::fmt::Display) {
+        if self.quiet {
+            return;
+        }
+        if self.is_json() {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// One line of CSV, quoting the fields that need it (RFC 4180)
+pub fn csv_row(fields: &[&str]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json_pretty_and_compact() {
+        let value = serde_json::json!({"name": "Windows 11"});
+        let compact = Output {
+            format: OutputFormat::Json,
+            pretty: false,
+            quiet: false,
+        };
+        assert_eq!(
+            compact.render_json(&value).unwrap(),
+            r#"{"name":"Windows 11"}"#
+        );
+        assert_eq!(
+            Output::default().render_json(&value).unwrap(),
+            "{\n  \"name\": \"Windows 11\"\n}"
+        );
+    }
+
+    #[test]
+    fn test_csv_row_quotes() {
+        assert_eq!(
+            csv_row(&["en-us", "English (United States)", "x64"]),
+            "en-us,English (United States),x64"
+        );
+        assert_eq!(
+            csv_row(&["Chinese, Simplified", "say \"hi\""]),
+            "\"Chinese, Simplified\",\"say \"\"hi\"\"\""
+        );
+    }
+}
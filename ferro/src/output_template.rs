@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+
+use crate::types::Selection;
+use crate::utils;
+
+/// Environment variable holding the default output path template
+pub const ENV_VAR: &str = "FERRO_OUTPUT";
+
+/// Placeholders a template may use, for error messages
+const PLACEHOLDERS: &str = "{version}, {release}, {edition}, {language}, {arch}, {filename}";
+
+/// Render an output path template such as `~/isos/{version}/{release}/{language}_{arch}.iso`.
+///
+/// Placeholders are replaced with the selection's values, each made safe as a single path
+/// component, and `{filename}` with the name Ferro would otherwise use. `$VAR` and `${VAR}`
+/// are replaced with environment variables, and a leading `~` with the home directory.
+pub fn render(
+    template: &str,
+    selection: Option<&Selection>,
+    filename: &str,
+    max_length: usize,
+) -> Result<PathBuf> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '$']) {
+        rendered.push_str(&rest[..start]);
+        let (value, after) = if let Some(tail) = rest[start..].strip_prefix('{') {
+            let (name, after) = braced(tail, template)?;
+            let value = placeholder(name, selection, filename)?;
+            (utils::sanitize_filename(&value, max_length), after)
+        } else {
+            let tail = &rest[start + 1..];
+            let (name, after) = match tail.strip_prefix('{') {
+                Some(tail) => braced(tail, template)?,
+                None => {
+                    let end = tail
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(tail.len());
+                    tail.split_at(end)
+                }
+            };
+            let value = if name.is_empty() {
+                "$".to_string()
+            } else {
+                std::env::var(name)
+                    .with_context(|| format!("{} refers to ${}, which is not set", ENV_VAR, name))?
+            };
+            (value, after)
+        };
+        rendered.push_str(&value);
+        rest = after;
+    }
+    rendered.push_str(rest);
+    expand_tilde(&rendered)
+}
+
+/// Split `name}rest` at the closing brace
+fn braced<'a>(tail: &'a str, template: &str) -> Result<(&'a str, &'a str)> {
+    let end = tail
+        .find('}')
+        .ok_or_else(|| anyhow!("Unclosed '{{' in {}: {}", ENV_VAR, template))?;
+    Ok((&tail[..end], &tail[end + 1..]))
+}
+
+fn placeholder(name: &str, selection: Option<&Selection>, filename: &str) -> Result<String> {
+    if name == "filename" {
+        return Ok(filename.to_string());
+    }
+    let selection = || {
+        selection.ok_or_else(|| {
+            anyhow!(
+                "{} uses {{{}}}, but a --url download has no selection; pass --output",
+                ENV_VAR,
+                name
+            )
+        })
+    };
+    Ok(match name {
+        "version" => selection()?.version.clone(),
+        "release" => selection()?.release.clone(),
+        "edition" => selection()?.edition.clone(),
+        "language" => selection()?.language.clone(),
+        "arch" | "architecture" => selection()?.architecture.clone(),
+        other => {
+            return Err(anyhow!(
+                "Unknown placeholder {{{}}} in {} (supported: {})",
+                other,
+                ENV_VAR,
+                PLACEHOLDERS
+            ))
+        }
+    })
+}
+
+fn expand_tilde(path: &str) -> Result<PathBuf> {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(std::path::is_separator) => rest,
+        _ => return Ok(PathBuf::from(path)),
+    };
+    let home = dirs::home_dir().context("Could not determine the home directory for '~'")?;
+    Ok(home.join(rest.trim_start_matches(std::path::is_separator)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection() -> Selection {
+        Selection {
+            version: "Windows 11".to_string(),
+            release: "24H2".to_string(),
+            edition: "Home/Pro/Edu".to_string(),
+            language: "English".to_string(),
+            architecture: "x64".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        let path = render(
+            "isos/{version}/{release}/{edition}/{language}_{arch}.iso",
+            Some(&selection()),
+            "Win11_24H2_English_x64.iso",
+            255,
+        )
+        .unwrap();
+        assert_eq!(
+            path,
+            PathBuf::from("isos/Windows 11/24H2/Home_Pro_Edu/English_x64.iso")
+        );
+
+        let path = render("isos/{filename}", None, "a.iso", 255).unwrap();
+        assert_eq!(path, PathBuf::from("isos/a.iso"));
+    }
+
+    #[test]
+    fn test_render_expands_home_and_environment() {
+        let home = dirs::home_dir().unwrap();
+        let path = render("~/isos/{filename}", None, "a.iso", 255).unwrap();
+        assert_eq!(path, home.join("isos/a.iso"));
+
+        let path_var = std::env::var("PATH").unwrap();
+        let path = render("${PATH}/$PATH", None, "a.iso", 255).unwrap();
+        assert_eq!(path, PathBuf::from(format!("{}/{}", path_var, path_var)));
+    }
+
+    #[test]
+    fn test_render_errors() {
+        assert!(render("{version}.iso", None, "a.iso", 255).is_err());
+        assert!(render("{build}.iso", Some(&selection()), "a.iso", 255).is_err());
+        assert!(render("{version.iso", Some(&selection()), "a.iso", 255).is_err());
+        assert!(render("$FERRO_TEST_UNSET_VARIABLE/a.iso", None, "a.iso", 255).is_err());
+    }
+}
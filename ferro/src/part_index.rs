@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use crate::lock;
+use crate::segmented::{self, PartialState};
+
+/// Where unfinished segmented downloads live, keyed by their source, so a rerun with a
+/// different `--output` can pick up the partial file of an earlier attempt.
+///
+/// Only segmented modes use it: their partial file is filled out of order, so a
+/// single-stream download, which appends to its `.part`, can't continue one anyway.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartIndex {
+    /// Output path of each unfinished download, by URL without its query string
+    entries: HashMap<String, PathBuf>,
+}
+
+impl PartIndex {
+    /// `<cache dir>/ferro/partials.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("ferro").join("partials.json"))
+    }
+
+    /// Load the index, treating a missing or unreadable file as empty
+    pub async fn load(path: &Path) -> Self {
+        let Ok(contents) = tokio::fs::read_to_string(path).await else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            debug!("Ignoring unreadable partial download index: {}", e);
+            Self::default()
+        })
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, serde_json::to_string_pretty(self)?)
+            .await
+            .with_context(|| format!("Failed to write partial download index: {}", path.display()))
+    }
+
+    pub fn lookup(&self, url: &str) -> Option<&Path> {
+        self.entries
+            .get(&segmented::url_without_query(url))
+            .map(PathBuf::as_path)
+    }
+
+    pub fn record(&mut self, url: &str, output_path: &Path) {
+        self.entries
+            .insert(segmented::url_without_query(url), absolute(output_path));
+    }
+
+    pub fn remove(&mut self, url: &str) {
+        self.entries.remove(&segmented::url_without_query(url));
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Exclusive lock on the index, held from loading it to saving it again so parallel
+/// Ferro runs don't overwrite each other's entries
+struct IndexLock(File);
+
+impl IndexLock {
+    /// Wait for the lock on `index_path`, `None` if it can't be taken
+    async fn acquire(index_path: &Path) -> Option<Self> {
+        let lock_path = lock::lock_path(index_path);
+        let locked = tokio::task::spawn_blocking(move || {
+            if let Some(parent) = lock_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)?;
+            file.lock_exclusive()?;
+            Ok::<_, std::io::Error>(file)
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        match locked {
+            Ok(file) => Some(Self(file)),
+            Err(e) => {
+                debug!("Failed to lock the partial download index: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+/// Record that `output_path` holds the unfinished download of `url`
+pub async fn record(url: &str, output_path: &Path) {
+    update(|index| index.record(url, output_path)).await;
+}
+
+/// Forget the download of `url` once it's complete
+pub async fn remove(url: &str) {
+    update(|index| index.remove(url)).await;
+}
+
+/// The index is only a convenience, so failing to update it is never an error
+async fn update(change: impl FnOnce(&mut PartIndex)) {
+    let Some(path) = PartIndex::default_path() else {
+        return;
+    };
+    let Some(_lock) = IndexLock::acquire(&path).await else {
+        return;
+    };
+    let mut index = PartIndex::load(&path).await;
+    change(&mut index);
+    if let Err(e) = index.save(&path).await {
+        debug!("{:#}", e);
+    }
+}
+
+/// If an earlier attempt left a matching partial download under another output name,
+/// move its `.part` and `.part.json` next to `output_path` so the download resumes
+/// from there instead of starting over
+pub async fn adopt_previous(url: &str, total_size: u64, output_path: &Path) {
+    let Some(index_path) = PartIndex::default_path() else {
+        return;
+    };
+    let Some(_lock) = IndexLock::acquire(&index_path).await else {
+        return;
+    };
+    let mut index = PartIndex::load(&index_path).await;
+    let Some(previous) = index.lookup(url).map(Path::to_path_buf) else {
+        return;
+    };
+    if previous == absolute(output_path) {
+        return;
+    }
+
+    let previous_state = segmented::state_path(&previous);
+    match PartialState::load(&previous_state).await {
+        Some(state) if state.matches(url, total_size) => {}
+        _ => return,
+    }
+
+    let moves = [
+        (
+            segmented::part_path(&previous),
+            segmented::part_path(output_path),
+        ),
+        (previous_state, segmented::state_path(output_path)),
+    ];
+    for (from, to) in &moves {
+        // Renaming fails across filesystems; the download then simply starts over
+        if let Err(e) = tokio::fs::rename(from, to).await {
+            debug!(
+                "Could not move {} to {}: {}",
+                from.display(),
+                to.display(),
+                e
+            );
+            return;
+        }
+    }
+    info!(
+        "Reusing the partial download of {} for {}",
+        previous.display(),
+        output_path.display()
+    );
+
+    index.record(url, output_path);
+    if let Err(e) = index.save(&index_path).await {
+        debug!("{:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_ignores_signature() {
+        let mut index = PartIndex::default();
+        index.record(
+            "https://software.download.prss.microsoft.com/db/Win11.iso?t=1&e=2",
+            Path::new("/tmp/first-name.iso"),
+        );
+        assert_eq!(
+            index.lookup("https://software.download.prss.microsoft.com/db/Win11.iso?t=3&e=4"),
+            Some(Path::new("/tmp/first-name.iso"))
+        );
+
+        index.remove("https://software.download.prss.microsoft.com/db/Win11.iso");
+        assert_eq!(
+            index.lookup("https://software.download.prss.microsoft.com/db/Win11.iso"),
+            None
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::utils;
+
+/// How often the plain format prints a progress line
+const PLAIN_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the JSON format prints a progress event
+const JSON_REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How download progress is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// An in-place progress bar
+    #[default]
+    Bar,
+    /// A new line with the percentage every few seconds, for logs that can't render the bar
+    Plain,
+    /// A JSON object per line, about four a second and one when done, for frontends
+    Json,
+    /// No progress output
+    None,
+}
+
+/// Receives progress updates from a download
+pub trait ProgressObserver: Send + Sync {
+    /// Set the number of bytes downloaded so far, e.g. when resuming
+    fn set_position(&self, position: u64);
+    /// Record `delta` more bytes downloaded
+    fn inc(&self, delta: u64);
+    /// The download completed
+    fn finish(&self);
+
+    /// A segmented download is starting; `segments` holds the bytes already downloaded
+    /// and the length of each segment, by segment index
+    fn start_segments(&self, _segments: &[SegmentProgress]) {}
+
+    /// Record `delta` more bytes downloaded by one segment. Observers that don't show
+    /// segments only see the aggregate.
+    fn segment_inc(&self, _segment: usize, delta: u64) {
+        self.inc(delta);
+    }
+}
+
+/// Progress of one connection of a segmented download
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentProgress {
+    pub downloaded: u64,
+    pub len: u64,
+}
+
+pub type Progress = Arc<dyn ProgressObserver>;
+
+/// Create the observer for `format`; `total_size` is unknown when the server sends no length
+pub fn observer(format: ProgressFormat, total_size: Option<u64>) -> Progress {
+    match format {
+        ProgressFormat::Bar => match total_size {
+            Some(total_size) => Arc::new(BarProgress::new(total_size)),
+            None => {
+                log::warn!("Content-Length header not found, progress bar disabled");
+                Arc::new(NoProgress)
+            }
+        },
+        ProgressFormat::Plain => Arc::new(PlainProgress::new(total_size)),
+        ProgressFormat::Json => Arc::new(JsonProgress::new(total_size)),
+        ProgressFormat::None => Arc::new(NoProgress),
+    }
+}
+
+/// The indicatif bar Ferro has always shown, with a sub-bar per connection in segmented mode
+pub struct BarProgress {
+    multi: MultiProgress,
+    bar: ProgressBar,
+    segments: Mutex<Vec<ProgressBar>>,
+}
+
+impl BarProgress {
+    pub fn new(total_size: u64) -> Self {
+        let multi = MultiProgress::new();
+        let bar = multi.add(ProgressBar::new(total_size));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+                .expect("progress bar template is valid")
+                .progress_chars("#>-"),
+        );
+        Self {
+            multi,
+            bar,
+            segments: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ProgressObserver for BarProgress {
+    fn set_position(&self, position: u64) {
+        self.bar.set_position(position);
+    }
+
+    fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+    }
+
+    fn finish(&self) {
+        for segment in self.segments.lock().unwrap().drain(..) {
+            segment.finish_and_clear();
+        }
+        self.bar.finish_with_message("Download completed");
+    }
+
+    fn start_segments(&self, segments: &[SegmentProgress]) {
+        let style = ProgressStyle::default_bar()
+            .template(
+                "  {prefix:>3} [{bar:30.green/white}] {bytes}/{total_bytes} ({bytes_per_sec})",
+            )
+            .expect("segment bar template is valid")
+            .progress_chars("=> ");
+
+        let mut bars = self.segments.lock().unwrap();
+        for (index, segment) in segments.iter().enumerate() {
+            let bar = self.multi.add(ProgressBar::new(segment.len));
+            bar.set_style(style.clone());
+            bar.set_prefix(format!("#{}", index + 1));
+            bar.set_position(segment.downloaded);
+            bars.push(bar);
+        }
+    }
+
+    fn segment_inc(&self, segment: usize, delta: u64) {
+        if let Some(bar) = self.segments.lock().unwrap().get(segment) {
+            bar.inc(delta);
+        }
+        self.bar.inc(delta);
+    }
+}
+
+/// Prints `Downloaded 23% (1.4 GB/6.0 GB) at 40.0 MB/s` to stderr every few seconds
+pub struct PlainProgress {
+    total_size: Option<u64>,
+    position: AtomicU64,
+    started: Instant,
+    /// When the last line was printed and the position at that time, for the rate
+    last_report: Mutex<(Instant, u64)>,
+}
+
+impl PlainProgress {
+    pub fn new(total_size: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            total_size,
+            position: AtomicU64::new(0),
+            started: now,
+            last_report: Mutex::new((now, 0)),
+        }
+    }
+
+    fn maybe_report(&self) {
+        let mut last_report = self.last_report.lock().unwrap();
+        let elapsed = last_report.0.elapsed();
+        if elapsed < PLAIN_REPORT_INTERVAL {
+            return;
+        }
+
+        let position = self.position.load(Ordering::Relaxed);
+        let rate = position.saturating_sub(last_report.1) as f64 / elapsed.as_secs_f64();
+        eprintln!("{}", self.line(position, rate));
+        *last_report = (Instant::now(), position);
+    }
+
+    fn line(&self, position: u64, rate: f64) -> String {
+        let rate = utils::bytes_to_human_readable(rate as u64);
+        match self.total_size {
+            Some(total_size) if total_size > 0 => format!(
+                "Downloaded {}% ({}/{}) at {}/s",
+                position * 100 / total_size,
+                utils::bytes_to_human_readable(position),
+                utils::bytes_to_human_readable(total_size),
+                rate
+            ),
+            _ => format!(
+                "Downloaded {} at {}/s",
+                utils::bytes_to_human_readable(position),
+                rate
+            ),
+        }
+    }
+}
+
+impl ProgressObserver for PlainProgress {
+    fn set_position(&self, position: u64) {
+        self.position.store(position, Ordering::Relaxed);
+        // A resumed download's existing bytes don't count towards the rate
+        *self.last_report.lock().unwrap() = (Instant::now(), position);
+    }
+
+    fn inc(&self, delta: u64) {
+        self.position.fetch_add(delta, Ordering::Relaxed);
+        self.maybe_report();
+    }
+
+    fn finish(&self) {
+        let position = self.position.load(Ordering::Relaxed);
+        let rate = position as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+        eprintln!("{}", self.line(position, rate));
+    }
+}
+
+/// One line of `--progress-format json` output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    Progress {
+        downloaded: u64,
+        total: Option<u64>,
+        /// Bytes per second since the previous event
+        rate: u64,
+        /// Seconds left at that rate, when the total is known
+        eta: Option<u64>,
+    },
+    Complete {
+        downloaded: u64,
+        total: Option<u64>,
+        /// Average bytes per second over the whole download
+        rate: u64,
+        elapsed_ms: u64,
+    },
+}
+
+/// Prints a [`ProgressEvent`] as a JSON line to stderr every [`JSON_REPORT_INTERVAL`]
+pub struct JsonProgress {
+    total_size: Option<u64>,
+    position: AtomicU64,
+    started: Instant,
+    /// When the last event was printed and the position at that time, for the rate
+    last_report: Mutex<(Instant, u64)>,
+}
+
+impl JsonProgress {
+    pub fn new(total_size: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            total_size,
+            position: AtomicU64::new(0),
+            started: now,
+            last_report: Mutex::new((now, 0)),
+        }
+    }
+
+    fn maybe_report(&self) {
+        let mut last_report = self.last_report.lock().unwrap();
+        let elapsed = last_report.0.elapsed();
+        if elapsed < JSON_REPORT_INTERVAL {
+            return;
+        }
+
+        let position = self.position.load(Ordering::Relaxed);
+        let rate = position.saturating_sub(last_report.1) as f64 / elapsed.as_secs_f64();
+        print_event(&self.progress_event(position, rate as u64));
+        *last_report = (Instant::now(), position);
+    }
+
+    fn progress_event(&self, downloaded: u64, rate: u64) -> ProgressEvent {
+        ProgressEvent::Progress {
+            downloaded,
+            total: self.total_size,
+            rate,
+            eta: self
+                .total_size
+                .filter(|_| rate > 0)
+                .map(|total| total.saturating_sub(downloaded).div_ceil(rate)),
+        }
+    }
+}
+
+impl ProgressObserver for JsonProgress {
+    fn set_position(&self, position: u64) {
+        self.position.store(position, Ordering::Relaxed);
+        // A resumed download's existing bytes don't count towards the rate
+        *self.last_report.lock().unwrap() = (Instant::now(), position);
+    }
+
+    fn inc(&self, delta: u64) {
+        self.position.fetch_add(delta, Ordering::Relaxed);
+        self.maybe_report();
+    }
+
+    fn finish(&self) {
+        let downloaded = self.position.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed();
+        print_event(&ProgressEvent::Complete {
+            downloaded,
+            total: self.total_size,
+            rate: (downloaded as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+            elapsed_ms: elapsed.as_millis() as u64,
+        });
+    }
+}
+
+fn print_event(event: &ProgressEvent) {
+    match serde_json::to_string(event) {
+        Ok(line) => eprintln!("{}", line),
+        Err(e) => log::debug!("Could not serialize a progress event: {}", e),
+    }
+}
+
+pub struct NoProgress;
+
+impl ProgressObserver for NoProgress {
+    fn set_position(&self, _position: u64) {}
+    fn inc(&self, _delta: u64) {}
+    fn finish(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_progress_line() {
+        let progress = PlainProgress::new(Some(4 * 1024 * 1024 * 1024));
+        assert_eq!(
+            progress.line(1024 * 1024 * 1024, 40.0 * 1024.0 * 1024.0),
+            "Downloaded 25% (1.0 GB/4.0 GB) at 40.0 MB/s"
+        );
+
+        let unknown = PlainProgress::new(None);
+        assert_eq!(unknown.line(2048, 1024.0), "Downloaded 2.0 KB at 1.0 KB/s");
+    }
+
+    #[test]
+    fn test_json_progress_event() {
+        let progress = JsonProgress::new(Some(1000));
+        let event = progress.progress_event(250, 100);
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"progress","downloaded":250,"total":1000,"rate":100,"eta":8}"#
+        );
+
+        // Without a rate or a total there is no ETA
+        assert!(matches!(
+            progress.progress_event(250, 0),
+            ProgressEvent::Progress { eta: None, .. }
+        ));
+        assert!(matches!(
+            JsonProgress::new(None).progress_event(250, 100),
+            ProgressEvent::Progress { eta: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_segment_progress_counts_towards_total() {
+        let progress = PlainProgress::new(Some(100));
+        progress.start_segments(&[
+            SegmentProgress {
+                downloaded: 0,
+                len: 50,
+            },
+            SegmentProgress {
+                downloaded: 0,
+                len: 50,
+            },
+        ]);
+        progress.segment_inc(1, 20);
+        progress.segment_inc(0, 10);
+        assert_eq!(progress.position.load(Ordering::Relaxed), 30);
+    }
+}
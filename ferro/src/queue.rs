@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::types::{Selection, SelectionQuery};
+
+/// One entry of a `--batch` job file. Anything left out is picked the same way as
+/// for a single `ferro download`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    #[serde(flatten)]
+    pub query: SelectionQuery,
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemStatus {
+    Pending,
+    InProgress,
+    Done,
+    Failed,
+}
+
+/// A job together with what has happened to it so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub job: Job,
+    pub status: ItemStatus,
+    /// Selection the job resolved to, kept so a resumed run downloads the same thing
+    pub selection: Option<Selection>,
+    /// File the job is being written to, once known
+    pub output: Option<PathBuf>,
+    /// Reason the last attempt failed
+    pub error: Option<String>,
+}
+
+/// Outcome of one queue item, printed as a JSON Lines record as soon as the item finishes
+/// when a batch runs with `--format json`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ItemReport {
+    Ok {
+        selection: Selection,
+        path: PathBuf,
+        sha256: Option<String>,
+        bytes: u64,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selection: Option<Selection>,
+        reason: String,
+    },
+}
+
+/// Download queue persisted to the data directory, so an interrupted batch can be resumed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Queue {
+    pub created: DateTime<Utc>,
+    pub items: Vec<QueueItem>,
+}
+
+impl Queue {
+    pub fn new(jobs: Vec<Job>) -> Self {
+        Self {
+            created: Utc::now(),
+            items: jobs
+                .into_iter()
+                .map(|job| QueueItem {
+                    job,
+                    status: ItemStatus::Pending,
+                    selection: None,
+                    output: None,
+                    error: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Read a job file: a JSON array of jobs
+    pub async fn from_job_file(path: &Path) -> Result<Self> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read job file: {}", path.display()))?;
+        let jobs: Vec<Job> = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse job file: {}", path.display()))?;
+        Ok(Self::new(jobs))
+    }
+
+    /// Location of the saved queue, `<data dir>/ferro/queue.json`
+    pub fn default_path() -> Result<PathBuf> {
+        let data_dir = dirs::data_dir().context("Could not determine the data directory")?;
+        Ok(data_dir.join("ferro").join("queue.json"))
+    }
+
+    /// Load a saved queue, or `None` if there isn't one
+    pub async fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read queue: {}", path.display()))
+            }
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .with_context(|| format!("Failed to parse queue: {}", path.display()))
+    }
+
+    /// Save the queue, replacing the previous state in one step so an interruption
+    /// never leaves a truncated file behind
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.with_context(|| {
+                format!("Failed to create queue directory: {}", parent.display())
+            })?;
+        }
+
+        let temporary = path.with_extension("json.tmp");
+        tokio::fs::write(&temporary, serde_json::to_string_pretty(self)?)
+            .await
+            .with_context(|| format!("Failed to write queue: {}", temporary.display()))?;
+        tokio::fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("Failed to write queue: {}", path.display()))
+    }
+
+    pub fn count(&self, status: ItemStatus) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.status == status)
+            .count()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.count(ItemStatus::Done) == self.items.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_file_format() {
+        let jobs: Vec<Job> = serde_json::from_str(
+            r#"[
+                {"version": "Windows 11", "language": "English", "output": "win11.iso"},
+                {"version": "Windows 10", "architecture": "x86"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(jobs[0].query.language.as_deref(), Some("English"));
+        assert_eq!(jobs[0].output, Some(PathBuf::from("win11.iso")));
+        assert_eq!(jobs[1].query.release, None);
+
+        let queue = Queue::new(jobs);
+        assert_eq!(queue.count(ItemStatus::Pending), 2);
+        assert!(!queue.is_finished());
+    }
+
+    #[test]
+    fn test_item_report_format() {
+        let report = ItemReport::Ok {
+            selection: Selection {
+                version: "Windows 11".to_string(),
+                release: "24H2".to_string(),
+                edition: "Windows 11".to_string(),
+                language: "English".to_string(),
+                architecture: "x64".to_string(),
+            },
+            path: PathBuf::from("win11.iso"),
+            sha256: Some("abc".to_string()),
+            bytes: 42,
+        };
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["status"], "ok");
+        assert_eq!(value["selection"]["release"], "24H2");
+        assert_eq!(value["path"], "win11.iso");
+        assert_eq!(value["bytes"], 42);
+
+        let report = ItemReport::Error {
+            selection: None,
+            reason: "no such version".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"status":"error","reason":"no such version"}"#
+        );
+    }
+}
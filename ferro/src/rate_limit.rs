@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Span after which the byte count starts over, so a pause in the transfer doesn't
+/// build up an allowance that is then spent in a burst
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Caps the combined speed of every transfer that shares it
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    window: Mutex<Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    bytes: u64,
+}
+
+impl Window {
+    /// Count `bytes` received at `now` and return how long to wait so the window's
+    /// average stays at `bytes_per_sec`
+    fn record(&mut self, bytes: u64, bytes_per_sec: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.start);
+        let due = Duration::from_secs_f64(self.bytes as f64 / bytes_per_sec as f64);
+        if elapsed >= WINDOW && due <= elapsed {
+            self.start = now;
+            self.bytes = 0;
+        }
+        self.bytes += bytes;
+        let due = Duration::from_secs_f64(self.bytes as f64 / bytes_per_sec as f64);
+        due.saturating_sub(now.saturating_duration_since(self.start))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    /// Account for `bytes` just received, sleeping while the transfer is ahead of the limit
+    pub async fn throttle(&self, bytes: u64) {
+        let delay = self
+            .window
+            .lock()
+            .unwrap()
+            .record(bytes, self.bytes_per_sec, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_record() {
+        let start = Instant::now();
+        let mut window = Window { start, bytes: 0 };
+
+        // Two seconds' worth at once has to wait for the second one
+        assert_eq!(window.record(2000, 1000, start), Duration::from_secs(2));
+        assert_eq!(
+            window.record(500, 1000, start + Duration::from_secs(2)),
+            Duration::from_millis(500)
+        );
+
+        // After an idle spell the count starts over instead of allowing a burst
+        assert_eq!(
+            window.record(1000, 1000, start + Duration::from_secs(10)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(window.start, start + Duration::from_secs(10));
+    }
+}
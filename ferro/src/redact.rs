@@ -0,0 +1,81 @@
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+
+/// What masked values are replaced with
+const MASK: &str = "<redacted>";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Session IDs are v4 UUIDs, wherever they appear
+static SESSION_ID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b").unwrap()
+});
+
+/// Query parameters that carry a session or sign a download URL. Microsoft's download
+/// links are signed with `t`, `e`, `P1` and `P2`; Azure SAS tokens use `sig`, `se` and friends.
+static SIGNED_PARAM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)([?&](?:session_?id|t|e|p1|p2|p3|p4|sig|se|st|sp|sv|sr|skoid|sktid|skt|ske|sks|skv)=)[^&\s#"]+"#)
+        .unwrap()
+});
+
+/// Cookie values in `Cookie:` / `Set-Cookie:` lines and in debug-printed header maps
+static COOKIE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)("(?:set-)?cookie": ")[^"]*|((?:set-)?cookie:\s*)[^\r\n]*"#).unwrap()
+});
+
+/// Turn on `--redact-logs`
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// `text` with session IDs, URL signatures and cookie values masked when `--redact-logs`
+/// is on, unchanged otherwise. Wrap anything that may hold them before logging or
+/// dumping it.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    if ENABLED.load(Ordering::Relaxed) {
+        Cow::Owned(mask(text))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn mask(text: &str) -> String {
+    let text = COOKIE.replace_all(text, |captures: &regex::Captures| {
+        let prefix = captures.get(1).or_else(|| captures.get(2)).unwrap();
+        format!("{}{}", prefix.as_str(), MASK)
+    });
+    let text = SIGNED_PARAM.replace_all(&text, format!("${{1}}{}", MASK).as_str());
+    SESSION_ID.replace_all(&text, MASK).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_secrets() {
+        assert_eq!(
+            mask("https://www.microsoft.com/software-download-connector/api/GetProductDownloadLinksBySku?profile=606624d44113&SKU=18716&sessionID=6f9a3b1e-2c4d-4e5f-8a9b-0c1d2e3f4a5b"),
+            "https://www.microsoft.com/software-download-connector/api/GetProductDownloadLinksBySku?profile=606624d44113&SKU=18716&sessionID=<redacted>"
+        );
+        assert_eq!(
+            mask(
+                r#"{"Uri":"https://software.download.prss.microsoft.com/dbazure/Win11_24H2_English_x64.iso?t=8a1f&P1=1729&P2=602&P3=2&P4=abc%3d%3d"}"#
+            ),
+            r#"{"Uri":"https://software.download.prss.microsoft.com/dbazure/Win11_24H2_English_x64.iso?t=<redacted>&P1=<redacted>&P2=<redacted>&P3=<redacted>&P4=<redacted>"}"#
+        );
+        assert_eq!(
+            mask(r#"{"content-type": "application/json", "set-cookie": "MUID=12AB; path=/"}"#),
+            r#"{"content-type": "application/json", "set-cookie": "<redacted>"}"#
+        );
+        assert_eq!(mask("Cookie: MUID=12AB; MC1=x"), "Cookie: <redacted>");
+    }
+
+    #[test]
+    fn test_redact_is_off_by_default() {
+        let text = "sessionID=6f9a3b1e-2c4d-4e5f-8a9b-0c1d2e3f4a5b";
+        assert!(matches!(redact(text), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,558 @@
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use log::{debug, info, warn};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::http;
+use crate::part_index;
+use crate::progress::{Progress, SegmentProgress};
+use crate::rate_limit::RateLimiter;
+use crate::signals;
+
+/// Amount of data a segment worker buffers before writing it out and
+/// recording the progress in the sidecar
+const WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How often the sidecar is persisted while segments are downloading
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the segments' transfers are paced
+#[derive(Debug, Clone)]
+pub struct Pacing {
+    /// How long a segment may receive nothing before it reconnects
+    pub stall_timeout: Duration,
+    /// Shared by every segment, so together they stay under the limit
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+/// Progress of a single byte range of a segmented download
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SegmentState {
+    /// First byte of the segment
+    pub start: u64,
+    /// Last byte of the segment (inclusive, like the HTTP Range header)
+    pub end: u64,
+    /// Number of bytes of this segment already written to the partial file
+    pub downloaded: u64,
+}
+
+impl SegmentState {
+    pub(crate) fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.downloaded >= self.len()
+    }
+}
+
+/// Contents of the `.part.json` sidecar that accompanies a `.part` file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialState {
+    pub url: String,
+    pub total_size: u64,
+    pub segments: Vec<SegmentState>,
+}
+
+impl PartialState {
+    /// Split `total_size` bytes into `connections` contiguous segments
+    pub fn new(url: &str, total_size: u64, connections: usize) -> Self {
+        let connections = (connections.max(1) as u64).min(total_size.max(1));
+        let segment_size = total_size / connections;
+        let segments = (0..connections)
+            .map(|i| {
+                let start = i * segment_size;
+                let end = if i == connections - 1 {
+                    total_size - 1
+                } else {
+                    start + segment_size - 1
+                };
+                SegmentState {
+                    start,
+                    end,
+                    downloaded: 0,
+                }
+            })
+            .collect();
+
+        Self {
+            url: url.to_string(),
+            total_size,
+            segments,
+        }
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.segments.iter().map(|s| s.downloaded).sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.segments.iter().all(SegmentState::is_complete)
+    }
+
+    /// Whether this partial download was started from the same remote file.
+    /// Microsoft's signed URLs change on every resolution, so only the path is compared.
+    pub fn matches(&self, url: &str, total_size: u64) -> bool {
+        self.total_size == total_size && url_without_query(&self.url) == url_without_query(url)
+    }
+
+    pub async fn load(path: &Path) -> Option<Self> {
+        let contents = tokio::fs::read_to_string(path).await.ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                warn!("Ignoring unreadable resume state {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(path, contents)
+            .await
+            .with_context(|| format!("Failed to write resume state: {}", path.display()))
+    }
+}
+
+/// Path of the partial data file for an output path
+pub fn part_path(output_path: &Path) -> PathBuf {
+    append_extension(output_path, "part")
+}
+
+/// Path of the resume state sidecar for an output path
+pub fn state_path(output_path: &Path) -> PathBuf {
+    append_extension(output_path, "part.json")
+}
+
+/// Delete the partial file and resume state of `output_path`, and forget any partial
+/// download of `url` recorded under another name, so the next download starts over
+pub async fn discard_partial(url: &str, output_path: &Path) -> Result<()> {
+    for path in [part_path(output_path), state_path(output_path)] {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => info!("Removed {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+        }
+    }
+    part_index::remove(url).await;
+    Ok(())
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut os_string = path.as_os_str().to_owned();
+    os_string.push(".");
+    os_string.push(extension);
+    PathBuf::from(os_string)
+}
+
+pub fn url_without_query(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(mut parsed) => {
+            parsed.set_query(None);
+            parsed.set_fragment(None);
+            parsed.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Download `url` into `output_path` using one range request per segment, recording
+/// each segment's progress in a `.part.json` sidecar so an interrupted download
+/// continues every segment from where it stopped.
+pub async fn download(
+    client: &Client,
+    url: &str,
+    output_path: &Path,
+    total_size: u64,
+    connections: usize,
+    pacing: Pacing,
+    progress: Progress,
+) -> Result<()> {
+    let part_path = part_path(output_path);
+    let state_path = state_path(output_path);
+
+    if !state_path.exists() {
+        part_index::adopt_previous(url, total_size, output_path).await;
+    }
+
+    let state = match resume_state(&part_path, &state_path, url, total_size).await {
+        Some(state) => {
+            info!(
+                "Resuming segmented download ({} of {} bytes already downloaded)",
+                state.downloaded(),
+                total_size
+            );
+            state
+        }
+        None => {
+            let state = PartialState::new(url, total_size, connections);
+            let file = File::create(&part_path).await.with_context(|| {
+                format!("Failed to create partial file: {}", part_path.display())
+            })?;
+            file.set_len(total_size)
+                .await
+                .context("Failed to allocate partial file")?;
+            state.save(&state_path).await?;
+            part_index::record(url, output_path).await;
+            state
+        }
+    };
+
+    // Resumed state may carry a URL whose signature has expired, always use the fresh one
+    let state = Arc::new(Mutex::new(PartialState {
+        url: url.to_string(),
+        ..state
+    }));
+
+    {
+        let state = state.lock().unwrap();
+        progress.set_position(state.downloaded());
+        let segments: Vec<SegmentProgress> = state
+            .segments
+            .iter()
+            .map(|segment| SegmentProgress {
+                downloaded: segment.downloaded,
+                len: segment.len(),
+            })
+            .collect();
+        progress.start_segments(&segments);
+    }
+
+    let saver = {
+        let state = state.clone();
+        let state_path = state_path.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STATE_SAVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let snapshot = state.lock().unwrap().clone();
+                if let Err(e) = snapshot.save(&state_path).await {
+                    debug!("Failed to save resume state: {}", e);
+                }
+            }
+        })
+    };
+
+    let pending: Vec<usize> = state
+        .lock()
+        .unwrap()
+        .segments
+        .iter()
+        .enumerate()
+        .filter(|(_, segment)| !segment.is_complete())
+        .map(|(index, _)| index)
+        .collect();
+
+    let workers: Vec<_> = pending
+        .into_iter()
+        .map(|index| {
+            tokio::spawn(download_segment_until_done(
+                client.clone(),
+                url.to_string(),
+                part_path.clone(),
+                state.clone(),
+                index,
+                pacing.clone(),
+                progress.clone(),
+            ))
+        })
+        .collect();
+
+    let abort_handles: Vec<_> = workers.iter().map(|worker| worker.abort_handle()).collect();
+    let mut first_error = None;
+    let joined = async {
+        for worker in workers {
+            let result = worker
+                .await
+                .map_err(|e| anyhow!("Segment worker panicked: {}", e))
+                .and_then(|r| r);
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+    };
+    let interrupted = tokio::select! {
+        _ = joined => None,
+        signal = signals::received() => Some(signal),
+    };
+    if interrupted.is_some() {
+        // Workers only record data once it's written, so the saved state stays accurate
+        for handle in &abort_handles {
+            handle.abort();
+        }
+    }
+
+    saver.abort();
+    let final_state = state.lock().unwrap().clone();
+    final_state.save(&state_path).await?;
+
+    if let Some(signal) = interrupted {
+        return Err(
+            anyhow::Error::new(signals::Interrupted(signal)).context(format!(
+                "Download interrupted by {}, rerun to resume from {}",
+                signal,
+                part_path.display()
+            )),
+        );
+    }
+
+    if let Some(e) = first_error {
+        return Err(e.context(format!(
+            "Segmented download interrupted, rerun to resume from {}",
+            part_path.display()
+        )));
+    }
+
+    if !final_state.is_complete() {
+        return Err(anyhow!(
+            "Segmented download finished with incomplete segments"
+        ));
+    }
+
+    progress.finish();
+
+    tokio::fs::rename(&part_path, output_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to move download into place: {}",
+                output_path.display()
+            )
+        })?;
+    if let Err(e) = tokio::fs::remove_file(&state_path).await {
+        debug!("Failed to remove resume state: {}", e);
+    }
+    part_index::remove(url).await;
+
+    Ok(())
+}
+
+/// Load the sidecar state if it belongs to this download and its partial file is intact
+async fn resume_state(
+    part_path: &Path,
+    state_path: &Path,
+    url: &str,
+    total_size: u64,
+) -> Option<PartialState> {
+    let state = PartialState::load(state_path).await?;
+    if !state.matches(url, total_size) {
+        info!("Existing partial download is for a different file, starting over");
+        return None;
+    }
+
+    let part_len = tokio::fs::metadata(part_path).await.ok()?.len();
+    if part_len != total_size {
+        warn!("Partial file size does not match resume state, starting over");
+        return None;
+    }
+
+    Some(state)
+}
+
+/// Download a segment, continuing from where it stopped when its connection stalls
+async fn download_segment_until_done(
+    client: Client,
+    url: String,
+    part_path: PathBuf,
+    state: Arc<Mutex<PartialState>>,
+    index: usize,
+    pacing: Pacing,
+    progress: Progress,
+) -> Result<()> {
+    let mut stalls = 0;
+    loop {
+        let before = state.lock().unwrap().segments[index].downloaded;
+        let result =
+            download_segment(&client, &url, &part_path, &state, index, &pacing, &progress).await;
+        let Err(e) = result else {
+            return Ok(());
+        };
+        let Some(stalled) = e.downcast_ref::<http::Stalled>() else {
+            return Err(e);
+        };
+
+        if state.lock().unwrap().segments[index].downloaded > before {
+            stalls = 0;
+        }
+        stalls += 1;
+        if stalls > http::STALL_RETRIES {
+            return Err(e);
+        }
+        warn!(
+            "Segment {}: {}, reconnecting (attempt {}/{})",
+            index,
+            stalled,
+            stalls,
+            http::STALL_RETRIES
+        );
+    }
+}
+
+async fn download_segment(
+    client: &Client,
+    url: &str,
+    part_path: &Path,
+    state: &Mutex<PartialState>,
+    index: usize,
+    pacing: &Pacing,
+    progress: &Progress,
+) -> Result<()> {
+    let stall_timeout = pacing.stall_timeout;
+    let segment = state.lock().unwrap().segments[index].clone();
+    let offset = segment.start + segment.downloaded;
+
+    debug!(
+        "Segment {}: requesting bytes {}-{}",
+        index, offset, segment.end
+    );
+
+    let response = http::send_with_connect_retry(
+        || {
+            client
+                .get(url)
+                .header("Range", format!("bytes={}-{}", offset, segment.end))
+        },
+        "segment",
+    )
+    .await
+    .with_context(|| format!("Failed to start segment {}", index))?;
+
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow!(
+            "Segment {} request failed with status: {}",
+            index,
+            response.status()
+        ));
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .await
+        .with_context(|| format!("Failed to open partial file: {}", part_path.display()))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .context("Failed to seek in partial file")?;
+
+    let mut stream = response.bytes_stream();
+    let mut buffer = Vec::with_capacity(WRITE_BUFFER_SIZE);
+    let remaining = segment.len() - segment.downloaded;
+    let mut received = 0u64;
+
+    loop {
+        let chunk = match tokio::time::timeout(stall_timeout, stream.next()).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(_) => {
+                // Keep what arrived, so the retry continues after it
+                commit(&mut file, &mut buffer, state, index, progress).await?;
+                return Err(anyhow::Error::new(http::Stalled(stall_timeout))
+                    .context(format!("Segment {} stalled", index)));
+            }
+        };
+        let chunk = chunk.with_context(|| format!("Failed to read segment {}", index))?;
+        // Never write past the end of the segment, even if the server sends extra bytes
+        let take = chunk.len().min((remaining - received) as usize);
+        buffer.extend_from_slice(&chunk[..take]);
+        received += take as u64;
+
+        if buffer.len() >= WRITE_BUFFER_SIZE || received == remaining {
+            commit(&mut file, &mut buffer, state, index, progress).await?;
+        }
+        if let Some(rate_limiter) = &pacing.rate_limiter {
+            rate_limiter.throttle(take as u64).await;
+        }
+        if received == remaining {
+            break;
+        }
+    }
+
+    commit(&mut file, &mut buffer, state, index, progress).await?;
+
+    if received < remaining {
+        return Err(anyhow!(
+            "Segment {} ended early ({} of {} bytes)",
+            index,
+            received,
+            remaining
+        ));
+    }
+
+    Ok(())
+}
+
+/// Write buffered bytes and only then record them as downloaded, so the sidecar
+/// never claims data that hasn't reached the file
+async fn commit(
+    file: &mut File,
+    buffer: &mut Vec<u8>,
+    state: &Mutex<PartialState>,
+    index: usize,
+    progress: &Progress,
+) -> Result<()> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+
+    file.write_all(buffer)
+        .await
+        .context("Failed to write segment data")?;
+    file.flush().await.context("Failed to flush segment data")?;
+
+    let written = buffer.len() as u64;
+    state.lock().unwrap().segments[index].downloaded += written;
+    progress.segment_inc(index, written);
+    buffer.clear();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_state_splits_evenly() {
+        let state = PartialState::new("https://example.com/a.iso", 10, 3);
+        let ranges: Vec<_> = state.segments.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(ranges, vec![(0, 2), (3, 5), (6, 9)]);
+        assert_eq!(
+            state.segments.iter().map(SegmentState::len).sum::<u64>(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_partial_state_caps_connections_to_size() {
+        let state = PartialState::new("https://example.com/a.iso", 2, 8);
+        assert_eq!(state.segments.len(), 2);
+    }
+
+    #[test]
+    fn test_partial_state_matches_ignores_query() {
+        let state = PartialState::new("https://example.com/a.iso?t=1&h=abc", 100, 2);
+        assert!(state.matches("https://example.com/a.iso?t=2&h=def", 100));
+        assert!(!state.matches("https://example.com/b.iso?t=2", 100));
+        assert!(!state.matches("https://example.com/a.iso?t=2", 101));
+    }
+
+    #[test]
+    fn test_sidecar_paths() {
+        let output = Path::new("/tmp/Win11.iso");
+        assert_eq!(part_path(output), PathBuf::from("/tmp/Win11.iso.part"));
+        assert_eq!(
+            state_path(output),
+            PathBuf::from("/tmp/Win11.iso.part.json")
+        );
+    }
+}
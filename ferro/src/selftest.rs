@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::header::RANGE;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::http::{self, HttpOptions, DOWNLOAD_USER_AGENT};
+use crate::iso_api::{ApiConfig, IsoApi};
+
+/// Version the self-test walks through
+const SELFTEST_VERSION: &str = "Windows 11";
+
+/// Bytes requested by the final probe of the download URL
+const PROBE_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageStatus {
+    Pass,
+    Fail,
+    /// Not attempted because an earlier stage failed
+    Skip,
+}
+
+impl std::fmt::Display for StageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StageStatus::Pass => write!(f, "PASS"),
+            StageStatus::Fail => write!(f, "FAIL"),
+            StageStatus::Skip => write!(f, "SKIP"),
+        }
+    }
+}
+
+/// Outcome of one step of the self-test
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub stage: &'static str,
+    pub status: StageStatus,
+    /// What the stage found, or why it failed
+    pub detail: String,
+    pub elapsed_ms: u128,
+}
+
+/// Stages in the order they run; each one needs the result of the previous
+const STAGES: [&str; 8] = [
+    "session",
+    "versions",
+    "releases",
+    "editions",
+    "languages",
+    "architectures",
+    "url",
+    "probe",
+];
+
+/// Resolve a Windows 11 download end to end and fetch its first kilobyte, reporting
+/// each stage separately so an API change shows up as the exact step that broke.
+///
+/// Stops at the first failure; the remaining stages are reported as skipped.
+pub async fn run(api_config: &ApiConfig) -> Vec<StageReport> {
+    let mut reports = Vec::new();
+    let mut walk = Walk::default();
+
+    for stage in STAGES {
+        let started = Instant::now();
+        let result = walk.step(stage, api_config).await;
+        let (status, detail) = match result {
+            Ok(detail) => (StageStatus::Pass, detail),
+            Err(e) => (StageStatus::Fail, format!("{:#}", e)),
+        };
+        reports.push(StageReport {
+            stage,
+            status,
+            detail,
+            elapsed_ms: started.elapsed().as_millis(),
+        });
+        if status == StageStatus::Fail {
+            break;
+        }
+    }
+
+    for stage in &STAGES[reports.len()..] {
+        reports.push(StageReport {
+            stage,
+            status: StageStatus::Skip,
+            detail: String::new(),
+            elapsed_ms: 0,
+        });
+    }
+
+    reports
+}
+
+/// What the stages have resolved so far
+#[derive(Default)]
+struct Walk {
+    api: Option<IsoApi>,
+    release: String,
+    edition: String,
+    language: String,
+    url: String,
+}
+
+impl Walk {
+    /// Run one stage, returning a short description of what it found
+    async fn step(&mut self, stage: &str, api_config: &ApiConfig) -> Result<String> {
+        if stage == "session" {
+            let api = IsoApi::with_config(api_config.clone()).await?;
+            self.api = Some(api);
+            return Ok("locale check succeeded".to_string());
+        }
+        if stage == "probe" {
+            return probe(&self.url, &api_config.http).await;
+        }
+
+        let api = self.api.as_mut().context("No API session")?;
+        match stage {
+            "versions" => {
+                let versions = api.get_available_versions().await?;
+                versions
+                    .iter()
+                    .find(|v| v.name == SELFTEST_VERSION)
+                    .with_context(|| format!("{} is not in the catalog", SELFTEST_VERSION))?;
+                Ok(format!("{} versions", versions.len()))
+            }
+            "releases" => {
+                let releases = api.get_releases(SELFTEST_VERSION).await?;
+                self.release = releases.first().context("No releases found")?.name.clone();
+                Ok(self.release.clone())
+            }
+            "editions" => {
+                let editions = api.get_editions(SELFTEST_VERSION, &self.release).await?;
+                self.edition = editions.first().context("No editions found")?.name.clone();
+                Ok(self.edition.clone())
+            }
+            "languages" => {
+                let languages = api
+                    .get_languages(SELFTEST_VERSION, &self.release, &self.edition)
+                    .await?;
+                let language = languages
+                    .iter()
+                    .find(|l| {
+                        l.display_name
+                            .eq_ignore_ascii_case("English (United States)")
+                    })
+                    .or_else(|| languages.first())
+                    .context("No languages found")?;
+                self.language = language.display_name.clone();
+                Ok(format!("{} languages", languages.len()))
+            }
+            "architectures" => {
+                let architectures = api
+                    .get_architectures(
+                        SELFTEST_VERSION,
+                        &self.release,
+                        &self.edition,
+                        &self.language,
+                    )
+                    .await?;
+                let architecture = architectures
+                    .iter()
+                    .find(|a| a.name == "x64")
+                    .or_else(|| architectures.first())
+                    .context("No architectures found")?;
+                self.url = architecture.url.clone();
+                let names: Vec<&str> = architectures.iter().map(|a| a.name.as_str()).collect();
+                Ok(names.join(", "))
+            }
+            "url" => {
+                let url = reqwest::Url::parse(&self.url).context("Download URL is not valid")?;
+                Ok(url.host_str().unwrap_or_default().to_string())
+            }
+            _ => Err(anyhow!("Unknown stage '{}'", stage)),
+        }
+    }
+}
+
+/// Request the first `PROBE_SIZE` bytes of the download, the way a download would start
+async fn probe(url: &str, http_options: &HttpOptions) -> Result<String> {
+    let client = http_options
+        .configure(Client::builder(), DOWNLOAD_USER_AGENT)
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    let response = http::send_with_connect_retry(
+        || {
+            client
+                .get(url)
+                .header(RANGE, format!("bytes=0-{}", PROBE_SIZE - 1))
+        },
+        "probe request",
+    )
+    .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow!("Download server returned {}", status));
+    }
+
+    if status != StatusCode::PARTIAL_CONTENT {
+        // The server is sending the whole file; don't wait for it
+        return Err(anyhow!(
+            "Download server returned {} instead of 206 Partial Content",
+            status
+        ));
+    }
+
+    let body = response
+        .bytes()
+        .await
+        .context("Failed to read probe data")?;
+    if body.is_empty() {
+        return Err(anyhow!("Download server returned no data"));
+    }
+    Ok(format!("received {} bytes", body.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stages_need_a_session() {
+        let mut walk = Walk::default();
+        let err = walk
+            .step("releases", &ApiConfig::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "No API session");
+    }
+}
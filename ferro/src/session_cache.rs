@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use reqwest_cookie_store::CookieStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How long whitelisted sessions are reused before a run starts new ones
+pub const MAX_AGE: Duration = Duration::minutes(30);
+
+/// Cache file used unless `--no-cache` is given: `<cache dir>/ferro/session.json`
+pub fn default_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("ferro").join("session.json"))
+}
+
+/// Microsoft session IDs and cookies saved between runs, so a new run can skip creating
+/// and whitelisting sessions while they're still fresh
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionCache {
+    /// When the oldest of the sessions was whitelisted
+    pub created: DateTime<Utc>,
+    /// Session IDs by session index, as `IsoApi` keeps them
+    pub session_ids: HashMap<usize, String>,
+    /// The cookie jar, in cookie_store's JSON format
+    pub cookies: serde_json::Value,
+}
+
+impl SessionCache {
+    pub fn new(
+        created: DateTime<Utc>,
+        session_ids: HashMap<usize, String>,
+        cookies: &CookieStore,
+    ) -> Result<Self> {
+        let mut json = Vec::new();
+        // Session cookies have no expiry date but are exactly what the API checks
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(cookies, &mut json)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize cookies: {}", e))?;
+        Ok(Self {
+            created,
+            session_ids,
+            cookies: serde_json::from_slice(&json)?,
+        })
+    }
+
+    /// Load the cache at `path` if it exists and is younger than [`MAX_AGE`]
+    pub fn load_fresh(path: &Path, now: DateTime<Utc>) -> Option<Self> {
+        let contents = std::fs::read(path).ok()?;
+        let cache: Self = match serde_json::from_slice(&contents) {
+            Ok(cache) => cache,
+            Err(e) => {
+                debug!(
+                    "Ignoring unreadable session cache {}: {}",
+                    path.display(),
+                    e
+                );
+                return None;
+            }
+        };
+        if !cache.is_fresh(now) {
+            debug!("Session cache {} has expired", path.display());
+            return None;
+        }
+        Some(cache)
+    }
+
+    pub fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now - self.created < MAX_AGE
+    }
+
+    /// The cached cookies, or an empty jar if they can't be read back
+    pub fn cookie_store(&self) -> CookieStore {
+        let json = self.cookies.to_string();
+        cookie_store::serde::json::load_all(json.as_bytes()).unwrap_or_else(|e| {
+            debug!("Ignoring unreadable cached cookies: {}", e);
+            CookieStore::default()
+        })
+    }
+
+    /// Write the cache, readable only by the current user since the cookies identify the session
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("Failed to write session cache: {}", path.display()))?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_cache_round_trip() {
+        let mut cookies = CookieStore::default();
+        let url = url::Url::parse("https://www.microsoft.com/").unwrap();
+        cookies
+            .parse("MUID=abc; Path=/", &url)
+            .expect("cookie is valid");
+        let created = Utc::now();
+        let cache = SessionCache::new(
+            created,
+            HashMap::from([(0, "session-0".to_string())]),
+            &cookies,
+        )
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!("ferro-session-{}.json", std::process::id()));
+        cache.save(&path).unwrap();
+        let loaded = SessionCache::load_fresh(&path, created + Duration::minutes(5)).unwrap();
+        assert_eq!(loaded.session_ids[&0], "session-0");
+        assert!(loaded
+            .cookie_store()
+            .contains("www.microsoft.com", "/", "MUID"));
+
+        assert!(SessionCache::load_fresh(&path, created + MAX_AGE).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use std::future::pending;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long a command gets to clean up after a shutdown signal before Ferro exits anyway
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+static RECEIVED: OnceLock<watch::Receiver<Option<ShutdownSignal>>> = OnceLock::new();
+
+/// A signal asking Ferro to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    /// SIGINT, i.e. Ctrl-C
+    Interrupt,
+    /// SIGTERM, as sent by systemd, container runtimes and other process managers
+    Terminate,
+}
+
+impl ShutdownSignal {
+    /// Conventional shell exit status for a process stopped by this signal (128 + signal number)
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ShutdownSignal::Interrupt => 130,
+            ShutdownSignal::Terminate => 143,
+        }
+    }
+}
+
+impl std::fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShutdownSignal::Interrupt => write!(f, "SIGINT"),
+            ShutdownSignal::Terminate => write!(f, "SIGTERM"),
+        }
+    }
+}
+
+/// Error returned when a command stopped because of a shutdown signal
+#[derive(Debug)]
+pub struct Interrupted(pub ShutdownSignal);
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted by {}", self.0)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Start listening for SIGINT and SIGTERM. The first one is reported through
+/// [`received`] so downloads can flush and keep their partial files; a second one
+/// exits immediately.
+pub fn install() -> Result<()> {
+    let (sender, receiver) = watch::channel(None);
+    if RECEIVED.set(receiver).is_err() {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::spawn(async move {
+            loop {
+                let signal = tokio::select! {
+                    _ = interrupt.recv() => ShutdownSignal::Interrupt,
+                    _ = terminate.recv() => ShutdownSignal::Terminate,
+                };
+                notify(&sender, signal);
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            notify(&sender, ShutdownSignal::Interrupt);
+        }
+    });
+
+    Ok(())
+}
+
+fn notify(sender: &watch::Sender<Option<ShutdownSignal>>, signal: ShutdownSignal) {
+    if sender.borrow().is_some() {
+        eprintln!("Received {} again, exiting immediately", signal);
+        std::process::exit(signal.exit_code());
+    }
+    eprintln!("Received {}, stopping...", signal);
+    sender.send_replace(Some(signal));
+}
+
+/// Resolves once a shutdown signal has been received; never resolves if
+/// [`install`] wasn't called
+pub async fn received() -> ShutdownSignal {
+    let Some(receiver) = RECEIVED.get() else {
+        return pending().await;
+    };
+
+    let mut receiver = receiver.clone();
+    let signal = match receiver.wait_for(Option::is_some).await {
+        Ok(signal) => signal.expect("wait_for only returns a received signal"),
+        Err(_) => return pending().await,
+    };
+    signal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupted_exit_codes() {
+        let error = anyhow::Error::new(Interrupted(ShutdownSignal::Terminate))
+            .context("Download interrupted");
+        let interrupted = error.downcast_ref::<Interrupted>().unwrap();
+        assert_eq!(interrupted.0.exit_code(), 143);
+        assert_eq!(interrupted.to_string(), "Interrupted by SIGTERM");
+        assert_eq!(ShutdownSignal::Interrupt.exit_code(), 130);
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{anyhow, Context, Result};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use log::warn;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::segmented;
+
+/// A destination for downloaded bytes
+pub type Sink = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Compression applied to a download on its way to disk, for archiving ISOs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// Zstandard, written as `<output>.zst`
+    Zstd,
+    /// gzip, written as `<output>.gz`
+    Gzip,
+}
+
+impl Compression {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zst",
+            Compression::Gzip => "gz",
+        }
+    }
+
+    fn encoder(self, writer: impl AsyncWrite + Send + Unpin + 'static) -> Sink {
+        match self {
+            Compression::Zstd => Box::new(ZstdEncoder::new(writer)),
+            Compression::Gzip => Box::new(GzipEncoder::new(writer)),
+        }
+    }
+
+    /// `path` with this compression's extension, unless it already ends with it or is
+    /// standard output
+    pub fn compressed_path(self, path: &Path) -> PathBuf {
+        if self.is_compressed_path(path) || is_stdout(path) {
+            return path.to_path_buf();
+        }
+        let mut os_string = path.as_os_str().to_owned();
+        os_string.push(".");
+        os_string.push(self.extension());
+        PathBuf::from(os_string)
+    }
+
+    /// The name the file at `path` gets once decompressed
+    pub fn decompressed_path(self, path: &Path) -> PathBuf {
+        if self.is_compressed_path(path) {
+            path.with_extension("")
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    fn is_compressed_path(self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(self.extension()))
+    }
+}
+
+/// Whether an output path stands for standard output, as in `--output -`
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Fans every chunk out to several sinks in one pass, so a single download can
+/// populate multiple destinations (e.g. a local disk and a NAS mount).
+///
+/// Data goes to a `.part` file next to each destination, which only gets its final
+/// name once [`TeeWriter::finish`] succeeds, so an interrupted download never leaves
+/// a truncated file under the name of a complete one. A destination of `-` is written
+/// to standard output instead.
+pub struct TeeWriter {
+    destinations: Vec<(PathBuf, Sink)>,
+    tolerate_errors: bool,
+}
+
+impl TeeWriter {
+    /// Create (truncating) a partial file for every path, compressing what's written to it
+    /// if `compression` is set. With `tolerate_errors`, a destination that fails later is
+    /// dropped as long as at least one survives.
+    pub async fn create(
+        paths: &[PathBuf],
+        tolerate_errors: bool,
+        compression: Option<Compression>,
+    ) -> Result<Self> {
+        let mut destinations = Vec::with_capacity(paths.len());
+        for path in paths {
+            if is_stdout(path) {
+                let stdout = tokio::io::stdout();
+                let sink = match compression {
+                    Some(compression) => compression.encoder(stdout),
+                    None => Box::new(stdout) as Sink,
+                };
+                destinations.push((path.clone(), sink));
+                continue;
+            }
+            let part_path = segmented::part_path(path);
+            let file = File::create(&part_path).await.with_context(|| {
+                format!("Failed to create output file: {}", part_path.display())
+            })?;
+            let sink = match compression {
+                Some(compression) => compression.encoder(file),
+                None => Box::new(file) as Sink,
+            };
+            destinations.push((path.clone(), sink));
+        }
+
+        Ok(Self {
+            destinations,
+            tolerate_errors,
+        })
+    }
+
+    /// Continue writing the partial file of a single uncompressed destination, after the
+    /// data it already holds
+    pub async fn append(path: &Path) -> Result<Self> {
+        let part_path = segmented::part_path(path);
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&part_path)
+            .await
+            .with_context(|| format!("Failed to open output file: {}", part_path.display()))?;
+        Ok(Self {
+            destinations: vec![(path.to_path_buf(), Box::new(file) as Sink)],
+            tolerate_errors: false,
+        })
+    }
+
+    /// Paths of the destinations that are still being written
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.destinations
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    pub async fn write_all(&mut self, chunk: &[u8]) -> Result<()> {
+        let mut failed = Vec::new();
+        for (index, (path, sink)) in self.destinations.iter_mut().enumerate() {
+            if let Err(e) = sink.write_all(chunk).await {
+                failed.push((index, path.clone(), e));
+            }
+        }
+        self.drop_failed(failed, "write to")
+    }
+
+    /// Push buffered data out to every destination, leaving them open for more writes
+    pub async fn flush(&mut self) -> Result<()> {
+        let mut failed = Vec::new();
+        for (index, (path, sink)) in self.destinations.iter_mut().enumerate() {
+            if let Err(e) = sink.flush().await {
+                failed.push((index, path.clone(), e));
+            }
+        }
+        self.drop_failed(failed, "flush")
+    }
+
+    /// Flush and close every destination, finalizing sinks that wrap an encoder.
+    /// Nothing can be written afterwards.
+    pub async fn close(&mut self) -> Result<()> {
+        let mut failed = Vec::new();
+        for (index, (path, sink)) in self.destinations.iter_mut().enumerate() {
+            if let Err(e) = sink.shutdown().await {
+                failed.push((index, path.clone(), e));
+            }
+        }
+        self.drop_failed(failed, "close")
+    }
+
+    /// Close every destination and move its partial file into place
+    pub async fn finish(&mut self) -> Result<()> {
+        self.close().await?;
+
+        let mut failed = Vec::new();
+        for (index, (path, _)) in self.destinations.iter().enumerate() {
+            if is_stdout(path) {
+                continue;
+            }
+            if let Err(e) = tokio::fs::rename(segmented::part_path(path), path).await {
+                failed.push((index, path.clone(), e));
+            }
+        }
+        self.drop_failed(failed, "move into place")
+    }
+
+    fn drop_failed(
+        &mut self,
+        failed: Vec<(usize, PathBuf, std::io::Error)>,
+        action: &str,
+    ) -> Result<()> {
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        if !self.tolerate_errors || failed.len() == self.destinations.len() {
+            let (_, path, e) = failed.into_iter().next().unwrap();
+            return Err(anyhow!("Failed to {} {}: {}", action, path.display(), e));
+        }
+
+        for (index, path, e) in failed.into_iter().rev() {
+            warn!(
+                "Dropping output {} after failing to {} it: {}",
+                path.display(),
+                action,
+                e
+            );
+            self.destinations.remove(index);
+        }
+        Ok(())
+    }
+}
+
+/// Directory a path will be written into, "." for bare filenames
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Refuse outputs that point at the same file, or at the file being downloaded from,
+/// before anything is created or truncated
+pub fn check_collisions(paths: &[PathBuf], source: Option<&Path>) -> Result<()> {
+    let source = source.map(normalize);
+    let mut seen: Vec<(PathBuf, &PathBuf)> = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let normalized = normalize(path);
+        if source.as_ref() == Some(&normalized) {
+            return Err(anyhow!(
+                "Colliding output path: {} is the file being downloaded",
+                path.display()
+            ));
+        }
+        if let Some((_, first)) = seen.iter().find(|(existing, _)| *existing == normalized) {
+            return Err(anyhow!(
+                "Duplicate output path: {} and {} refer to the same file",
+                first.display(),
+                path.display()
+            ));
+        }
+        seen.push((normalized, path));
+    }
+
+    Ok(())
+}
+
+/// Absolute form of a path with symlinks resolved as far as the file system allows,
+/// so different spellings of the same file compare equal
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    let file_name = path.file_name().unwrap_or_default();
+    match parent_dir(path).canonicalize() {
+        Ok(parent) => parent.join(file_name),
+        Err(_) => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_collisions() {
+        let dir = std::env::temp_dir();
+        let a = dir.join("ferro-collision-test.iso");
+        let same_as_a = dir.join(".").join("ferro-collision-test.iso");
+        let b = dir.join("ferro-collision-test-2.iso");
+
+        assert!(check_collisions(&[a.clone(), b.clone()], None).is_ok());
+        assert!(check_collisions(&[a.clone(), same_as_a.clone()], None).is_err());
+        assert!(check_collisions(&[b], Some(&a)).is_ok());
+        assert!(check_collisions(&[same_as_a], Some(&a)).is_err());
+    }
+
+    #[test]
+    fn test_stdout_path() {
+        assert!(is_stdout(Path::new("-")));
+        assert!(!is_stdout(Path::new("./-")));
+        assert!(!is_stdout(Path::new("win11.iso")));
+        assert_eq!(
+            Compression::Zstd.compressed_path(Path::new("-")),
+            Path::new("-")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compressed_output_round_trip() {
+        use async_compression::tokio::bufread::ZstdDecoder;
+        use tokio::io::AsyncReadExt;
+
+        let path = Compression::Zstd.compressed_path(
+            &std::env::temp_dir().join(format!("ferro-compress-{}.iso", std::process::id())),
+        );
+        assert_eq!(path.extension().unwrap(), "zst");
+        assert_eq!(
+            Compression::Zstd
+                .decompressed_path(&path)
+                .extension()
+                .unwrap(),
+            "iso"
+        );
+
+        let data = b"ISO bytes ".repeat(10_000);
+        let mut writer =
+            TeeWriter::create(std::slice::from_ref(&path), false, Some(Compression::Zstd))
+                .await
+                .unwrap();
+        let (first, rest) = data.split_at(data.len() / 2);
+        writer.write_all(first).await.unwrap();
+        // A flush mid-stream, as before resuming a transfer, must leave the encoder open
+        writer.flush().await.unwrap();
+        writer.write_all(rest).await.unwrap();
+        writer.finish().await.unwrap();
+
+        let compressed = tokio::fs::read(&path).await.unwrap();
+        assert!(compressed.len() < data.len());
+        let mut decompressed = Vec::new();
+        ZstdDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .await
+            .unwrap();
+        assert_eq!(decompressed, data);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Accumulates the wall-clock time spent in each stage of a run.
+/// Clones share the same underlying record, so one instance can be handed to
+/// both the API client and the downloader.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    stages: Arc<Mutex<Vec<(&'static str, Duration)>>>,
+}
+
+impl Timings {
+    /// Start timing a stage; the elapsed time is recorded when the returned guard is dropped
+    pub fn start(&self, stage: &'static str) -> StageTimer {
+        StageTimer {
+            timings: self.clone(),
+            stage,
+            started: Instant::now(),
+        }
+    }
+
+    /// Add `elapsed` to a stage, keeping stages in the order they were first seen
+    pub fn record(&self, stage: &'static str, elapsed: Duration) {
+        let mut stages = self.stages.lock().unwrap();
+        match stages.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, total)) => *total += elapsed,
+            None => stages.push((stage, elapsed)),
+        }
+    }
+
+    /// One-line report such as "locale 0.3s, whitelist 0.2s, download 4m10s"
+    pub fn summary(&self) -> String {
+        let stages = self.stages.lock().unwrap();
+        if stages.is_empty() {
+            return "no stages recorded".to_string();
+        }
+        stages
+            .iter()
+            .map(|(stage, elapsed)| format!("{} {}", stage, format_duration(*elapsed)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Records the time since it was created into its stage when dropped, so
+/// stages that exit early with an error are still accounted for
+pub struct StageTimer {
+    timings: Timings,
+    stage: &'static str,
+    started: Instant,
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        self.timings.record(self.stage, self.started.elapsed());
+    }
+}
+
+/// Format a duration compactly: "0.3s", "4m10s", "1h02m05s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!(
+            "{}h{:02}m{:02}s",
+            secs / 3600,
+            (secs % 3600) / 60,
+            secs % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(300)), "0.3s");
+        assert_eq!(format_duration(Duration::from_secs(250)), "4m10s");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h02m05s");
+    }
+
+    #[test]
+    fn test_record_accumulates_in_order() {
+        let timings = Timings::default();
+        timings.record("sku", Duration::from_millis(1000));
+        timings.record("links", Duration::from_millis(1100));
+        timings.record("sku", Duration::from_millis(800));
+        assert_eq!(timings.summary(), "sku 1.8s, links 1.1s");
+    }
+}
@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowsVersion {
@@ -13,6 +15,13 @@ pub struct WindowsRelease {
     pub index: usize,
 }
 
+/// The releases of one version, as listed by `list releases --all-versions`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionReleases {
+    pub version: String,
+    pub releases: Vec<WindowsRelease>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowsEdition {
     pub name: String,
@@ -38,6 +47,158 @@ pub struct WindowsArchitecture {
     pub url: String,
 }
 
+/// One language × architecture download of an edition, as listed by
+/// `list architectures --all-languages`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixEntry {
+    pub language: String,
+    pub display_name: String,
+    pub architecture: String,
+    pub url: String,
+}
+
+/// Whether a language's download links could be fetched, as reported by `probe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    /// Download links were returned
+    Reachable,
+    /// Microsoft refused the request, usually an IP ban
+    Banned,
+    /// The request succeeded but offered no downloads
+    Empty,
+    /// The request failed for another reason
+    Error,
+}
+
+impl std::fmt::Display for ProbeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeStatus::Reachable => write!(f, "reachable"),
+            ProbeStatus::Banned => write!(f, "banned"),
+            ProbeStatus::Empty => write!(f, "empty"),
+            ProbeStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The probe result of one language of an edition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeEntry {
+    pub language: String,
+    pub display_name: String,
+    pub status: ProbeStatus,
+    /// Architectures with a download link, when reachable
+    pub architectures: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A fully resolved version/release/edition/language/architecture choice
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub version: String,
+    pub release: String,
+    pub edition: String,
+    pub language: String,
+    pub architecture: String,
+}
+
+/// Result of a download, printed as a single line by `--output-on-stdout-json`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum DownloadReport {
+    Ok {
+        path: PathBuf,
+        size: u64,
+        sha256: Option<String>,
+        duration_ms: u128,
+        url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selection: Option<Selection>,
+    },
+    /// Nothing was downloaded because `path` is already up to date, as with `--if-newer`
+    Skipped {
+        path: PathBuf,
+        reason: String,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+/// Download link printed by `--get-url --format json`
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedUrl {
+    pub url: String,
+    /// SHA-256 Microsoft (or GitHub) published for the file, when known
+    pub sha256: Option<String>,
+    pub selection: Selection,
+}
+
+/// What a download would fetch and write, printed by `--dry-run` instead of downloading
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRun {
+    pub url: String,
+    /// Size the server reports for the file, when it does
+    pub size: Option<u64>,
+    pub outputs: Vec<PathBuf>,
+    /// `None` for a `--url` download
+    pub selection: Option<Selection>,
+}
+
+/// A selection where any part may be left out for Ferro to pick a default.
+///
+/// Each part can also be given as a 0-based position in the corresponding `list`
+/// output; a name takes precedence over an index.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectionQuery {
+    pub version: Option<String>,
+    pub release: Option<String>,
+    pub edition: Option<String>,
+    pub language: Option<String>,
+    pub architecture: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edition_index: Option<usize>,
+    /// Product edition ID to request SKUs for, whether or not the catalog lists it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edition_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture_index: Option<usize>,
+}
+
+impl Selection {
+    /// Filename used when no output path is given: the server's filename, or one
+    /// synthesized from the selection when the URL doesn't contain one
+    pub fn default_filename(&self, download_url: &str) -> String {
+        crate::utils::extract_filename_from_url(download_url).unwrap_or_else(|| {
+            format!(
+                "{}_{}_{}_{}.iso",
+                self.version.replace(" ", ""),
+                self.release,
+                self.language,
+                self.architecture
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.version, self.release, self.edition, self.language, self.architecture
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MicrosoftApiResponse {
     #[serde(rename = "Skus")]
@@ -51,6 +212,9 @@ pub struct MicrosoftApiResponse {
     pub validation_container: Option<ValidationContainer>,
     #[serde(rename = "Tickets")]
     pub tickets: Option<serde_json::Value>,
+    /// Fields Microsoft returned that aren't mapped above, kept for debugging API changes
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +235,56 @@ pub struct Sku {
     pub product_edition_name: Option<String>,
     #[serde(rename = "FriendlyFileNames")]
     pub friendly_file_names: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Sku {
+    /// SHA-256 hashes the SKU information carries for this SKU's files, as (friendly file
+    /// name, hash). Usually none.
+    ///
+    /// The API has no documented field for these, so any unmapped field that maps one of
+    /// `FriendlyFileNames` to a SHA-256 is used, either as an object keyed by file name or as
+    /// a list of objects holding a file name and a hash.
+    pub fn published_hashes(&self) -> Vec<(String, String)> {
+        let file_names = self.friendly_file_names.as_deref().unwrap_or_default();
+        let mut hashes = Vec::new();
+
+        for value in self.extra.values() {
+            match value {
+                serde_json::Value::Object(map) => {
+                    for file_name in file_names {
+                        if let Some(hash) = map.get(file_name).and_then(as_sha256) {
+                            hashes.push((file_name.clone(), hash));
+                        }
+                    }
+                }
+                serde_json::Value::Array(entries) => {
+                    for entry in entries.iter().filter_map(|entry| entry.as_object()) {
+                        let file_name = entry.values().find_map(|field| {
+                            field
+                                .as_str()
+                                .filter(|s| file_names.iter().any(|name| name == s))
+                        });
+                        let hash = entry.values().find_map(as_sha256);
+                        if let (Some(file_name), Some(hash)) = (file_name, hash) {
+                            hashes.push((file_name.to_string(), hash));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        hashes
+    }
+}
+
+fn as_sha256(value: &serde_json::Value) -> Option<String> {
+    value
+        .as_str()
+        .filter(|s| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -89,6 +303,59 @@ pub struct ApiError {
     pub value: String,
 }
 
+impl ApiError {
+    pub fn kind(&self) -> ApiErrorKind {
+        ApiErrorKind::from_type(self.error_type)
+    }
+}
+
+/// The numeric error types Microsoft's download API is known to return in `Errors`.
+///
+/// This is the table of types seen so far; when users report a new one, add a variant
+/// here so it gets a name in logs and error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    /// Type 9: Microsoft's Sentinel system blocked the request, usually an IP ban
+    /// (the "715-123130" message Fido reports)
+    Banned,
+    /// A type not seen before
+    Unknown(u32),
+}
+
+impl ApiErrorKind {
+    pub fn from_type(error_type: u32) -> Self {
+        match error_type {
+            9 => ApiErrorKind::Banned,
+            other => ApiErrorKind::Unknown(other),
+        }
+    }
+
+    pub fn error_type(self) -> u32 {
+        match self {
+            ApiErrorKind::Banned => 9,
+            ApiErrorKind::Unknown(error_type) => error_type,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ApiErrorKind::Banned => "IP banned",
+            ApiErrorKind::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for ApiErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "API error type {} ({})",
+            self.error_type(),
+            self.description()
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidationContainer {
     #[serde(rename = "ErrorList")]
@@ -97,9 +364,38 @@ pub struct ValidationContainer {
     pub errors: Vec<serde_json::Value>,
 }
 
+/// A GitHub release, as returned by `GET /repos/{owner}/{repo}/releases/tags/{tag}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitHubRelease {
+    pub tag_name: String,
+    pub assets: Vec<GitHubAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitHubAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl GitHubRelease {
+    /// The asset with the SHA-256 of `file_name`: its own `<file_name>.sha256` if the release
+    /// has one, otherwise a combined list such as `SHA256SUMS` or `sha256sums.txt`
+    pub fn checksum_asset(&self, file_name: &str) -> Option<&GitHubAsset> {
+        let own = format!("{}.sha256", file_name.to_lowercase());
+        self.assets
+            .iter()
+            .find(|asset| asset.name.to_lowercase() == own)
+            .or_else(|| {
+                self.assets.iter().find(|asset| {
+                    let name = asset.name.to_lowercase();
+                    name.starts_with("sha256sum") || name.ends_with(".sha256sum")
+                })
+            })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionData {
-    #[allow(dead_code)]
     pub session_id: String,
     pub org_id: String,
     pub profile_id: String,
@@ -123,17 +419,18 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
             page_type: "windows11".to_string(),
             releases: vec![WindowsReleaseData {
                 name: "24H2 (Build 26100.1742 - 2024.10)".to_string(),
+                default_edition: Some("Windows 11 Home/Pro/Edu".to_string()),
                 editions: vec![
                     WindowsEditionData {
                         name: "Windows 11 Home/Pro/Edu".to_string(),
                         ids: vec![3113, 3131],
                     },
                     WindowsEditionData {
-                        name: "Windows 11 Home China ".to_string(),
+                        name: "Windows 11 Home China".to_string(),
                         ids: vec![3115, 3132],
                     },
                     WindowsEditionData {
-                        name: "Windows 11 Pro China ".to_string(),
+                        name: "Windows 11 Pro China".to_string(),
                         ids: vec![3114, 3133],
                     },
                 ],
@@ -144,24 +441,51 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
             page_type: "Windows10ISO".to_string(),
             releases: vec![WindowsReleaseData {
                 name: "22H2 v1 (Build 19045.2965 - 2023.05)".to_string(),
+                default_edition: Some("Windows 10 Home/Pro/Edu".to_string()),
                 editions: vec![
                     WindowsEditionData {
                         name: "Windows 10 Home/Pro/Edu".to_string(),
                         ids: vec![2618],
                     },
                     WindowsEditionData {
-                        name: "Windows 10 Home China ".to_string(),
+                        name: "Windows 10 Home China".to_string(),
                         ids: vec![2378],
                     },
                 ],
             }],
         },
+        // Evaluation Center ISOs; the edition is a label, the links are in `iso_api`
+        WindowsVersionData {
+            name: "Windows Server 2025".to_string(),
+            page_type: "EVAL_SERVER 2025".to_string(),
+            releases: vec![WindowsReleaseData {
+                name: "24H2 (Build 26100.1742)".to_string(),
+                default_edition: None,
+                editions: vec![WindowsEditionData {
+                    name: "Standard/Datacenter Evaluation".to_string(),
+                    ids: vec![0],
+                }],
+            }],
+        },
+        WindowsVersionData {
+            name: "Windows Server 2022".to_string(),
+            page_type: "EVAL_SERVER 2022".to_string(),
+            releases: vec![WindowsReleaseData {
+                name: "21H2 (Build 20348.169)".to_string(),
+                default_edition: None,
+                editions: vec![WindowsEditionData {
+                    name: "Standard/Datacenter Evaluation".to_string(),
+                    ids: vec![0],
+                }],
+            }],
+        },
         WindowsVersionData {
             name: "UEFI Shell 2.2".to_string(),
             page_type: "UEFI_SHELL 2.2".to_string(),
             releases: vec![
                 WindowsReleaseData {
                     name: "25H1 (edk2-stable202505)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -175,6 +499,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "24H2 (edk2-stable202411)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -188,6 +513,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "24H1 (edk2-stable202405)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -201,6 +527,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "23H2 (edk2-stable202311)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -214,6 +541,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "23H1 (edk2-stable202305)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -227,6 +555,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "22H2 (edk2-stable202211)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -240,6 +569,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "22H1 (edk2-stable202205)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -253,6 +583,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "21H2 (edk2-stable202108)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -266,6 +597,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "21H1 (edk2-stable202105)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -279,6 +611,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
                 },
                 WindowsReleaseData {
                     name: "20H2 (edk2-stable202011)".to_string(),
+                    default_edition: None,
                     editions: vec![
                         WindowsEditionData {
                             name: "Release".to_string(),
@@ -297,6 +630,7 @@ pub fn get_windows_versions() -> Vec<WindowsVersionData> {
             page_type: "UEFI_SHELL 2.0".to_string(),
             releases: vec![WindowsReleaseData {
                 name: "4.632 [20100426]".to_string(),
+                default_edition: None,
                 editions: vec![WindowsEditionData {
                     name: "Release".to_string(),
                     ids: vec![0],
@@ -316,6 +650,8 @@ pub struct WindowsVersionData {
 #[derive(Debug, Clone)]
 pub struct WindowsReleaseData {
     pub name: String,
+    /// Edition picked when none is given; the first edition when unset
+    pub default_edition: Option<String>,
     pub editions: Vec<WindowsEditionData>,
 }
 
@@ -324,3 +660,239 @@ pub struct WindowsEditionData {
     pub name: String,
     pub ids: Vec<u32>,
 }
+
+/// Check the hand-maintained catalog for data-entry mistakes, returning one
+/// message per problem found (empty when the catalog is consistent)
+pub fn validate_catalog(versions: &[WindowsVersionData]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut version_names = HashSet::new();
+
+    for version in versions {
+        check_name(&mut problems, "Version", &version.name, "catalog");
+        if !version_names.insert(version.name.as_str()) {
+            problems.push(format!("Duplicate version name '{}'", version.name));
+        }
+        if version.page_type.trim().is_empty() {
+            problems.push(format!("Version '{}' has an empty page type", version.name));
+        }
+        if version.releases.is_empty() {
+            problems.push(format!("Version '{}' has no releases", version.name));
+        }
+
+        // UEFI Shell and Evaluation Center entries use 0 as a sentinel edition ID rather
+        // than a Microsoft product ID
+        let uses_sentinel =
+            version.page_type.starts_with("UEFI") || version.page_type.starts_with("EVAL");
+        let mut release_names = HashSet::new();
+
+        for release in &version.releases {
+            check_name(&mut problems, "Release", &release.name, &version.name);
+            if !release_names.insert(release.name.as_str()) {
+                problems.push(format!(
+                    "Duplicate release name '{}' in '{}'",
+                    release.name, version.name
+                ));
+            }
+            if release.editions.is_empty() {
+                problems.push(format!(
+                    "Release '{}' of '{}' has no editions",
+                    release.name, version.name
+                ));
+            }
+
+            let mut edition_names = HashSet::new();
+            for edition in &release.editions {
+                let location = format!("{} {}", version.name, release.name);
+                check_name(&mut problems, "Edition", &edition.name, &location);
+                if !edition_names.insert(edition.name.as_str()) {
+                    problems.push(format!(
+                        "Duplicate edition name '{}' in '{}'",
+                        edition.name, location
+                    ));
+                }
+                if edition.ids.is_empty() {
+                    problems.push(format!(
+                        "Edition '{}' in '{}' has no IDs",
+                        edition.name, location
+                    ));
+                }
+                if !uses_sentinel && edition.ids.contains(&0) {
+                    problems.push(format!(
+                        "Edition '{}' in '{}' uses the sentinel ID 0",
+                        edition.name, location
+                    ));
+                }
+            }
+
+            if let Some(default_edition) = &release.default_edition {
+                if !edition_names.contains(default_edition.as_str()) {
+                    problems.push(format!(
+                        "Default edition '{}' of '{} {}' is not one of its editions",
+                        default_edition, version.name, release.name
+                    ));
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_name(problems: &mut Vec<String>, kind: &str, name: &str, location: &str) {
+    if name.trim().is_empty() {
+        problems.push(format!("{} with an empty name in '{}'", kind, location));
+    } else if name.trim() != name {
+        problems.push(format!(
+            "{} '{}' in '{}' has leading or trailing whitespace",
+            kind, name, location
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_error_kind() {
+        assert_eq!(ApiErrorKind::from_type(9), ApiErrorKind::Banned);
+        assert_eq!(
+            ApiErrorKind::from_type(9).to_string(),
+            "API error type 9 (IP banned)"
+        );
+        assert_eq!(
+            ApiErrorKind::from_type(12).to_string(),
+            "API error type 12 (unknown)"
+        );
+    }
+
+    #[test]
+    fn test_sku_published_hashes() {
+        let sku: Sku = serde_json::from_value(serde_json::json!({
+            "Id": "1",
+            "Language": "English",
+            "LocalizedLanguage": "English",
+            "LocalizedProductDisplayName": "Windows 11",
+            "FriendlyFileNames": ["Win11_24H2_English_x64.iso", "Win11_24H2_English_Arm64.iso"],
+            "FileHashes": {"Win11_24H2_English_x64.iso": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"},
+            "Files": [{"Name": "Win11_24H2_English_Arm64.iso", "Sha256": "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"}],
+            "Other": {"Win11_24H2_English_x64.iso": "not a hash"}
+        }))
+        .unwrap();
+        let mut hashes = sku.published_hashes();
+        hashes.sort();
+        assert_eq!(
+            hashes,
+            vec![
+                (
+                    "Win11_24H2_English_Arm64.iso".to_string(),
+                    "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string()
+                ),
+                (
+                    "Win11_24H2_English_x64.iso".to_string(),
+                    "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_string()
+                ),
+            ]
+        );
+
+        // An entry as Microsoft usually returns it, with every field mapped
+        let sku: Sku = serde_json::from_str(
+            r#"{"Id":"18454","Language":"English","LocalizedLanguage":"English (United States)","LocalizedProductDisplayName":"Windows 11","ProductDisplayName":"Windows 11","Description":"Windows 11 24H2 English","ProductEditionName":"Windows 11 Home/Pro/Edu","FriendlyFileNames":["Win11_24H2_English_x64.iso"]}"#,
+        )
+        .unwrap();
+        assert!(sku.published_hashes().is_empty());
+    }
+
+    #[test]
+    fn test_github_checksum_asset() {
+        let release: GitHubRelease = serde_json::from_str(
+            r#"{"tag_name": "25H1", "draft": false, "assets": [
+                {"name": "UEFI-Shell-2.2-25H1-RELEASE.iso", "size": 1, "browser_download_url": "https://github.com/pbatard/UEFI-Shell/releases/download/25H1/UEFI-Shell-2.2-25H1-RELEASE.iso"},
+                {"name": "SHA256SUMS", "size": 1, "browser_download_url": "https://github.com/pbatard/UEFI-Shell/releases/download/25H1/SHA256SUMS"},
+                {"name": "UEFI-Shell-2.2-25H1-DEBUG.iso.sha256", "size": 1, "browser_download_url": "https://github.com/pbatard/UEFI-Shell/releases/download/25H1/UEFI-Shell-2.2-25H1-DEBUG.iso.sha256"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let asset = |file_name| release.checksum_asset(file_name).map(|a| a.name.as_str());
+        assert_eq!(
+            asset("UEFI-Shell-2.2-25H1-DEBUG.iso"),
+            Some("UEFI-Shell-2.2-25H1-DEBUG.iso.sha256")
+        );
+        assert_eq!(asset("UEFI-Shell-2.2-25H1-RELEASE.iso"), Some("SHA256SUMS"));
+    }
+
+    #[test]
+    fn test_builtin_catalog_is_valid() {
+        let problems = validate_catalog(&get_windows_versions());
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn test_validate_catalog_reports_problems() {
+        let edition = |name: &str, ids: Vec<u32>| WindowsEditionData {
+            name: name.to_string(),
+            ids,
+        };
+        let versions = vec![WindowsVersionData {
+            name: "Windows 11".to_string(),
+            page_type: "windows11".to_string(),
+            releases: vec![WindowsReleaseData {
+                name: "24H2".to_string(),
+                default_edition: None,
+                editions: vec![
+                    edition("Pro ", vec![3113]),
+                    edition("Home", vec![]),
+                    edition("Home", vec![0]),
+                ],
+            }],
+        }];
+
+        let problems = validate_catalog(&versions);
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[test]
+    fn test_download_report_format() {
+        let report = DownloadReport::Ok {
+            path: PathBuf::from("a.iso"),
+            size: 42,
+            sha256: Some("abc".to_string()),
+            duration_ms: 1500,
+            url: "https://example.com/a.iso".to_string(),
+            selection: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"status":"ok","path":"a.iso","size":42,"sha256":"abc","duration_ms":1500,"url":"https://example.com/a.iso"}"#
+        );
+
+        let report = DownloadReport::Skipped {
+            path: PathBuf::from("a.iso"),
+            reason: "not modified since the last download".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"status":"skipped","path":"a.iso","reason":"not modified since the last download"}"#
+        );
+    }
+
+    #[test]
+    fn test_resolved_url_format() {
+        let resolved = ResolvedUrl {
+            url: "https://example.com/a.iso".to_string(),
+            sha256: None,
+            selection: Selection {
+                version: "Windows 11".to_string(),
+                release: "24H2".to_string(),
+                edition: "Windows 11 Home/Pro/Edu".to_string(),
+                language: "English".to_string(),
+                architecture: "x64".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&resolved).unwrap(),
+            r#"{"url":"https://example.com/a.iso","sha256":null,"selection":{"version":"Windows 11","release":"24H2","edition":"Windows 11 Home/Pro/Edu","language":"English","architecture":"x64"}}"#
+        );
+    }
+}
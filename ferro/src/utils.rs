@@ -17,12 +17,76 @@ pub fn get_system_architecture() -> String {
     }
 }
 
+/// `--architecture` value that asks for the host's architecture, as when none is given
+pub const AUTO_ARCHITECTURE: &str = "auto";
+
+/// Architecture to download for a host of `system_arch` from those `offered`: the host's own,
+/// else x64, which Windows on ARM64 runs under emulation, else the first one offered
+pub fn pick_host_architecture<'a>(offered: &[&'a str], system_arch: &str) -> Option<&'a str> {
+    let find = |name: &str| offered.iter().find(|arch| arch.eq_ignore_ascii_case(name));
+    find(system_arch)
+        .or_else(|| find("x64"))
+        .or_else(|| offered.first())
+        .copied()
+}
+
+/// Whether a version name refers to a UEFI Shell release rather than Windows
+pub fn is_uefi_shell(version_name: &str) -> bool {
+    version_name.to_lowercase().contains("uefi")
+}
+
+/// Whether a version is a Windows Server evaluation, downloaded from the Evaluation Center
+/// rather than through the software-download API
+pub fn is_evaluation_center(version_name: &str) -> bool {
+    version_name.to_lowercase().contains("server")
+}
+
+/// Whether `url` is served from a Microsoft domain, as the download links the API hands out are
+pub fn is_microsoft_url(url: &str) -> bool {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_lowercase))
+        .is_some_and(|host| host == "microsoft.com" || host.ends_with(".microsoft.com"))
+}
+
+/// How long a signed download link has to stay valid for a download to start with it; a link
+/// closer to its expiry than this is resolved again, so segments or retries don't hit a 403
+pub const LINK_EXPIRY_MARGIN: chrono::Duration = chrono::Duration::minutes(10);
+
+/// When a signed Microsoft download link stops working, from the Unix time in its `P1`
+/// parameter; `None` for links that don't carry one
+pub fn link_expiry(url: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    let parsed = url::Url::parse(url).ok()?;
+    let (_, expiry) = parsed.query_pairs().find(|(name, _)| name == "P1")?;
+    chrono::DateTime::from_timestamp(expiry.parse().ok()?, 0)
+}
+
+/// Whether `url` has expired, or will within [`LINK_EXPIRY_MARGIN`] of `now`
+pub fn link_expires_soon(url: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+    link_expiry(url).is_some_and(|expiry| expiry - LINK_EXPIRY_MARGIN <= now)
+}
+
 /// Extract filename from URL
 pub fn extract_filename_from_url(url: &str) -> Option<String> {
     let re = Regex::new(r".*\/(.+\.iso).*").ok()?;
     re.captures(url)?.get(1)?.as_str().to_string().into()
 }
 
+/// Extract the filename from a Content-Disposition header value,
+/// e.g. `attachment; filename="Win11_24H2_English_x64.iso"`
+pub fn filename_from_content_disposition(header: &str) -> Option<String> {
+    let value = header.split(';').map(str::trim).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("filename")
+            .then(|| value.trim().trim_matches('"'))
+    })?;
+
+    // Never let the server pick a directory, only a file name
+    let name = value.rsplit(['/', '\\']).next()?;
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+}
+
 /// Convert bytes to human readable format
 pub fn bytes_to_human_readable(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB"];
@@ -41,6 +105,114 @@ pub fn bytes_to_human_readable(bytes: u64) -> String {
     }
 }
 
+/// Parse a byte count with an optional binary suffix, e.g. "512M", "2G", "500k" or "1048576"
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+
+    let multiplier: u64 = match suffix.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        "t" | "tb" | "tib" => 1024 * 1024 * 1024 * 1024,
+        other => return Err(format!("unknown size suffix '{}'", other)),
+    };
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    if number < 0.0 {
+        return Err(format!("invalid size '{}'", value));
+    }
+
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Parse a SHA-256 digest given as 64 hex digits, normalized to lowercase
+pub fn parse_sha256(value: &str) -> Result<String, String> {
+    parse_hex_digest(value, 64)
+}
+
+/// Parse a `--query-locale` value. Only the shape is checked, loosely: a 2-3 letter language,
+/// a dash and a 2-4 character region or script, e.g. `de-DE` or `zh-Hans`; whether Microsoft
+/// serves it is only known once it's used.
+pub fn parse_locale(value: &str) -> Result<String, String> {
+    let value = value.trim().replace('_', "-");
+    let valid = match value.split_once('-') {
+        Some((language, region)) => {
+            (2..=3).contains(&language.len())
+                && language.chars().all(|c| c.is_ascii_alphabetic())
+                && (2..=4).contains(&region.len())
+                && region.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(format!("expected a locale like en-US, got '{}'", value));
+    }
+    Ok(value)
+}
+
+/// Parse a SHA-1 digest given as 40 hex digits, normalized to lowercase
+pub fn parse_sha1(value: &str) -> Result<String, String> {
+    parse_hex_digest(value, 40)
+}
+
+fn parse_hex_digest(value: &str, digits: usize) -> Result<String, String> {
+    let value = value.trim();
+    if value.len() != digits || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected {} hexadecimal digits", digits));
+    }
+    Ok(value.to_lowercase())
+}
+
+/// Make a derived filename safe on common filesystems (FAT32, NTFS, SMB shares): characters
+/// Windows rejects are replaced with `_`, trailing dots and spaces are dropped, and the name
+/// is cut to `max_length` bytes while keeping its extension.
+pub fn sanitize_filename(name: &str, max_length: usize) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim_end_matches(['.', ' ']);
+    if cleaned.len() <= max_length {
+        return cleaned.to_string();
+    }
+
+    let (stem, extension) = match cleaned.rfind('.') {
+        Some(dot) if cleaned.len() - dot <= 8 => cleaned.split_at(dot),
+        _ => (cleaned, ""),
+    };
+    let mut stem_length = max_length.saturating_sub(extension.len());
+    while !stem.is_char_boundary(stem_length) {
+        stem_length -= 1;
+    }
+    format!("{}{}", &stem[..stem_length], extension)
+}
+
+/// Item at a 0-based `--<what>-index` position, with an error naming the valid range
+pub fn pick_index<'a, T>(items: &'a [T], index: usize, what: &str) -> anyhow::Result<&'a T> {
+    match items.len() {
+        0 => Err(anyhow::anyhow!("No {}s found", what)),
+        len => items.get(index).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} index {} is out of range, expected 0-{}",
+                what,
+                index,
+                len - 1
+            )
+        }),
+    }
+}
+
 /// Convert Microsoft architecture type code to formal architecture name
 pub fn get_arch_from_type(arch_type: u32) -> String {
     match arch_type {
@@ -51,8 +223,50 @@ pub fn get_arch_from_type(arch_type: u32) -> String {
     }
 }
 
+/// The items whose name matches `query` most closely, ignoring case: the exact name, else every
+/// name containing `query`, else every name with a word starting with each word of `query`, so
+/// "11", "24h2" and "win 11 pro" all work. More than one item means `query` is ambiguous.
+pub fn match_names<'a, T>(items: &'a [T], query: &str, name: impl Fn(&T) -> &str) -> Vec<&'a T> {
+    let query = query.trim().to_lowercase();
+    let exact: Vec<&T> = items
+        .iter()
+        .filter(|item| name(item).to_lowercase() == query)
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+
+    let containing: Vec<&T> = items
+        .iter()
+        .filter(|item| name(item).to_lowercase().contains(&query))
+        .collect();
+    if !containing.is_empty() {
+        return containing;
+    }
+
+    let query_words = words(&query);
+    if query_words.is_empty() {
+        return Vec::new();
+    }
+    items
+        .iter()
+        .filter(|item| {
+            let name = name(item).to_lowercase();
+            let name_words = words(&name);
+            query_words
+                .iter()
+                .all(|query_word| name_words.iter().any(|word| word.starts_with(query_word)))
+        })
+        .collect()
+}
+
+fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
 /// Select language based on system locale
-#[allow(dead_code)]
 pub fn select_language_by_locale(language_name: &str, system_locale: &str) -> bool {
     let locale = system_locale.to_lowercase();
     let lang = language_name.to_lowercase();
@@ -108,6 +322,71 @@ pub fn select_language_by_locale(language_name: &str, system_locale: &str) -> bo
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_pick_host_architecture() {
+        let offered = ["x64", "ARM64"];
+        assert_eq!(pick_host_architecture(&offered, "ARM64"), Some("ARM64"));
+        assert_eq!(pick_host_architecture(&offered, "arm64"), Some("ARM64"));
+        assert_eq!(pick_host_architecture(&["x64"], "ARM64"), Some("x64"));
+        assert_eq!(
+            pick_host_architecture(&["x86", "x64"], "riscv64"),
+            Some("x64")
+        );
+        assert_eq!(pick_host_architecture(&["ARM64"], "x64"), Some("ARM64"));
+        assert_eq!(pick_host_architecture(&[], "x64"), None);
+    }
+
+    #[test]
+    fn test_is_microsoft_url() {
+        assert!(is_microsoft_url(
+            "https://software.download.prss.microsoft.com/dbazure/Win11_24H2_English_x64.iso?t=abc"
+        ));
+        assert!(is_microsoft_url(
+            "https://go.microsoft.com/fwlink/?linkid=2293312"
+        ));
+        assert!(!is_microsoft_url("https://microsoft.com.example.org/a.iso"));
+        assert!(!is_microsoft_url("https://notmicrosoft.com/a.iso"));
+        assert!(!is_microsoft_url("not a url"));
+    }
+
+    #[test]
+    fn test_match_names() {
+        let names = [
+            "Windows 11 Home/Pro/Edu",
+            "Windows 11 Home China",
+            "Windows 11 Pro China",
+        ];
+        let matches = |query| match_names(&names, query, |name| name);
+
+        assert_eq!(matches("windows 11 home china"), vec![&names[1]]);
+        assert_eq!(matches("edu"), vec![&names[0]]);
+        assert_eq!(matches("win 11 pro chi"), vec![&names[2]]);
+        // Every edition mentioning "pro" matches, so the caller has to ask which one
+        assert_eq!(matches("Pro"), vec![&names[0], &names[2]]);
+        assert!(matches("enterprise").is_empty());
+        assert!(matches(" - ").is_empty());
+
+        let releases = ["24H2 (Build 26100.1742 - 2024.10)"];
+        assert_eq!(match_names(&releases, "24h2", |name| name).len(), 1);
+    }
+
+    #[test]
+    fn test_link_expiry() {
+        let url = "https://software.download.prss.microsoft.com/dbazure/Win11_24H2_English_x64.iso?t=8a1f&P1=1729000000&P2=601&P3=2&P4=abc%3d%3d";
+        let expiry = chrono::DateTime::from_timestamp(1_729_000_000, 0).unwrap();
+        assert_eq!(link_expiry(url), Some(expiry));
+        assert!(!link_expires_soon(url, expiry - chrono::Duration::hours(1)));
+        assert!(link_expires_soon(
+            url,
+            expiry - chrono::Duration::minutes(5)
+        ));
+        assert!(link_expires_soon(url, expiry + chrono::Duration::hours(1)));
+
+        // Links without a signature never count as expired
+        assert_eq!(link_expiry("https://example.com/a.iso?P1=soon"), None);
+        assert!(!link_expires_soon("https://example.com/a.iso", expiry));
+    }
+
     #[test]
     fn test_bytes_to_human_readable() {
         assert_eq!(bytes_to_human_readable(1024), "1.0 KB");
@@ -116,12 +395,84 @@ mod tests {
         assert_eq!(bytes_to_human_readable(5 * 1024 * 1024 * 1024), "5.0 GB");
     }
 
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1048576"), Ok(1048576));
+        assert_eq!(parse_byte_size("500k"), Ok(500 * 1024));
+        assert_eq!(parse_byte_size("2M"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1.5GiB"), Ok(1536 * 1024 * 1024));
+        assert!(parse_byte_size("12X").is_err());
+        assert!(parse_byte_size("M").is_err());
+    }
+
+    #[test]
+    fn test_parse_sha256() {
+        let digest = "04309BF024EFFBAA8087D62A1FDCF95A96DBDB26FBB0F078176F670E630F7AE8";
+        assert_eq!(parse_sha256(digest), Ok(digest.to_lowercase()));
+        assert!(parse_sha256(&digest[1..]).is_err());
+        assert!(parse_sha256(&digest.replace('B', "G")).is_err());
+        assert!(parse_sha1(&digest[..40]).is_ok());
+        assert!(parse_sha1(digest).is_err());
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!(parse_locale("de-DE"), Ok("de-DE".to_string()));
+        assert_eq!(parse_locale("en_GB"), Ok("en-GB".to_string()));
+        assert!(parse_locale("zh-Hans").is_ok());
+        assert!(parse_locale("es-419").is_ok());
+        assert!(parse_locale("english").is_err());
+        assert!(parse_locale("en-").is_err());
+        assert!(parse_locale("en-US/../x").is_err());
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(
+            sanitize_filename("Win11_24H2: Pro?.iso", 255),
+            "Win11_24H2_ Pro_.iso"
+        );
+        assert_eq!(sanitize_filename("name. ", 255), "name");
+
+        let long = format!("{}.iso", "x".repeat(300));
+        let short = sanitize_filename(&long, 64);
+        assert_eq!(short.len(), 64);
+        assert!(short.ends_with("x.iso"));
+
+        // Never cut a multi-byte character in half
+        assert_eq!(sanitize_filename("ééé.iso", 9), "éé.iso");
+    }
+
+    #[test]
+    fn test_pick_index() {
+        let items = ["a", "b", "c"];
+        assert_eq!(pick_index(&items, 2, "edition").unwrap(), &"c");
+        assert_eq!(
+            pick_index(&items, 3, "edition").unwrap_err().to_string(),
+            "edition index 3 is out of range, expected 0-2"
+        );
+        assert!(pick_index::<&str>(&[], 0, "edition").is_err());
+    }
+
     #[test]
     fn test_extract_filename_from_url() {
         let url = "https://example.com/path/to/file.iso?param=value";
         assert_eq!(extract_filename_from_url(url), Some("file.iso".to_string()));
     }
 
+    #[test]
+    fn test_filename_from_content_disposition() {
+        assert_eq!(
+            filename_from_content_disposition("attachment; filename=\"Win11.iso\""),
+            Some("Win11.iso".to_string())
+        );
+        assert_eq!(
+            filename_from_content_disposition("attachment; FILENAME=../../etc/Win11.iso"),
+            Some("Win11.iso".to_string())
+        );
+        assert_eq!(filename_from_content_disposition("inline"), None);
+    }
+
     #[test]
     fn test_get_arch_from_type() {
         assert_eq!(get_arch_from_type(0), "x86");